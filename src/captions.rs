@@ -0,0 +1,335 @@
+/*
+ * This file is part of KOTI.
+ *
+ * KOTI is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * KOTI is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Afero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with KOTI.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Sentence-level captions for the narration, burned into the video or written out as SRT and
+//! WebVTT files.
+
+use crate::{
+    process::tts::{clean_text, normalize},
+    text2image,
+};
+use image::RgbaImage;
+use std::fmt::Write;
+
+/// Longest a single caption can be before it's split up, in characters.
+const MAX_CUE_CHARS: usize = 84;
+/// Longest a line in a caption file can be, in characters.
+const MAX_LINE_CHARS: usize = 42;
+
+/// Words that end in a period without ending the sentence.
+const ABBREVIATIONS: &[&str] = &[
+    "mr", "mrs", "ms", "dr", "st", "jr", "sr", "vs", "etc", "e.g", "i.e", "no", "approx",
+];
+
+/// Which captions to make.
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CaptionSettings {
+    /// Draw the captions over the video.
+    pub burn: bool,
+    /// Write the captions out as `.srt` and `.vtt` files next to the video, and upload them to
+    /// YouTube along with it.
+    pub files: bool,
+}
+
+impl Default for CaptionSettings {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            burn: false,
+            files: true,
+        }
+    }
+}
+
+/// A piece of text shown from `start` to `end`, in seconds.
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct Cue {
+    pub start: f32,
+    pub end: f32,
+    pub text: String,
+}
+
+impl Cue {
+    /// The same cue, moved later by `offset` seconds.
+    #[inline]
+    pub fn shifted(&self, offset: f32) -> Self {
+        Self {
+            start: self.start + offset,
+            end: self.end + offset,
+            text: self.text.clone(),
+        }
+    }
+}
+
+/// Split narration into sentences, breaking up any that are too long to show at once.
+#[inline]
+pub fn split_sentences(text: &str) -> Vec<String> {
+    let text = clean_text(text);
+    let chars: Vec<char> = text.chars().collect();
+    let mut sentences = vec![];
+    let mut start = 0;
+
+    for i in 0..chars.len() {
+        if !".!?".contains(chars[i]) {
+            continue;
+        }
+
+        // the sentence ends once any closing quotes are over, if there's a space after them
+        let mut end = i + 1;
+        while end < chars.len() && "\"')]".contains(chars[end]) {
+            end += 1;
+        }
+        if end < chars.len() && !chars[end].is_whitespace() {
+            continue;
+        }
+
+        // "Dr. Bright" and "J. Smith" carry on
+        if chars[i] == '.' {
+            let word: String = chars[start..i]
+                .iter()
+                .rev()
+                .take_while(|c| !c.is_whitespace())
+                .collect::<Vec<_>>()
+                .into_iter()
+                .rev()
+                .collect();
+            let word = word.trim_start_matches(|c: char| !c.is_alphanumeric());
+            if word.chars().count() == 1 && word.chars().all(char::is_uppercase)
+                || ABBREVIATIONS.contains(&word.to_lowercase().as_str())
+            {
+                continue;
+            }
+        }
+
+        sentences.push(chars[start..end].iter().collect::<String>());
+        start = end;
+    }
+    sentences.push(chars[start..].iter().collect());
+
+    sentences
+        .iter()
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .flat_map(|s| split_long(s, MAX_CUE_CHARS))
+        .collect()
+}
+
+/// Split text into pieces of about the same length that are no longer than `max` characters,
+/// unless a single word is.
+#[inline]
+fn split_long(text: &str, max: usize) -> Vec<String> {
+    let len = text.chars().count();
+    if len <= max {
+        return vec![text.to_string()];
+    }
+
+    let pieces = (len + max - 1) / max;
+    let target = len / pieces;
+    let mut out: Vec<String> = vec![];
+    let mut current = String::new();
+    for word in text.split_whitespace() {
+        let current_len = current.chars().count();
+        let word_len = word.chars().count();
+        if !current.is_empty() && (current_len >= target || current_len + 1 + word_len > max) {
+            out.push(std::mem::take(&mut current));
+        }
+        if !current.is_empty() {
+            current.push(' ');
+        }
+        current.push_str(word);
+    }
+    if !current.is_empty() {
+        out.push(current);
+    }
+    out
+}
+
+/// Time the sentences of some narration across the `duration` seconds it takes to read out,
+/// giving each one time in proportion to how much there is to say.
+#[inline]
+pub fn time_sentences(text: &str, duration: f32) -> Vec<Cue> {
+    let sentences = split_sentences(text);
+
+    // read the sentences the way the TTS engine will, so "$1,000" counts as three words
+    let weights: Vec<usize> = sentences
+        .iter()
+        .map(|s| {
+            normalize(s)
+                .chars()
+                .filter(|c| c.is_alphanumeric())
+                .count()
+                .max(1)
+        })
+        .collect();
+    let total: usize = weights.iter().sum();
+
+    let mut said = 0;
+    sentences
+        .into_iter()
+        .zip(weights)
+        .map(|(text, weight)| {
+            let start = duration * said as f32 / total as f32;
+            said += weight;
+            let end = duration * said as f32 / total as f32;
+            Cue { start, end, text }
+        })
+        .collect()
+}
+
+/// Wrap a caption onto lines of at most `MAX_LINE_CHARS` characters.
+#[inline]
+fn wrap(text: &str) -> String {
+    let mut lines: Vec<String> = vec![];
+    for word in text.split_whitespace() {
+        match lines.last_mut() {
+            Some(line) if line.chars().count() + 1 + word.chars().count() <= MAX_LINE_CHARS => {
+                line.push(' ');
+                line.push_str(word);
+            }
+            _ => lines.push(word.to_string()),
+        }
+    }
+    lines.join("\n")
+}
+
+/// Format a time as `hh:mm:ss` followed by `separator` and milliseconds.
+#[inline]
+fn timestamp(seconds: f32, separator: char) -> String {
+    let millis = (seconds.max(0.0) * 1000.0).round() as u64;
+    format!(
+        "{:02}:{:02}:{:02}{}{:03}",
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        separator,
+        millis % 1000
+    )
+}
+
+/// Write the cues out as a SubRip file.
+#[inline]
+pub fn to_srt(cues: &[Cue]) -> String {
+    let mut srt = String::new();
+    for (i, cue) in cues.iter().enumerate() {
+        let _ = write!(
+            srt,
+            "{}\n{} --> {}\n{}\n\n",
+            i + 1,
+            timestamp(cue.start, ','),
+            timestamp(cue.end, ','),
+            wrap(&cue.text)
+        );
+    }
+    srt
+}
+
+/// Write the cues out as a WebVTT file.
+#[inline]
+pub fn to_vtt(cues: &[Cue]) -> String {
+    let mut vtt = String::from("WEBVTT\n\n");
+    for cue in cues {
+        let _ = write!(
+            vtt,
+            "{} --> {}\n{}\n\n",
+            timestamp(cue.start, '.'),
+            timestamp(cue.end, '.'),
+            wrap(&cue.text)
+        );
+    }
+    vtt
+}
+
+/// Draw a caption near the bottom of an otherwise transparent image the size of the video.
+#[inline]
+pub async fn caption_image(text: &str, width: u32, height: u32) -> crate::Result<RgbaImage> {
+    let (caption, w, h) = text2image::text_overlay(
+        text,
+        height as f32 / 20.0,
+        width * 9 / 10,
+        height / 3,
+        [255, 255, 255],
+        [0, 0, 0],
+        4,
+    )
+    .await?;
+
+    let mut img = RgbaImage::new(width, height);
+    let x = width.saturating_sub(w) / 2;
+    let y = height.saturating_sub(h + height / 12);
+    image::imageops::overlay(&mut img, &caption, x, y);
+    Ok(img)
+}
+
+#[test]
+fn test_split_sentences() {
+    let cases: &[(&str, &[&str])] = &[
+        ("One. Two! Three?", &["One.", "Two!", "Three?"]),
+        (
+            "I met Dr. Smith and J. Doe at 3.5 miles.",
+            &["I met Dr. Smith and J. Doe at 3.5 miles."],
+        ),
+        (
+            "He said \"stop.\" Then he left",
+            &["He said \"stop.\"", "Then he left"],
+        ),
+        (
+            "**Edit:** thanks\n\nfor the gold",
+            &["Edit: thanks.", "for the gold"],
+        ),
+        ("", &[]),
+    ];
+    for (text, expected) in cases {
+        assert_eq!(split_sentences(text), *expected, "splitting {:?}", text);
+    }
+
+    // long sentences are broken into pieces of about the same length
+    let long = "word ".repeat(40);
+    let pieces = split_sentences(&long);
+    assert_eq!(pieces.len(), 3);
+    assert!(pieces.iter().all(|p| p.len() <= MAX_CUE_CHARS));
+}
+
+#[test]
+fn test_caption_files() {
+    let cues = time_sentences("Short. This one is a lot longer than that.", 5.0);
+    assert_eq!(cues.len(), 2);
+    assert_eq!(cues[0].start, 0.0);
+    assert_eq!(cues[0].end, cues[1].start);
+    assert_eq!(cues[1].end, 5.0);
+    assert!(cues[0].end < 1.0);
+
+    let cues = vec![
+        Cue {
+            start: 0.0,
+            end: 1.5,
+            text: "Short.".to_string(),
+        }
+        .shifted(3661.0),
+        Cue {
+            start: 3662.5,
+            end: 3665.0,
+            text: "A caption that is long enough to go over more than one line.".to_string(),
+        },
+    ];
+    assert_eq!(
+        to_srt(&cues),
+        "1\n01:01:01,000 --> 01:01:02,500\nShort.\n\n\
+         2\n01:01:02,500 --> 01:01:05,000\nA caption that is long enough to go over\nmore than one line.\n\n"
+    );
+    assert!(to_vtt(&cues).starts_with("WEBVTT\n\n01:01:01.000 --> 01:01:02.500\nShort.\n\n"));
+}
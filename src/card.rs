@@ -0,0 +1,254 @@
+/*
+ * This file is part of KOTI.
+ *
+ * KOTI is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * KOTI is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Afero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with KOTI.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Renders reddit-style comment cards without needing a browser.
+
+use crate::text2image::{draw_glyphs, glyphs_extent, layout_text, load_font};
+use image::{Rgba, RgbaImage};
+use std::path::PathBuf;
+
+const PADDING: u32 = 24;
+const NESTING_INDENT: u32 = 28;
+const NESTING_BAR_WIDTH: u32 = 4;
+const HEADER_FONT_SIZE: f32 = 26.0;
+const BODY_FONT_SIZE: f32 = 36.0;
+const HEADER_GAP: u32 = 12;
+const META_GAP: u32 = 10;
+const PARAGRAPH_GAP: u32 = 18;
+
+/// Color scheme for a card.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Theme {
+    Light,
+    Dark,
+}
+
+impl Default for Theme {
+    #[inline]
+    fn default() -> Self {
+        Self::Dark
+    }
+}
+
+struct Palette {
+    background: [u8; 3],
+    body: [u8; 3],
+    meta: [u8; 3],
+    author: [u8; 3],
+    nesting_bar: [u8; 3],
+}
+
+impl Theme {
+    #[inline]
+    fn palette(self) -> Palette {
+        match self {
+            Self::Light => Palette {
+                background: [255, 255, 255],
+                body: [28, 28, 28],
+                meta: [120, 124, 126],
+                author: [0, 121, 211],
+                nesting_bar: [218, 224, 230],
+            },
+            Self::Dark => Palette {
+                background: [26, 26, 27],
+                body: [215, 218, 220],
+                meta: [129, 131, 132],
+                author: [79, 188, 255],
+                nesting_bar: [52, 53, 54],
+            },
+        }
+    }
+}
+
+/// The contents of a card.
+#[derive(Debug, Clone)]
+pub struct Card {
+    /// Shown as-is at the top of the card, e.g. "u/username".
+    pub author: String,
+    pub score: Option<i64>,
+    /// How long ago the comment was posted, in seconds.
+    pub age: Option<u64>,
+    pub depth: usize,
+    pub body: String,
+}
+
+/// Render a card into an image of the given width.
+#[inline]
+pub async fn render_card(card: &Card, width: u32, theme: Theme) -> crate::Result<RgbaImage> {
+    let font = load_font().await?;
+    let palette = theme.palette();
+
+    let indent = PADDING + (card.depth as u32) * NESTING_INDENT;
+    let text_width = width.saturating_sub(indent + PADDING);
+    if text_width == 0 {
+        return Err(crate::Error::GlyphOverflow);
+    }
+
+    // header line: author, followed by the score and age
+    let author_glyphs = layout_text(&card.author, HEADER_FONT_SIZE, text_width, font)?;
+    let (author_width, _) = glyphs_extent(&author_glyphs);
+    let meta = card
+        .score
+        .map(format_score)
+        .into_iter()
+        .chain(card.age.map(format_age))
+        .map(|s| format!("\u{2022} {} ", s))
+        .collect::<String>();
+    let meta_glyphs = layout_text(
+        &meta,
+        HEADER_FONT_SIZE,
+        text_width.saturating_sub(author_width + META_GAP),
+        font,
+    )?;
+    let header_height = HEADER_FONT_SIZE as u32;
+
+    // body text, one layout per paragraph
+    let paragraphs = card
+        .body
+        .split('\n')
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+        .map(|p| {
+            let glyphs = layout_text(p, BODY_FONT_SIZE, text_width, font)?;
+            let (_, h) = glyphs_extent(&glyphs);
+            Ok((glyphs, h))
+        })
+        .collect::<crate::Result<Vec<_>>>()?;
+    let body_height = paragraphs.iter().map(|(_, h)| h).sum::<u32>()
+        + PARAGRAPH_GAP * (paragraphs.len().saturating_sub(1) as u32);
+
+    let height = PADDING + header_height + HEADER_GAP + body_height + PADDING;
+    let [r, g, b] = palette.background;
+    let mut img = RgbaImage::from_pixel(width, height, Rgba([r, g, b, 255]));
+
+    // nesting bars go to the left of the text, one per level of depth
+    let [r, g, b] = palette.nesting_bar;
+    (0..card.depth as u32).for_each(|level| {
+        let x = PADDING + level * NESTING_INDENT;
+        (x..x + NESTING_BAR_WIDTH).for_each(|x| {
+            (0..height).for_each(|y| img.put_pixel(x, y, Rgba([r, g, b, 255])));
+        });
+    });
+
+    let indent = indent as i32;
+    let mut y = PADDING as i32;
+    draw_glyphs(&mut img, &author_glyphs, indent, y, palette.author);
+    draw_glyphs(
+        &mut img,
+        &meta_glyphs,
+        indent + (author_width + META_GAP) as i32,
+        y,
+        palette.meta,
+    );
+    y += (header_height + HEADER_GAP) as i32;
+
+    paragraphs.into_iter().for_each(|(glyphs, h)| {
+        draw_glyphs(&mut img, &glyphs, indent, y, palette.body);
+        y += (h + PARAGRAPH_GAP) as i32;
+    });
+
+    Ok(img)
+}
+
+/// Render a card and save it as a PNG.
+#[inline]
+pub async fn save_card(
+    card: &Card,
+    width: u32,
+    theme: Theme,
+    path: PathBuf,
+) -> crate::Result<PathBuf> {
+    let img = render_card(card, width, theme).await?;
+    tokio::task::spawn_blocking(move || {
+        img.save_with_format(&path, image::ImageFormat::Png)?;
+        crate::Result::Ok(path)
+    })
+    .await?
+}
+
+#[inline]
+fn format_score(score: i64) -> String {
+    match score {
+        1 => "1 point".to_string(),
+        s if s.abs() >= 10_000 => format!("{:.1}k points", (s as f64) / 1000.0),
+        s => format!("{} points", s),
+    }
+}
+
+#[inline]
+fn format_age(age: u64) -> String {
+    const UNITS: &[(u64, &str)] = &[
+        (60 * 60 * 24 * 365, "year"),
+        (60 * 60 * 24 * 30, "month"),
+        (60 * 60 * 24, "day"),
+        (60 * 60, "hour"),
+        (60, "minute"),
+    ];
+
+    UNITS
+        .iter()
+        .find(|(secs, _)| age >= *secs)
+        .map(|(secs, unit)| match age / secs {
+            1 => format!("1 {} ago", unit),
+            n => format!("{} {}s ago", n, unit),
+        })
+        .unwrap_or_else(|| "just now".to_string())
+}
+
+#[test]
+fn test_format() {
+    assert_eq!(format_score(1), "1 point");
+    assert_eq!(format_score(512), "512 points");
+    assert_eq!(format_score(48211), "48.2k points");
+    assert_eq!(format_age(30), "just now");
+    assert_eq!(format_age(60), "1 minute ago");
+    assert_eq!(format_age(60 * 60 * 5 + 12), "5 hours ago");
+    assert_eq!(format_age(60 * 60 * 24 * 400), "1 year ago");
+}
+
+#[test]
+fn test_render_card() {
+    tokio::runtime::Builder::new_current_thread()
+        .build()
+        .expect("Unable to construct Tokio runtime")
+        .block_on(async move {
+            let card = Card {
+                author: "u/lumen_drift".to_string(),
+                score: Some(9120),
+                age: Some(60 * 60 * 3),
+                depth: 2,
+                body: "How to properly apologize.\n\nNot \"sorry you feel that way\".".to_string(),
+            };
+
+            let dark = render_card(&card, 800, Theme::Dark).await.unwrap();
+            assert_eq!(dark.width(), 800);
+            assert_eq!(*dark.get_pixel(799, 0), Rgba([26, 26, 27, 255]));
+            assert_eq!(
+                *dark.get_pixel(PADDING + NESTING_INDENT, 0),
+                Rgba([52, 53, 54, 255])
+            );
+
+            // rendering is deterministic
+            let again = render_card(&card, 800, Theme::Dark).await.unwrap();
+            assert_eq!(dark.as_raw(), again.as_raw());
+
+            let light = render_card(&card, 800, Theme::Light).await.unwrap();
+            assert_eq!(light.dimensions(), dark.dimensions());
+            assert_eq!(*light.get_pixel(799, 0), Rgba([255, 255, 255, 255]));
+        });
+}
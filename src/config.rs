@@ -0,0 +1,254 @@
+/*
+ * This file is part of KOTI.
+ *
+ * KOTI is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * KOTI is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Afero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with KOTI.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::{
+    captions::CaptionSettings,
+    card::Theme,
+    frame_source::SourceEntry,
+    loudness::LoudnessSettings,
+    process::{
+        tts::{TtsCacheConfig, TtsSettings},
+        DuckingSettings,
+    },
+    render::RenderBackendKind,
+    thumbnail::ThumbnailSettings,
+};
+use std::{
+    collections::HashSet,
+    io::ErrorKind,
+    mem,
+    path::{Path, PathBuf},
+};
+use tokio::{fs::File, io::AsyncReadExt};
+
+const DEFAULT_FOOTER: &str =
+    "Subscribe to King of the Internet! https://www.youtube.com/channel/UCMk4074NTvhjdFksS8MflMA
+Subscribe to my main channel! https://www.youtube.com/channel/UCa22ge_MKVapVkX8lN1jDuQ
+";
+
+/// Configuration for the channel, loaded from `channel.json` in the data directory.
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChannelConfig {
+    pub sources: Vec<SourceEntry>,
+    pub video_size: VideoSize,
+    /// How videos are narrated, unless their frame source says otherwise.
+    pub tts: TtsSettings,
+    /// How much synthesized audio to keep around for reuse.
+    pub tts_cache: TtsCacheConfig,
+    /// Volume of the background music, in decibels.
+    pub music_volume: f32,
+    /// How the music gets quieter while something is being read out.
+    pub ducking: DuckingSettings,
+    /// How loud rendered videos should be.
+    pub loudness: LoudnessSettings,
+    pub description_footer: String,
+    /// Color scheme used for rendered comment cards.
+    pub card_theme: Theme,
+    /// Program used to render the video, either "melt" or "ffmpeg".
+    pub render_backend: RenderBackendKind,
+    /// Give up on rendering after this many seconds, or never if it's null.
+    pub render_timeout: Option<u64>,
+    /// Captions for the narration.
+    pub captions: CaptionSettings,
+    /// Show the narrated text with each word lit up as it's read, instead of the frame's images.
+    pub karaoke: bool,
+    /// How many thumbnails to make for each video, and how one is picked.
+    pub thumbnails: ThumbnailSettings,
+}
+
+#[derive(Debug, Copy, Clone, serde::Deserialize, serde::Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct VideoSize {
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Default for ChannelConfig {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            sources: vec![SourceEntry {
+                name: "askreddit".to_string(),
+                kind: "reddit_text".to_string(),
+                weight: 1,
+                params: serde_json::json!({
+                    "subreddit": "AskReddit",
+                    "upvote_threshold": 500,
+                    "comment_threshold": 200,
+                    "reply_threshold": 100,
+                    "time_window": "day",
+                }),
+                tts: None,
+            }],
+            video_size: VideoSize {
+                width: 1920,
+                height: 1080,
+            },
+            tts: TtsSettings::default(),
+            tts_cache: TtsCacheConfig::default(),
+            music_volume: -10.0,
+            ducking: DuckingSettings::default(),
+            loudness: LoudnessSettings::default(),
+            description_footer: DEFAULT_FOOTER.to_string(),
+            card_theme: Theme::Dark,
+            render_backend: RenderBackendKind::Melt,
+            render_timeout: Some(4 * 60 * 60),
+            captions: CaptionSettings::default(),
+            karaoke: false,
+            thumbnails: ThumbnailSettings::default(),
+        }
+    }
+}
+
+impl ChannelConfig {
+    /// Load the channel configuration from the data directory, or use the defaults if there
+    /// isn't one.
+    #[inline]
+    pub async fn load(datadir: &Path) -> crate::Result<Self> {
+        let path = config_path(datadir);
+        let mut f = match File::open(&path).await {
+            Ok(f) => f,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                log::info!("No channel configuration at {:?}, using defaults", &path);
+                return Ok(Self::default());
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let mut data = vec![];
+        f.read_to_end(&mut data).await?;
+        mem::drop(f);
+
+        let this: Self = serde_json::from_slice(&data)
+            .map_err(|e| crate::Error::InvalidConfig(format!("{:?}: {}", &path, e)))?;
+        this.validate()?;
+        Ok(this)
+    }
+
+    /// The TTS settings for videos from a frame source.
+    #[inline]
+    pub fn tts_for(&self, source: Option<&str>) -> &TtsSettings {
+        source
+            .and_then(|name| self.sources.iter().find(|s| s.name == name))
+            .and_then(|s| s.tts.as_ref())
+            .unwrap_or(&self.tts)
+    }
+
+    /// Make sure the configuration makes sense.
+    #[inline]
+    pub fn validate(&self) -> crate::Result {
+        let invalid = |msg: String| Err(crate::Error::InvalidConfig(msg));
+
+        if self.sources.is_empty() {
+            return invalid("no frame sources are listed".to_string());
+        }
+        if self.sources.iter().all(|s| s.weight == 0) {
+            return invalid("every frame source has a weight of zero".to_string());
+        }
+        let mut names = HashSet::new();
+        for source in &self.sources {
+            if source.name.is_empty() {
+                return invalid(format!(
+                    "a frame source of kind {} has no name",
+                    source.kind
+                ));
+            }
+            if !names.insert(source.name.as_str()) {
+                return invalid(format!(
+                    "frame source \"{}\" is listed more than once",
+                    source.name
+                ));
+            }
+        }
+
+        let VideoSize { width, height } = self.video_size;
+        if width == 0 || height == 0 {
+            return invalid(format!("video size {}x{} is empty", width, height));
+        }
+        if width % 2 != 0 || height % 2 != 0 {
+            return invalid(format!(
+                "video size {}x{} must have even dimensions",
+                width, height
+            ));
+        }
+
+        self.tts.validate()?;
+        for source in &self.sources {
+            if let Some(Err(crate::Error::InvalidConfig(msg))) =
+                source.tts.as_ref().map(TtsSettings::validate)
+            {
+                return invalid(format!("frame source {}: {}", &source.name, msg));
+            }
+        }
+
+        if !self.music_volume.is_finite() || !(-60.0..=20.0).contains(&self.music_volume) {
+            return invalid(format!(
+                "music volume {}dB is outside of the range -60dB to 20dB",
+                self.music_volume
+            ));
+        }
+
+        self.ducking.validate()?;
+        self.loudness.validate()?;
+        self.thumbnails.validate()?;
+
+        if self.render_timeout == Some(0) {
+            return invalid("the render timeout must be at least a second".to_string());
+        }
+
+        Ok(())
+    }
+}
+
+#[inline]
+fn config_path(datadir: &Path) -> PathBuf {
+    datadir.join("channel.json")
+}
+
+#[test]
+fn test_validate() {
+    ChannelConfig::default().validate().unwrap();
+
+    let cfg: ChannelConfig = serde_json::from_str(
+        r#"{"video_size": {"width": 1280, "height": 720}, "music_volume": -15}"#,
+    )
+    .unwrap();
+    cfg.validate().unwrap();
+    assert_eq!(cfg.sources.len(), 1);
+
+    let mut cfg = ChannelConfig::default();
+    cfg.video_size.width = 1279;
+    cfg.validate().unwrap_err();
+
+    let mut cfg = ChannelConfig::default();
+    cfg.sources.push(cfg.sources[0].clone());
+    cfg.validate().unwrap_err();
+
+    let mut cfg = ChannelConfig::default();
+    cfg.tts.voice = Some("kal_diphone) (quit".to_string());
+    cfg.validate().unwrap_err();
+
+    let mut cfg = ChannelConfig::default();
+    cfg.render_timeout = Some(0);
+    cfg.validate().unwrap_err();
+
+    let mut cfg = ChannelConfig::default();
+    cfg.thumbnails.variants = 0;
+    cfg.validate().unwrap_err();
+
+    serde_json::from_str::<ChannelConfig>(r#"{"music_volum": -15}"#).unwrap_err();
+}
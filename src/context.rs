@@ -15,26 +15,13 @@
  * along with KOTI.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use event_listener::{Event, EventListener};
+use crate::config::{ChannelConfig, VideoSize};
+use once_cell::sync::OnceCell;
 use std::{mem, path::PathBuf};
 use tokio::sync::Mutex;
 
-const VIDEO_WIDTH: usize = 1920;
-const VIDEO_HEIGHT: usize = 1080;
-
-const DEFAULT_STRING: &str =
-    "Subscribe to King of the Internet! https://www.youtube.com/channel/UCMk4074NTvhjdFksS8MflMA
-Subscribe to my main channel! https://www.youtube.com/channel/UCa22ge_MKVapVkX8lN1jDuQ
-";
-
 #[derive(Debug, Default)]
 struct ContextCore {
-    thumbnail_template: Option<String>,
-    thumbnail_text: Option<String>,
-    thumbnail_path: Option<PathBuf>,
-    video_title: Option<String>,
-    video_description: String,
-    video_path: Option<PathBuf>,
     basedir: Option<PathBuf>,
     datadir: Option<PathBuf>,
 }
@@ -42,7 +29,7 @@ struct ContextCore {
 #[derive(Debug, Default)]
 pub struct Context {
     core: Mutex<ContextCore>,
-    thumbnail_ready: Event,
+    config: OnceCell<ChannelConfig>,
 }
 
 impl Context {
@@ -50,16 +37,10 @@ impl Context {
     pub const fn new() -> Self {
         Context {
             core: Mutex::const_new(ContextCore {
-                thumbnail_template: None,
-                thumbnail_text: None,
-                thumbnail_path: None,
-                video_title: None,
-                video_description: String::new(),
-                video_path: None,
                 basedir: None,
                 datadir: None,
             }),
-            thumbnail_ready: Event::new(),
+            config: OnceCell::new(),
         }
     }
 
@@ -78,124 +59,36 @@ impl Context {
     }
 
     #[inline]
-    pub async fn basedir(&self) -> PathBuf {
-        self.core.lock().await.basedir.clone().unwrap()
-    }
-
-    #[inline]
-    pub async fn datadir(&self) -> PathBuf {
-        self.core.lock().await.datadir.clone().unwrap()
-    }
-
-    #[inline]
-    pub async fn reset(&self) {
-        *self.core.lock().await = Default::default();
-    }
-
-    #[inline]
-    pub async fn set_video_title(&self, title: String) {
-        let mut core = self.core.lock().await;
-        if mem::replace(&mut core.video_title, Some(title)).is_some() {
-            panic!("Video title already exists!");
+    pub fn set_config(&self, config: ChannelConfig) {
+        if self.config.set(config).is_err() {
+            panic!("Channel config already exists!");
         }
     }
 
+    /// Get the channel configuration, or the default configuration if none was loaded.
     #[inline]
-    pub async fn take_video_title(&self) -> String {
-        self.core
-            .lock()
-            .await
-            .video_title
-            .take()
-            .expect("Video title already taken!")
-    }
-
-    #[inline]
-    pub async fn set_thumbnail(&self, text: String, template: String) {
-        let mut core = self.core.lock().await;
-        if mem::replace(&mut core.thumbnail_text, Some(text)).is_some() {
-            panic!("Thumbnail text already exists!");
-        }
-        if mem::replace(&mut core.thumbnail_template, Some(template)).is_some() {
-            panic!("Thumbnail template already exists!");
-        }
-
-        mem::drop(core);
-        self.thumbnail_ready.notify_additional(usize::MAX);
+    pub fn config(&self) -> &ChannelConfig {
+        self.config.get_or_init(ChannelConfig::default)
     }
 
     #[inline]
-    pub async fn take_thumbnail_text(&self) -> String {
-        self.core
-            .lock()
-            .await
-            .thumbnail_text
-            .take()
-            .expect("Thumbnail text already taken!")
-    }
-
-    #[inline]
-    pub async fn take_thumbnail_template(&self) -> String {
-        self.core
-            .lock()
-            .await
-            .thumbnail_template
-            .take()
-            .expect("Thumbnail template already taken!")
-    }
-
-    #[inline]
-    pub async fn wait_for_thumbnail(&self) {
-        self.thumbnail_ready.listen().await
-    }
-
-    #[inline]
-    pub async fn set_video_path(&self, vidpath: PathBuf) {
-        if mem::replace(&mut self.core.lock().await.video_path, Some(vidpath)).is_some() {
-            panic!("Vidpath already existed!");
-        }
-    }
-
-    #[inline]
-    pub async fn take_video_path(&self) -> PathBuf {
-        self.core
-            .lock()
-            .await
-            .video_path
-            .take()
-            .expect("Video path not yet set")
+    pub async fn basedir(&self) -> PathBuf {
+        self.core.lock().await.basedir.clone().unwrap()
     }
 
     #[inline]
-    pub async fn set_thumbnail_path(&self, thumbpath: PathBuf) {
-        if mem::replace(&mut self.core.lock().await.thumbnail_path, Some(thumbpath)).is_some() {
-            panic!("Thumbpath already existed!");
-        }
+    pub async fn datadir(&self) -> PathBuf {
+        self.core.lock().await.datadir.clone().unwrap()
     }
 
     #[inline]
-    pub async fn take_thumbnail_path(&self) -> PathBuf {
-        self.core
-            .lock()
-            .await
-            .thumbnail_path
-            .take()
-            .expect("Thumbnail path not yet set")
+    pub async fn reset(&self) {
+        *self.core.lock().await = Default::default();
     }
 
     #[inline]
     pub fn video_size(&self) -> (usize, usize) {
-        // putting this in here because I might want to make this pluggable at some point
-        (VIDEO_WIDTH, VIDEO_HEIGHT)
-    }
-
-    #[inline]
-    pub async fn append_to_description(&self, desc: String) {
-        self.core.lock().await.video_description.push_str(&desc);
-    }
-
-    #[inline]
-    pub async fn take_video_description(&self) -> String {
-        mem::take(&mut self.core.lock().await.video_description)
+        let VideoSize { width, height } = self.config().video_size;
+        (width, height)
     }
 }
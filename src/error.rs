@@ -18,7 +18,7 @@
 use image::ImageError;
 use quick_xml::Error as XmlError;
 use serde_json::Error as JsonError;
-use std::{fmt, io::Error as IoError};
+use std::{fmt, io::Error as IoError, path::PathBuf};
 use thirtyfour::error::WebDriverError;
 use tokio::task::JoinError;
 
@@ -34,11 +34,19 @@ pub enum Error {
     NumParseError,
     ScoreTimedOut,
     Timeout,
-    EspeakError(Option<i32>, Option<String>),
+    TtsFailed(&'static str, Option<i32>, Option<String>),
     Hound(hound::Error),
     Xml(XmlError),
     GlyphOverflow,
     DisallowedWord,
+    TooShort,
+    InvalidConfig(String),
+    InvalidTimeline(String),
+    RenderFailed(&'static str, Option<i32>, String),
+    ProbeFailed(PathBuf, String),
+    NoMusic(Option<String>),
+    Http(hyper::Error),
+    HttpStatus(u16, String),
 }
 
 impl fmt::Display for Error {
@@ -55,16 +63,42 @@ impl fmt::Display for Error {
             Self::NumParseError => f.write_str("Could not parse number"),
             Self::ScoreTimedOut => f.write_str("Score timed out"),
             Self::Timeout => f.write_str("Operation timed out"),
-            Self::EspeakError(None, None) => f.write_str("Espeak failed"),
-            Self::EspeakError(Some(code), None) => write!(f, "Espeak failed with code {}", code),
-            Self::EspeakError(None, Some(err)) => write!(f, "Espeak failed: {}", err),
-            Self::EspeakError(Some(code), Some(err)) => {
-                write!(f, "Espeak failed with code {}: {}", code, err)
+            Self::TtsFailed(engine, None, None) => write!(f, "{} failed", engine),
+            Self::TtsFailed(engine, Some(code), None) => {
+                write!(f, "{} failed with code {}", engine, code)
+            }
+            Self::TtsFailed(engine, None, Some(err)) => write!(f, "{} failed: {}", engine, err),
+            Self::TtsFailed(engine, Some(code), Some(err)) => {
+                write!(f, "{} failed with code {}: {}", engine, code, err)
             }
             Self::Hound(h) => fmt::Display::fmt(h, f),
             Self::Xml(x) => fmt::Display::fmt(x, f),
             Self::GlyphOverflow => f.write_str("Glyphs could not fit in bounding box"),
             Self::DisallowedWord => f.write_str("Found a word that isn't allowed to be used"),
+            Self::TooShort => f.write_str("Video is shorter than a minute"),
+            Self::InvalidConfig(s) => write!(f, "Invalid channel configuration: {}", s),
+            Self::InvalidTimeline(s) => write!(f, "Invalid MLT project: {}", s),
+            Self::RenderFailed(program, code, stderr) => {
+                write!(f, "{} failed", program)?;
+                if let Some(code) = code {
+                    write!(f, " with code {}", code)?;
+                }
+                if !stderr.is_empty() {
+                    write!(f, ":\n{}", stderr)?;
+                }
+                Ok(())
+            }
+            Self::ProbeFailed(path, msg) => write!(f, "Unable to probe {:?}: {}", path, msg),
+            Self::NoMusic(None) => {
+                f.write_str("No music tracks can be used; add one with `koti music add`")
+            }
+            Self::NoMusic(Some(source)) => write!(
+                f,
+                "No music tracks can be used for videos from {}; add one with `koti music add`",
+                source
+            ),
+            Self::Http(h) => fmt::Display::fmt(h, f),
+            Self::HttpStatus(code, url) => write!(f, "Got HTTP status {} from {}", code, url),
         }
     }
 }
@@ -118,4 +152,11 @@ impl From<JsonError> for Error {
     }
 }
 
+impl From<hyper::Error> for Error {
+    #[inline]
+    fn from(h: hyper::Error) -> Error {
+        Self::Http(h)
+    }
+}
+
 pub type Result<T = ()> = std::result::Result<T, Error>;
@@ -17,11 +17,14 @@
 
 use std::path::PathBuf;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
 pub struct Frame {
     pub tts: String,
     pub overlaid: String,
     pub imagepath: Option<PathBuf>,
     pub imagefadesin: bool,
     pub persists_after_tts: f32,
+    /// Who is talking, so they keep the same voice throughout. `None` is the narrator.
+    #[serde(default)]
+    pub speaker: Option<String>,
 }
@@ -0,0 +1,252 @@
+/*
+ * This file is part of KOTI.
+ *
+ * KOTI is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * KOTI is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Afero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with KOTI.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::{
+    context::Context,
+    process::tts::TtsSettings,
+    reddit_text_source::{RedditJsonSource, RedditTextSource},
+    scp::ScpSource,
+    Frame,
+};
+use futures_lite::stream::{Stream, StreamExt};
+use nanorand::{tls_rng, RNG};
+use std::{
+    collections::{BTreeMap, HashMap},
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+};
+
+pub type FrameStream = Pin<Box<dyn Stream<Item = Frame> + Send + 'static>>;
+pub type ProduceFuture<'a> = Pin<Box<dyn Future<Output = crate::Result<SourceOutput>> + Send + 'a>>;
+pub type FrameSourceFactory = fn() -> Box<dyn FrameSource>;
+
+/// Title and thumbnail information for the video a frame source is producing.
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct VideoMetadata {
+    pub title: String,
+    pub thumbnail_text: String,
+    pub thumbnail_template: String,
+    /// Values for the text fields of the thumbnail template, such as the subreddit.
+    #[serde(default)]
+    pub thumbnail_fields: BTreeMap<String, String>,
+    /// Appended to the video description, e.g. for crediting the original authors.
+    pub description: String,
+}
+
+/// The result of running a frame source.
+pub struct SourceOutput {
+    pub metadata: VideoMetadata,
+    pub frames: FrameStream,
+}
+
+/// Something that can produce the frames for a video.
+pub trait FrameSource: Send + Sync {
+    /// The kind of frame source this is, e.g. "reddit_text".
+    fn name(&self) -> &str;
+
+    /// Configure this source using the parameters from its configuration entry.
+    fn configure(&mut self, params: &serde_json::Value) -> crate::Result;
+
+    /// Produce the metadata for the video, as well as the frames that make it up.
+    fn produce<'a>(&'a self, ctx: &'a Context) -> ProduceFuture<'a>;
+}
+
+/// A frame source listed in the channel configuration.
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct SourceEntry {
+    pub name: String,
+    pub kind: String,
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+    #[serde(default)]
+    pub params: serde_json::Value,
+    /// Narrate videos from this source differently from the rest of the channel.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tts: Option<TtsSettings>,
+}
+
+#[inline]
+fn default_weight() -> u32 {
+    1
+}
+
+struct RegisteredSource {
+    name: String,
+    weight: u32,
+    source: Arc<dyn FrameSource>,
+}
+
+/// The set of frame sources that videos can be created from.
+pub struct FrameSourceRegistry {
+    factories: HashMap<&'static str, FrameSourceFactory>,
+    sources: Vec<RegisteredSource>,
+}
+
+impl Default for FrameSourceRegistry {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FrameSourceRegistry {
+    /// Create a new registry that knows about the built-in frame source kinds.
+    #[inline]
+    pub fn new() -> Self {
+        let mut this = Self {
+            factories: HashMap::new(),
+            sources: vec![],
+        };
+        this.register_kind("reddit_text", || Box::new(RedditTextSource::default()));
+        this.register_kind("reddit_json", || Box::new(RedditJsonSource::default()));
+        this.register_kind("scp", || Box::new(ScpSource::default()));
+        this
+    }
+
+    /// Load the registry from the frame sources listed in the channel configuration.
+    #[inline]
+    pub fn load(ctx: &Context) -> crate::Result<Self> {
+        let mut this = Self::new();
+        ctx.config()
+            .sources
+            .iter()
+            .cloned()
+            .try_for_each(|entry| this.add_source(entry))?;
+        Ok(this)
+    }
+
+    #[inline]
+    pub fn register_kind(&mut self, kind: &'static str, factory: FrameSourceFactory) {
+        self.factories.insert(kind, factory);
+    }
+
+    /// Create a frame source from a configuration entry and add it to the registry.
+    #[inline]
+    pub fn add_source(&mut self, entry: SourceEntry) -> crate::Result {
+        let SourceEntry {
+            name,
+            kind,
+            weight,
+            params,
+            ..
+        } = entry;
+
+        if self.sources.iter().any(|s| s.name == name) {
+            return Err(crate::Error::Msg(format!(
+                "Frame source \"{}\" is defined more than once",
+                name
+            )));
+        }
+
+        let factory = self
+            .factories
+            .get(kind.as_str())
+            .ok_or_else(|| crate::Error::Msg(format!("Unknown frame source kind: {}", kind)))?;
+        let mut source = factory();
+        source.configure(&params)?;
+
+        self.sources.push(RegisteredSource {
+            name,
+            weight,
+            source: source.into(),
+        });
+        Ok(())
+    }
+
+    #[inline]
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.sources.iter().map(|s| s.name.as_str())
+    }
+
+    #[inline]
+    pub fn get(&self, name: &str) -> Option<Arc<dyn FrameSource>> {
+        self.sources
+            .iter()
+            .find(|s| s.name == name)
+            .map(|s| s.source.clone())
+    }
+
+    /// Choose a frame source at random, taking the weights into account.
+    #[inline]
+    pub fn choose(&self) -> Option<Arc<dyn FrameSource>> {
+        self.choose_name().and_then(|name| self.get(name))
+    }
+
+    /// Choose the name of a frame source at random, taking the weights into account.
+    #[inline]
+    pub fn choose_name(&self) -> Option<&str> {
+        let total: u32 = self.sources.iter().map(|s| s.weight).sum();
+        if total == 0 {
+            return None;
+        }
+
+        let mut pick = tls_rng().generate_range::<u32>(0, total);
+        self.sources
+            .iter()
+            .find(|s| match pick.checked_sub(s.weight) {
+                Some(rest) => {
+                    pick = rest;
+                    false
+                }
+                None => true,
+            })
+            .map(|s| s.name.as_str())
+    }
+}
+
+/// Run a frame source and collect the frames it produces.
+#[inline]
+pub async fn run_frame_source(
+    source: Arc<dyn FrameSource>,
+    ctx: &Context,
+) -> crate::Result<(VideoMetadata, Vec<Frame>)> {
+    log::info!("Running frame source: {}", source.name());
+    let SourceOutput { metadata, frames } = source.produce(ctx).await?;
+    println!("Created {} stream source", source.name());
+
+    let frames: Vec<Frame> = frames.collect().await;
+    if frames.is_empty() {
+        return Err(crate::Error::Msg(format!(
+            "Frame source {} did not produce any frames",
+            source.name()
+        )));
+    }
+
+    Ok((metadata, frames))
+}
+
+#[test]
+fn test_add_source() {
+    let ctx = Context::new();
+    let mut registry = FrameSourceRegistry::load(&ctx).unwrap();
+    assert!(registry.get("askreddit").is_some());
+    assert_eq!(registry.choose().unwrap().name(), "reddit_text");
+
+    registry
+        .add_source(ctx.config().sources[0].clone())
+        .unwrap_err();
+    registry
+        .add_source(SourceEntry {
+            name: "nothing".to_string(),
+            kind: "does_not_exist".to_string(),
+            weight: 1,
+            params: serde_json::Value::Null,
+            tts: None,
+        })
+        .unwrap_err();
+}
@@ -0,0 +1,703 @@
+/*
+ * This file is part of KOTI.
+ *
+ * KOTI is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * KOTI is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Afero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with KOTI.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! On-disk state for a video that is being made, so a failed run can pick up where it left off.
+
+use crate::{
+    config::ChannelConfig,
+    context::Context,
+    frame::Frame,
+    frame_source::{self, FrameSourceRegistry, VideoMetadata},
+    loudness::{self, LoudnessReport},
+    process::{self, ConvertedFrame, Plan, Project},
+    render::Progress,
+    thumbnail::{self, ThumbnailContent, ThumbnailRecord, ThumbnailVariant},
+    util, youtube,
+};
+use futures_lite::future;
+use nanorand::{tls_rng, RNG};
+use std::{
+    fmt, mem,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    fs::{self, File},
+    io::{AsyncReadExt, AsyncWriteExt},
+    sync::{watch, Mutex},
+};
+
+const MANIFEST_NAME: &str = "job.json";
+const PLAN_NAME: &str = "plan.json";
+
+/// A step in making a video. Each one saves its outputs to the job manifest once it's done.
+#[derive(
+    Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, serde::Deserialize, serde::Serialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    Scrape,
+    Tts,
+    Overlays,
+    Project,
+    Render,
+    Loudness,
+    Thumbnail,
+    Upload,
+}
+
+impl fmt::Display for Stage {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Scrape => "scrape",
+            Self::Tts => "tts",
+            Self::Overlays => "overlays",
+            Self::Project => "project",
+            Self::Render => "render",
+            Self::Loudness => "loudness",
+            Self::Thumbnail => "thumbnail",
+            Self::Upload => "upload",
+        })
+    }
+}
+
+/// Everything we know about a job, saved as `job.json` in the job directory.
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct JobManifest {
+    pub id: String,
+    /// Name of the frame source to use, or `None` to pick one at random.
+    pub source: Option<String>,
+    pub upload: bool,
+    /// Name of the frame source the frames came from.
+    #[serde(default)]
+    pub scraped_from: Option<String>,
+    #[serde(default)]
+    pub completed: Vec<Stage>,
+    #[serde(default)]
+    pub metadata: Option<VideoMetadata>,
+    #[serde(default)]
+    pub frames: Vec<Frame>,
+    /// TTS audio and its duration, for each frame.
+    #[serde(default)]
+    pub tts: Vec<Option<(PathBuf, f32)>>,
+    #[serde(default)]
+    pub converted: Vec<ConvertedFrame>,
+    #[serde(default)]
+    pub project: Option<Project>,
+    #[serde(default)]
+    pub video_path: Option<PathBuf>,
+    /// How loud the video and what went into it are.
+    #[serde(default)]
+    pub loudness: Option<LoudnessReport>,
+    /// The thumbnail to upload.
+    #[serde(default)]
+    pub thumbnail_path: Option<PathBuf>,
+    /// Every thumbnail that was made for the video.
+    #[serde(default)]
+    pub thumbnail_variants: Vec<ThumbnailVariant>,
+    /// Name of the thumbnail variant that was picked with `koti thumbnail pick`, if one was.
+    #[serde(default)]
+    pub thumbnail_picked: Option<String>,
+}
+
+impl JobManifest {
+    #[inline]
+    fn new(id: String, source: Option<String>, upload: bool) -> Self {
+        Self {
+            id,
+            source,
+            upload,
+            scraped_from: None,
+            completed: vec![],
+            metadata: None,
+            frames: vec![],
+            tts: vec![],
+            converted: vec![],
+            project: None,
+            video_path: None,
+            loudness: None,
+            thumbnail_path: None,
+            thumbnail_variants: vec![],
+            thumbnail_picked: None,
+        }
+    }
+
+    #[inline]
+    pub fn is_complete(&self, stage: Stage) -> bool {
+        self.completed.contains(&stage)
+    }
+}
+
+/// A video that is being made, backed by a directory in the home directory.
+#[derive(Debug)]
+pub struct Job {
+    dir: PathBuf,
+    manifest: Mutex<JobManifest>,
+}
+
+impl Job {
+    /// Create a new job with a random ID.
+    #[inline]
+    pub async fn create(
+        homedir: &Path,
+        source: Option<String>,
+        upload: bool,
+    ) -> crate::Result<Self> {
+        let id = format!("koti{}", tls_rng().generate::<usize>());
+        let dir = homedir.join(&id);
+        log::info!("Setting up shop at {:?}", &dir);
+        fs::create_dir_all(&dir).await?;
+
+        let manifest = JobManifest::new(id, source, upload);
+        save_manifest(&dir, &manifest).await?;
+        Ok(Self {
+            dir,
+            manifest: Mutex::new(manifest),
+        })
+    }
+
+    /// Open a job that was created earlier.
+    #[inline]
+    pub async fn open(homedir: &Path, id: &str) -> crate::Result<Self> {
+        let dir = homedir.join(id);
+        let mut f = File::open(dir.join(MANIFEST_NAME)).await.map_err(|e| {
+            crate::Error::Msg(format!("Unable to open job {} in {:?}: {}", id, &dir, e))
+        })?;
+        let mut data = vec![];
+        f.read_to_end(&mut data).await?;
+        mem::drop(f);
+
+        let manifest: JobManifest = serde_json::from_slice(&data)?;
+        log::info!(
+            "Resuming job {}, completed stages: {:?}",
+            &manifest.id,
+            &manifest.completed
+        );
+        Ok(Self {
+            dir,
+            manifest: Mutex::new(manifest),
+        })
+    }
+
+    #[inline]
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    #[inline]
+    pub async fn manifest(&self) -> JobManifest {
+        self.manifest.lock().await.clone()
+    }
+
+    #[inline]
+    pub async fn is_complete(&self, stage: Stage) -> bool {
+        self.manifest.lock().await.is_complete(stage)
+    }
+
+    /// Record the outputs of a stage and mark it as completed.
+    #[inline]
+    pub async fn complete<F: FnOnce(&mut JobManifest)>(&self, stage: Stage, f: F) -> crate::Result {
+        let mut manifest = self.manifest.lock().await;
+        f(&mut manifest);
+        if !manifest.is_complete(stage) {
+            manifest.completed.push(stage);
+        }
+        save_manifest(&self.dir, &manifest).await?;
+        log::info!("Job {} finished stage {}", &manifest.id, stage);
+        Ok(())
+    }
+
+    /// Use the thumbnail variant with the given name, if the video hasn't gone out yet.
+    #[inline]
+    pub async fn pick_thumbnail(&self, name: &str) -> crate::Result<ThumbnailVariant> {
+        let mut manifest = self.manifest.lock().await;
+        if manifest.is_complete(Stage::Upload) {
+            return Err(crate::Error::Msg(format!(
+                "Job {} has already been uploaded",
+                &manifest.id
+            )));
+        }
+        let variant = manifest
+            .thumbnail_variants
+            .iter()
+            .find(|v| v.name == name)
+            .cloned()
+            .ok_or_else(|| {
+                crate::Error::Msg(format!(
+                    "Job {} has no thumbnail variant named {}",
+                    &manifest.id, name
+                ))
+            })?;
+        manifest.thumbnail_path = Some(variant.path.clone());
+        manifest.thumbnail_picked = Some(variant.name.clone());
+        save_manifest(&self.dir, &manifest).await?;
+        Ok(variant)
+    }
+
+    /// Forget everything this job has done, so the next run starts over from scraping.
+    #[inline]
+    pub async fn restart(&self) -> crate::Result {
+        let mut manifest = self.manifest.lock().await;
+        *manifest = JobManifest::new(
+            manifest.id.clone(),
+            manifest.source.clone(),
+            manifest.upload,
+        );
+        save_manifest(&self.dir, &manifest).await
+    }
+}
+
+#[inline]
+async fn save_manifest(dir: &Path, manifest: &JobManifest) -> crate::Result {
+    // write to a temporary file first, so a crash never leaves a half-written manifest behind
+    let data = serde_json::to_vec_pretty(manifest)?;
+    let tmppath = dir.join(format!("{}.tmp", MANIFEST_NAME));
+    let mut f = File::create(&tmppath).await?;
+    f.write_all(&data).await?;
+    f.sync_all().await?;
+    mem::drop(f);
+    fs::rename(&tmppath, dir.join(MANIFEST_NAME)).await?;
+    Ok(())
+}
+
+/// What goes in the thumbnail: the fields from the frame source, plus the thumbnail text, the
+/// pictures in the video and the first of them as the `frame` image.
+#[inline]
+fn thumbnail_content(metadata: &VideoMetadata, frames: &[Frame]) -> ThumbnailContent {
+    let mut content = ThumbnailContent::new(metadata.thumbnail_text.clone());
+    content.fields.extend(
+        metadata
+            .thumbnail_fields
+            .iter()
+            .map(|(name, value)| (name.clone(), value.clone())),
+    );
+    content.frames = frames
+        .iter()
+        .filter_map(|frame| frame.imagepath.clone())
+        .collect();
+    if let Some(path) = content.frames.first() {
+        content.images.insert("frame".to_string(), path.clone());
+    }
+    content
+}
+
+#[inline]
+fn missing(what: &str) -> crate::Error {
+    crate::Error::Msg(format!("Job manifest is missing {}", what))
+}
+
+/// Run every stage of the job that hasn't been completed yet. For a dry run, stop once the MLT
+/// project has been written and write out the plan for the video instead of rendering it.
+#[inline]
+pub async fn run(job: Arc<Job>, datadir: PathBuf, dry_run: bool) -> crate::Result {
+    let ctx = Arc::new(Context::new());
+    ctx.set_basedir(job.dir().to_path_buf()).await;
+    ctx.set_config(ChannelConfig::load(&datadir).await?);
+    ctx.set_datadir(datadir).await;
+
+    // scrape: run the frame source and save the frames it produces
+    if !job.is_complete(Stage::Scrape).await {
+        let sources = FrameSourceRegistry::load(&ctx)?;
+        let name = match job.manifest().await.source {
+            Some(name) => name,
+            None => sources
+                .choose_name()
+                .ok_or(crate::Error::StaticMsg("No frame sources are enabled"))?
+                .to_string(),
+        };
+        let frame_source = sources
+            .get(&name)
+            .ok_or_else(|| crate::Error::Msg(format!("No frame source named {}", &name)))?;
+
+        let (metadata, frames) = frame_source::run_frame_source(frame_source, &ctx).await?;
+        job.complete(Stage::Scrape, move |m| {
+            // remember which source this was, so its settings are used for the rest of the job
+            m.scraped_from = Some(name);
+            m.metadata = Some(metadata);
+            m.frames = frames;
+        })
+        .await?;
+    }
+
+    let metadata = job
+        .manifest()
+        .await
+        .metadata
+        .ok_or_else(|| missing("video metadata"))?;
+
+    if dry_run {
+        restart_if_too_short(&job, prepare_project(&job, ctx).await).await?;
+        let plan_path = write_plan(&job).await?;
+        let id = job.manifest().await.id;
+        println!(
+            "Wrote the plan for job {} to {:?}; render it with: koti resume {}",
+            &id, plan_path, &id
+        );
+        return Ok(());
+    }
+
+    // the thumbnail only depends on the metadata, so make it while the video is being made
+    let video = async {
+        prepare_project(&job, ctx.clone()).await?;
+        render_project(&job, &ctx).await?;
+        normalize_loudness(&job, &ctx).await
+    };
+    let thumbnail = async {
+        if !job.is_complete(Stage::Thumbnail).await {
+            let content = thumbnail_content(&metadata, &job.manifest().await.frames);
+            let variants = thumbnail::create_thumbnail(
+                ctx.clone(),
+                content,
+                metadata.thumbnail_template.clone(),
+            )
+            .await?;
+            job.complete(Stage::Thumbnail, move |m| {
+                // use the first one unless another one gets picked
+                m.thumbnail_path = variants.first().map(|v| v.path.clone());
+                m.thumbnail_variants = variants;
+                m.thumbnail_picked = None;
+            })
+            .await?;
+        }
+
+        crate::Result::Ok(())
+    };
+    let (video, thumbnail) = futures_lite::future::zip(video, thumbnail).await;
+    restart_if_too_short(&job, video).await?;
+    thumbnail?;
+
+    // now that we have a video and a thumbnail, upload to YouTube
+    let manifest = job.manifest().await;
+    if !manifest.is_complete(Stage::Upload)
+        && ctx.config().thumbnails.pick
+        && manifest.thumbnail_picked.is_none()
+    {
+        println!(
+            "Made {} thumbnails for job {}:",
+            manifest.thumbnail_variants.len(),
+            &manifest.id
+        );
+        for variant in &manifest.thumbnail_variants {
+            println!(
+                "{}\t{:?}\t{}",
+                &variant.name,
+                &variant.path,
+                variant.describe()
+            );
+        }
+        println!(
+            "Pick one with: koti thumbnail pick {} VARIANT, then continue with: koti resume {}",
+            &manifest.id, &manifest.id
+        );
+        return Ok(());
+    }
+    if !manifest.is_complete(Stage::Upload) {
+        let video_path = manifest
+            .video_path
+            .clone()
+            .ok_or_else(|| missing("the video"))?;
+        let thumbnail_path = manifest
+            .thumbnail_path
+            .clone()
+            .ok_or_else(|| missing("the thumbnail"))?;
+
+        let project = manifest
+            .project
+            .as_ref()
+            .ok_or_else(|| missing("the project"))?;
+        if manifest.upload {
+            let music_credits = &project.music_credits;
+            let description = format!(
+                "{}Music Credits:\n{}\n",
+                &metadata.description, music_credits
+            );
+            let captions_path = project
+                .caption_files
+                .iter()
+                .find(|path| path.extension().map_or(false, |ext| ext == "srt"))
+                .cloned();
+            let video_id = youtube::upload_to_youtube(
+                &ctx,
+                video_path,
+                thumbnail_path,
+                captions_path,
+                metadata.title.clone(),
+                description,
+            )
+            .await?;
+            record_thumbnail(&ctx, &manifest, &metadata, Some(video_id)).await;
+        } else {
+            // the thumbnail is about to be moved out from under the record
+            record_thumbnail(&ctx, &manifest, &metadata, None).await;
+            let viddir = dirs::video_dir().ok_or(crate::Error::StaticMsg(
+                "Unable to find the videos directory",
+            ))?;
+            let vidpath = viddir.join(format!("{}.webm", &manifest.id));
+            fs::rename(video_path, &vidpath).await?;
+            log::info!("Moved video to {:?}", &vidpath);
+            let thumbpath = viddir.join(format!("{}.png", &manifest.id));
+            fs::rename(thumbnail_path, &thumbpath).await?;
+            log::info!("Moved thumbnail to {:?}", &thumbpath);
+            for path in &project.caption_files {
+                let extension = path.extension().unwrap_or_default().to_string_lossy();
+                let captionpath = viddir.join(format!("{}.{}", &manifest.id, extension));
+                fs::rename(path, &captionpath).await?;
+                log::info!("Moved captions to {:?}", &captionpath);
+            }
+        }
+
+        job.complete(Stage::Upload, |_| ()).await?;
+    }
+
+    fs::remove_dir_all(job.dir()).await?;
+
+    Ok(())
+}
+
+/// Note which thumbnail variant the video went out with, keeping copies of the variants. The video
+/// is already out, so this doesn't fail.
+#[inline]
+async fn record_thumbnail(
+    ctx: &Context,
+    manifest: &JobManifest,
+    metadata: &VideoMetadata,
+    video_id: Option<String>,
+) {
+    let used = match (
+        &manifest.thumbnail_picked,
+        manifest.thumbnail_variants.first(),
+    ) {
+        (Some(picked), _) => picked.clone(),
+        (None, Some(first)) => first.name.clone(),
+        // jobs from before there were variants
+        (None, None) => return,
+    };
+    let record = ThumbnailRecord {
+        job: manifest.id.clone(),
+        video_id,
+        title: metadata.title.clone(),
+        time: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs()),
+        used,
+        picked: manifest.thumbnail_picked.is_some(),
+        variants: manifest.thumbnail_variants.clone(),
+    };
+    if let Err(e) = thumbnail::record_thumbnail(ctx, record).await {
+        log::error!("Unable to record which thumbnail was used: {}", e);
+    }
+}
+
+#[inline]
+async fn restart_if_too_short(job: &Job, res: crate::Result) -> crate::Result {
+    if let Err(crate::Error::TooShort) = res {
+        // nothing we do later will fix this, so get some new content next time
+        job.restart().await?;
+    }
+    res
+}
+
+#[inline]
+async fn prepare_project(job: &Job, ctx: Arc<Context>) -> crate::Result {
+    // tts: read out the text for each frame
+    if !job.is_complete(Stage::Tts).await {
+        let JobManifest {
+            frames,
+            scraped_from,
+            ..
+        } = job.manifest().await;
+        let settings = ctx.config().tts_for(scraped_from.as_deref());
+        let tts = process::synthesize(&frames, settings, ctx.clone()).await?;
+        job.complete(Stage::Tts, move |m| m.tts = tts).await?;
+    }
+
+    // overlays: render the text overlays and work out the length of each frame
+    if !job.is_complete(Stage::Overlays).await {
+        let JobManifest { frames, tts, .. } = job.manifest().await;
+        let converted = process::render_overlays(&frames, &tts, ctx.clone()).await?;
+        job.complete(Stage::Overlays, move |m| m.converted = converted)
+            .await?;
+    }
+
+    // project: lay everything out in an MLT file
+    if !job.is_complete(Stage::Project).await {
+        let JobManifest {
+            converted,
+            scraped_from,
+            ..
+        } = job.manifest().await;
+        let project = process::build_project(converted, scraped_from, ctx.clone()).await?;
+        job.complete(Stage::Project, move |m| m.project = Some(project))
+            .await?;
+    }
+
+    Ok(())
+}
+
+#[inline]
+async fn render_project(job: &Job, ctx: &Context) -> crate::Result {
+    // render: run the project through the configured backend
+    if !job.is_complete(Stage::Render).await {
+        let project = job
+            .manifest()
+            .await
+            .project
+            .ok_or_else(|| missing("the project"))?;
+        let backend = ctx.config().render_backend.backend();
+        log::info!("Rendering with {}", backend.name());
+        let (progress, updates) = watch::channel(None);
+        let render = future::or(
+            backend.render(job.dir(), &project, &progress),
+            report_progress(updates),
+        );
+        // giving up on the render drops it, which stops the backend
+        match ctx.config().render_timeout {
+            Some(seconds) => util::timeout(render, seconds).await??,
+            None => render.await?,
+        }
+        job.complete(Stage::Render, move |m| {
+            m.video_path = Some(project.video_path)
+        })
+        .await?;
+    }
+
+    Ok(())
+}
+
+#[inline]
+async fn normalize_loudness(job: &Job, ctx: &Context) -> crate::Result {
+    // loudness: measure everything that went into the video, then bring the video to the target
+    if !job.is_complete(Stage::Loudness).await {
+        let JobManifest {
+            tts,
+            project,
+            video_path,
+            ..
+        } = job.manifest().await;
+        let video_path = video_path.ok_or_else(|| missing("the video"))?;
+
+        let mut tts_loudness = vec![];
+        for audio in &tts {
+            tts_loudness.push(match audio {
+                Some((path, _)) => loudness::integrated(path).await,
+                None => None,
+            });
+        }
+        let mut music = vec![];
+        for path in project.map(|p| p.music_paths).unwrap_or_default() {
+            music.push(loudness::integrated(&path).await);
+        }
+
+        let settings = &ctx.config().loudness;
+        let rendered = loudness::measure(&video_path).await?;
+        log::info!(
+            "The video is {} LUFS with a true peak of {} dBTP",
+            rendered.integrated,
+            rendered.true_peak
+        );
+        let normalized = match settings.needs_normalizing(&rendered) {
+            true => Some(loudness::normalize(&video_path, &rendered, settings).await?),
+            false => None,
+        };
+
+        let report = LoudnessReport {
+            tts: tts_loudness,
+            music,
+            rendered,
+            normalized,
+        };
+        job.complete(Stage::Loudness, move |m| m.loudness = Some(report))
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Log the progress of a render whenever it gets another percent further. Never finishes.
+#[inline]
+async fn report_progress(mut updates: watch::Receiver<Option<Progress>>) -> crate::Result {
+    let mut last_percentage = None;
+    while updates.changed().await.is_ok() {
+        let progress = *updates.borrow();
+        if let Some(progress) = progress {
+            if last_percentage != Some(progress.percentage()) {
+                last_percentage = Some(progress.percentage());
+                log::info!("Rendering: {}", progress);
+            }
+        }
+    }
+    future::pending().await
+}
+
+/// Write the plan for the video next to the MLT project, so both can be reviewed before rendering.
+#[inline]
+async fn write_plan(job: &Job) -> crate::Result<PathBuf> {
+    let JobManifest {
+        frames,
+        converted,
+        project,
+        ..
+    } = job.manifest().await;
+    let project = project.ok_or_else(|| missing("the project"))?;
+    let plan = Plan::new(&frames, &converted, &project);
+
+    let path = job.dir().join(PLAN_NAME);
+    let mut f = File::create(&path).await?;
+    f.write_all(&serde_json::to_vec_pretty(&plan)?).await?;
+    Ok(path)
+}
+
+#[test]
+fn test_job_manifest() {
+    tokio::runtime::Builder::new_current_thread()
+        .build()
+        .expect("Unable to construct Tokio runtime")
+        .block_on(async move {
+            let homedir = std::env::temp_dir()
+                .join(format!("koti-test-jobs{}", tls_rng().generate::<usize>()));
+            fs::create_dir_all(&homedir).await.unwrap();
+
+            let job = Job::create(&homedir, Some("askreddit".to_string()), false)
+                .await
+                .unwrap();
+            let id = job.manifest().await.id;
+            job.complete(Stage::Tts, |m| {
+                m.tts = vec![None, Some((PathBuf::from("tts0.wav"), 2.5))]
+            })
+            .await
+            .unwrap();
+
+            // the completed stages and their outputs survive a reload
+            let job = Job::open(&homedir, &id).await.unwrap();
+            let manifest = job.manifest().await;
+            assert_eq!(manifest.source.as_deref(), Some("askreddit"));
+            assert!(manifest.is_complete(Stage::Tts));
+            assert!(!manifest.is_complete(Stage::Scrape));
+            assert_eq!(manifest.tts[1], Some((PathBuf::from("tts0.wav"), 2.5)));
+
+            job.restart().await.unwrap();
+            let manifest = Job::open(&homedir, &id).await.unwrap().manifest().await;
+            assert!(manifest.completed.is_empty());
+            assert!(manifest.tts.is_empty());
+            assert_eq!(manifest.source.as_deref(), Some("askreddit"));
+
+            Job::open(&homedir, "does-not-exist").await.unwrap_err();
+            fs::remove_dir_all(&homedir).await.unwrap();
+        });
+}
@@ -0,0 +1,193 @@
+/*
+ * This file is part of KOTI.
+ *
+ * KOTI is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * KOTI is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Afero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with KOTI.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Show the narrated text with each word lit up as it's read out.
+
+use crate::{
+    process::tts::normalize,
+    text2image::{draw_glyphs, glyphs_extent, layout_text, load_font},
+};
+use image::{Rgba, RgbaImage};
+use std::path::Path;
+
+/// Length of the windows the audio is split into to find where the speech is, in seconds.
+const WINDOW: f32 = 0.01;
+/// How loud a window has to be, compared to the loudest one, to count as speech.
+const SPEECH_THRESHOLD: f32 = 0.08;
+
+const UNREAD_COLOR: [u8; 3] = [150, 150, 150];
+const READ_COLOR: [u8; 3] = [255, 255, 255];
+const HIGHLIGHT_COLOR: [u8; 3] = [255, 210, 0];
+
+/// Read a WAV file into mono samples between -1 and 1, along with its sample rate.
+#[inline]
+pub fn read_wav(path: &Path) -> crate::Result<(Vec<f32>, u32)> {
+    let mut reader = hound::WavReader::open(path)?;
+    let spec = reader.spec();
+    let samples: Vec<f32> = match spec.sample_format {
+        hound::SampleFormat::Float => reader.samples::<f32>().collect::<Result<_, _>>()?,
+        hound::SampleFormat::Int => {
+            let max = (1u64 << (spec.bits_per_sample - 1)) as f32;
+            reader
+                .samples::<i32>()
+                .map(|s| s.map(|s| s as f32 / max))
+                .collect::<Result<_, _>>()?
+        }
+    };
+
+    // mix the channels together
+    let channels = spec.channels.max(1) as usize;
+    let mono = samples
+        .chunks(channels)
+        .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+        .collect();
+    Ok((mono, spec.sample_rate))
+}
+
+/// Estimate when each word starts and ends, in seconds, from where the speech is in the audio.
+/// Words are spread across the parts of the audio that aren't silent, each taking time in
+/// proportion to how much there is to say, so the pauses between sentences are skipped over.
+#[inline]
+pub fn word_timings(words: &[&str], samples: &[f32], sample_rate: u32) -> Vec<(f32, f32)> {
+    let window = ((sample_rate as f32 * WINDOW) as usize).max(1);
+    let loudness: Vec<f32> = samples
+        .chunks(window)
+        .map(|chunk| (chunk.iter().map(|s| s * s).sum::<f32>() / chunk.len() as f32).sqrt())
+        .collect();
+    let loudest = loudness.iter().copied().fold(0.0, f32::max);
+    let mut speech: Vec<usize> = loudness
+        .iter()
+        .enumerate()
+        .filter(|(_, l)| loudest > 0.0 && **l >= loudest * SPEECH_THRESHOLD)
+        .map(|(i, _)| i)
+        .collect();
+    if speech.is_empty() {
+        // no idea where the speech is, so spread the words over the whole thing
+        speech = (0..loudness.len()).collect();
+    }
+    let window = window as f32 / sample_rate as f32;
+
+    // "$5" takes longer to say than it looks
+    let weights: Vec<usize> = words
+        .iter()
+        .map(|word| {
+            normalize(word)
+                .chars()
+                .filter(|c| c.is_alphanumeric())
+                .count()
+                .max(1)
+        })
+        .collect();
+    let total: usize = weights.iter().sum();
+    let position = |said: usize| -> usize { speech.len() * said / total.max(1) };
+
+    let mut said = 0;
+    weights
+        .iter()
+        .map(|weight| {
+            let (first, last) = (position(said), position(said + weight));
+            said += weight;
+            match speech.is_empty() {
+                true => (0.0, 0.0),
+                false => {
+                    let first = first.min(speech.len() - 1);
+                    let last = last.saturating_sub(1).max(first);
+                    (
+                        speech[first] as f32 * window,
+                        (speech[last] + 1) as f32 * window,
+                    )
+                }
+            }
+        })
+        .collect()
+}
+
+/// Draw the words in the middle of an image the size of the video, with the words before
+/// `highlight` already read, and the word at `highlight` being read. If `highlight` is `None`,
+/// every word has been read.
+#[inline]
+pub async fn karaoke_image(
+    words: &[&str],
+    highlight: Option<usize>,
+    width: u32,
+    height: u32,
+) -> crate::Result<RgbaImage> {
+    let font = load_font().await?;
+    let text = words.join(" ");
+    let max_width = width * 9 / 10;
+    let max_height = height * 9 / 10;
+
+    // shrink the text until it fits
+    let mut font_size = height as f32 / 14.0;
+    let mut glyphs = layout_text(&text, font_size, max_width, font)?;
+    while glyphs_extent(&glyphs).1 > max_height && font_size > 12.0 {
+        font_size *= 0.85;
+        glyphs = layout_text(&text, font_size, max_width, font)?;
+    }
+    let (text_width, text_height) = glyphs_extent(&glyphs);
+    let x = (width.saturating_sub(text_width) / 2) as i32;
+    let y = (height.saturating_sub(text_height) / 2) as i32;
+
+    // there's a glyph for each character of each word
+    let mut img = RgbaImage::from_pixel(width, height, Rgba([0, 0, 0, 255]));
+    let mut rest = &glyphs[..];
+    for (i, word) in words.iter().enumerate() {
+        let (word_glyphs, after) = rest.split_at(word.chars().count().min(rest.len()));
+        rest = after;
+        let color = match highlight {
+            Some(highlight) if i == highlight => HIGHLIGHT_COLOR,
+            Some(highlight) if i > highlight => UNREAD_COLOR,
+            _ => READ_COLOR,
+        };
+        draw_glyphs(&mut img, word_glyphs, x, y, color);
+    }
+
+    Ok(img)
+}
+
+#[test]
+fn test_word_timings() {
+    // half a second of speech, half a second of silence, then another half second of speech
+    let rate = 1000;
+    let tone = |n: usize| (0..n).map(|i| (i as f32 / 3.0).sin() * 0.5);
+    let samples: Vec<f32> = tone(500)
+        .chain(std::iter::repeat(0.0).take(500))
+        .chain(tone(500))
+        .collect();
+
+    let timings = word_timings(&["first", "third"], &samples, rate);
+    let close = |a: f32, b: f32| (a - b).abs() < 0.02;
+    assert!(
+        close(timings[0].0, 0.0) && close(timings[0].1, 0.5),
+        "{:?}",
+        timings
+    );
+    assert!(
+        close(timings[1].0, 1.0) && close(timings[1].1, 1.5),
+        "{:?}",
+        timings
+    );
+
+    // longer words get more time
+    let timings = word_timings(&["a", "$1,000"], &samples, rate);
+    assert!(timings[1].1 - timings[1].0 > 3.0 * (timings[0].1 - timings[0].0));
+
+    // silence doesn't break anything
+    let timings = word_timings(&["quiet"], &[0.0; 100], rate);
+    assert!(close(timings[0].0, 0.0) && close(timings[0].1, 0.1));
+    assert_eq!(word_timings(&["nothing"], &[], rate), vec![(0.0, 0.0)]);
+}
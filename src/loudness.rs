@@ -0,0 +1,319 @@
+/*
+ * This file is part of KOTI.
+ *
+ * KOTI is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * KOTI is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Afero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with KOTI.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Measure loudness as described by EBU R128, and bring rendered videos to a target loudness,
+//! using ffmpeg's `loudnorm` filter.
+
+use std::{
+    path::{Path, PathBuf},
+    process::Stdio,
+};
+use tokio::{fs, process::Command};
+
+/// The loudness range `loudnorm` aims for, unless the audio already has a wider one.
+const TARGET_RANGE: f32 = 11.0;
+
+/// How loud rendered videos should be.
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoudnessSettings {
+    /// Integrated loudness to bring videos to, in LUFS, or `None` to only measure them.
+    pub target: Option<f32>,
+    /// Highest the true peak can be, in dBTP.
+    pub true_peak: f32,
+    /// How far from the target a video can be before it's normalized, in LU.
+    pub tolerance: f32,
+}
+
+impl Default for LoudnessSettings {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            target: Some(-14.0),
+            true_peak: -1.0,
+            tolerance: 1.0,
+        }
+    }
+}
+
+impl LoudnessSettings {
+    #[inline]
+    pub fn validate(&self) -> crate::Result {
+        let invalid = |msg: String| Err(crate::Error::InvalidConfig(msg));
+
+        if let Some(target) = self.target {
+            if !target.is_finite() || !(-70.0..=-5.0).contains(&target) {
+                return invalid(format!(
+                    "loudness target {} LUFS is outside of the range -70 LUFS to -5 LUFS",
+                    target
+                ));
+            }
+        }
+        if !self.true_peak.is_finite() || !(-9.0..=0.0).contains(&self.true_peak) {
+            return invalid(format!(
+                "true peak {} dBTP is outside of the range -9 dBTP to 0 dBTP",
+                self.true_peak
+            ));
+        }
+        if !self.tolerance.is_finite() || !(0.0..=10.0).contains(&self.tolerance) {
+            return invalid(format!(
+                "loudness tolerance {} LU is outside of the range 0 LU to 10 LU",
+                self.tolerance
+            ));
+        }
+        Ok(())
+    }
+
+    /// Whether audio this loud needs to be normalized.
+    #[inline]
+    pub fn needs_normalizing(&self, loudness: &Loudness) -> bool {
+        match self.target {
+            Some(target) => {
+                (loudness.integrated - target).abs() > self.tolerance
+                    || loudness.true_peak > self.true_peak
+            }
+            None => false,
+        }
+    }
+}
+
+/// How loud something is.
+#[derive(Debug, Copy, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct Loudness {
+    /// Integrated loudness, in LUFS.
+    pub integrated: f32,
+    /// True peak, in dBTP.
+    pub true_peak: f32,
+    /// Loudness range, in LU.
+    pub range: f32,
+    /// The gating threshold, in LUFS.
+    pub threshold: f32,
+}
+
+/// Loudness measurements for a video and what went into it, saved in the job manifest.
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct LoudnessReport {
+    /// Integrated loudness of each frame's narration in LUFS, or `None` if there was nothing to
+    /// measure.
+    pub tts: Vec<Option<f32>>,
+    /// Integrated loudness of each music track, in LUFS.
+    pub music: Vec<Option<f32>>,
+    /// The video as it was rendered.
+    pub rendered: Loudness,
+    /// The video after it was normalized, or `None` if it didn't need to be.
+    pub normalized: Option<Loudness>,
+}
+
+/// Measure how loud the first audio stream of a file is.
+#[inline]
+pub async fn measure(path: &Path) -> crate::Result<Loudness> {
+    let output = Command::new("ffmpeg")
+        .args(&["-hide_banner", "-nostats", "-i"])
+        .arg(path)
+        .args(&[
+            "-map",
+            "0:a:0",
+            "-af",
+            "loudnorm=print_format=json",
+            "-f",
+            "null",
+            "-",
+        ])
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .output()
+        .await?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !output.status.success() {
+        return Err(crate::Error::ProbeFailed(
+            path.to_path_buf(),
+            stderr.trim().to_string(),
+        ));
+    }
+
+    parse_loudnorm(path, &stderr)
+}
+
+/// Measure how loud a file is, or `None` if it can't be measured.
+#[inline]
+pub async fn integrated(path: &Path) -> Option<f32> {
+    match measure(path).await {
+        Ok(loudness) => Some(loudness.integrated),
+        Err(e) => {
+            log::warn!("Unable to measure the loudness of {:?}: {}", path, e);
+            None
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct LoudnormOutput {
+    input_i: String,
+    input_tp: String,
+    input_lra: String,
+    input_thresh: String,
+}
+
+/// Read the measurements `loudnorm` prints at the end of its output.
+#[inline]
+fn parse_loudnorm(path: &Path, stderr: &str) -> crate::Result<Loudness> {
+    let failed = |msg: String| crate::Error::ProbeFailed(path.to_path_buf(), msg);
+    let json = match (stderr.rfind('{'), stderr.rfind('}')) {
+        (Some(start), Some(end)) if start < end => &stderr[start..=end],
+        _ => return Err(failed("loudnorm didn't print its measurements".to_string())),
+    };
+    let output: LoudnormOutput =
+        serde_json::from_str(json).map_err(|e| failed(format!("bad loudnorm output: {}", e)))?;
+
+    // silence measures as -inf, which can't be saved in the manifest
+    let value = |value: &str| {
+        value
+            .trim()
+            .parse::<f32>()
+            .ok()
+            .filter(|v| v.is_finite())
+            .ok_or_else(|| failed("it's too quiet or too short to measure".to_string()))
+    };
+    Ok(Loudness {
+        integrated: value(&output.input_i)?,
+        true_peak: value(&output.input_tp)?,
+        range: value(&output.input_lra)?,
+        threshold: value(&output.input_thresh)?,
+    })
+}
+
+/// The `loudnorm` filter that brings audio with the measured loudness to the target, changing
+/// its volume without compressing it when it can.
+#[inline]
+fn loudnorm_filter(target: f32, true_peak: f32, measured: &Loudness) -> String {
+    format!(
+        "loudnorm=I={}:TP={}:LRA={}:measured_I={}:measured_TP={}:measured_LRA={}:measured_thresh={}:linear=true:print_format=none",
+        target,
+        true_peak,
+        measured.range.max(TARGET_RANGE).min(50.0),
+        measured.integrated,
+        measured.true_peak,
+        measured.range,
+        measured.threshold
+    )
+}
+
+/// Bring a rendered video to the target loudness, replacing it, and measure it again.
+#[inline]
+pub async fn normalize(
+    video: &Path,
+    measured: &Loudness,
+    settings: &LoudnessSettings,
+) -> crate::Result<Loudness> {
+    let target = settings
+        .target
+        .ok_or(crate::Error::StaticMsg("No loudness target is set"))?;
+    let mut name = video.file_name().unwrap_or_default().to_os_string();
+    name.push(".normalized.webm");
+    let normalized: PathBuf = video.with_file_name(name);
+
+    log::info!(
+        "Normalizing {:?} from {} LUFS to {} LUFS",
+        video,
+        measured.integrated,
+        target
+    );
+    let output = Command::new("ffmpeg")
+        .args(&["-y", "-hide_banner", "-nostats", "-v", "error", "-i"])
+        .arg(video)
+        .args(&["-map", "0", "-c:v", "copy", "-af"])
+        .arg(loudnorm_filter(target, settings.true_peak, measured))
+        // loudnorm works at 192kHz, which opus can't take
+        .args(&["-ar", "48000", "-c:a", "libopus"])
+        .arg(&normalized)
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .output()
+        .await?;
+    if !output.status.success() {
+        let _ = fs::remove_file(&normalized).await;
+        return Err(crate::Error::RenderFailed(
+            "ffmpeg",
+            output.status.code(),
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+
+    fs::rename(&normalized, video).await?;
+    measure(video).await
+}
+
+#[test]
+fn test_parse_loudnorm() {
+    let path = Path::new("koti.webm");
+    let stderr = r#"Input #0, matroska,webm, from 'koti.webm':
+  Duration: 00:10:02.03, start: 0.000000, bitrate: 1204 kb/s
+size=N/A time=00:10:02.02 bitrate=N/A speed= 412x
+[Parsed_loudnorm_0 @ 0x55d1c2a4b8c0]
+{
+	"input_i" : "-22.43",
+	"input_tp" : "-3.10",
+	"input_lra" : "7.60",
+	"input_thresh" : "-32.61",
+	"output_i" : "-24.05",
+	"output_tp" : "-4.72",
+	"output_lra" : "6.90",
+	"output_thresh" : "-34.17",
+	"normalization_type" : "dynamic",
+	"target_offset" : "0.05"
+}
+"#;
+    let loudness = parse_loudnorm(path, stderr).unwrap();
+    assert_eq!(
+        loudness,
+        Loudness {
+            integrated: -22.43,
+            true_peak: -3.1,
+            range: 7.6,
+            threshold: -32.61,
+        }
+    );
+
+    let settings = LoudnessSettings::default();
+    assert!(settings.needs_normalizing(&loudness));
+    assert!(!settings.needs_normalizing(&Loudness {
+        integrated: -14.5,
+        true_peak: -1.5,
+        ..loudness
+    }));
+    // too close to clipping, even at the right loudness
+    assert!(settings.needs_normalizing(&Loudness {
+        integrated: -14.0,
+        true_peak: -0.2,
+        ..loudness
+    }));
+    assert!(!LoudnessSettings {
+        target: None,
+        ..settings
+    }
+    .needs_normalizing(&loudness));
+
+    assert_eq!(
+        loudnorm_filter(-14.0, -1.0, &loudness),
+        "loudnorm=I=-14:TP=-1:LRA=11:measured_I=-22.43:measured_TP=-3.1:measured_LRA=7.6:measured_thresh=-32.61:linear=true:print_format=none"
+    );
+
+    // silence can't be measured
+    let silent = stderr.replace("\"-22.43\"", "\"-inf\"");
+    parse_loudnorm(path, &silent).unwrap_err();
+    parse_loudnorm(path, "ffmpeg version n4.4").unwrap_err();
+}
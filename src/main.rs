@@ -15,16 +15,25 @@
  * along with KOTI.  If not, see <https://www.gnu.org/licenses/>.
  */
 
+pub mod captions;
+pub mod card;
+pub mod config;
 pub mod context;
 mod error;
 pub mod filter;
 pub mod frame;
+pub mod frame_source;
 pub mod image_size;
+pub mod job;
+pub mod karaoke;
+pub mod loudness;
 pub mod mlt;
 pub mod music;
+mod probe;
 mod process;
 mod reddit_text_source;
-//mod scp;
+pub mod render;
+mod scp;
 pub mod text2image;
 mod thumbnail;
 pub mod util;
@@ -33,15 +42,11 @@ mod youtube;
 pub use error::*;
 pub use frame::*;
 
-use clap::{App, Arg, SubCommand};
+use clap::{App, Arg, ArgMatches, SubCommand};
 use context::Context;
-use futures_lite::stream::{self, Stream, StreamExt};
-use nanorand::{tls_rng, RNG};
 use std::{
     env,
-    future::Future,
     path::{Path, PathBuf},
-    pin::Pin,
     process::exit,
     str::FromStr,
     sync::Arc,
@@ -49,116 +54,245 @@ use std::{
 use tokio::io::{self, AsyncReadExt, AsyncWriteExt};
 
 const THREAD_COUNT: usize = 4;
+const SAMPLE_THUMBNAIL_TEXT: &str =
+    "What's the most unsettling thing you've ever found in your house?";
 
-macro_rules! reddit_text_source {
-    ($sub: expr, $ut: expr, $ct: expr, $rt: expr, $net: expr) => {{
-        |cx| {
-            async fn inner(
-                sub: &str,
-                ut: i64,
-                ct: i64,
-                rt: i64,
-                net: &str,
-                cx: Arc<Context>,
-            ) -> crate::Result {
-                let f = reddit_text_source::reddit_text_source(sub, ut, ct, rt, net, &cx).await?;
-                println!("Created reddit text stream source");
-
-                process::process(f, cx).await
-            }
+/// Changes to a music track given on the command line. Fields that are `None` are left alone.
+#[derive(Debug, Default)]
+struct TrackChanges {
+    name: Option<String>,
+    path: Option<PathBuf>,
+    attribution: bool,
+    mood: Option<String>,
+    bpm: Option<u32>,
+    license: Option<String>,
+    sources: Option<Vec<String>>,
+    weight: Option<u32>,
+}
+
+impl TrackChanges {
+    #[inline]
+    fn from_matches(matches: &ArgMatches<'_>) -> crate::Result<Self> {
+        let number = |name: &str| {
+            matches
+                .value_of(name)
+                .map(|n| u32::from_str(n).map_err(|_| crate::Error::NumParseError))
+                .transpose()
+        };
+        Ok(Self {
+            name: matches.value_of("name").map(str::to_string),
+            path: matches.value_of_os("path").map(PathBuf::from),
+            attribution: matches.is_present("attribution"),
+            mood: matches.value_of("mood").map(str::to_string),
+            bpm: number("bpm")?,
+            license: matches.value_of("license").map(str::to_string),
+            sources: matches
+                .values_of("source")
+                .map(|sources| sources.map(str::to_string).collect()),
+            weight: number("weight")?,
+        })
+    }
 
-            Box::pin(inner($sub, $ut, $ct, $rt, $net, cx))
+    /// Apply the tags, where an empty string or a BPM of zero clears a tag.
+    #[inline]
+    fn apply_tags(self, entry: &mut music::MusicEntry) {
+        let text = |value: String| Some(value).filter(|v| !v.is_empty());
+        if let Some(mood) = self.mood {
+            entry.mood = text(mood);
+        }
+        if let Some(bpm) = self.bpm {
+            entry.bpm = Some(bpm).filter(|bpm| *bpm > 0);
+        }
+        if let Some(license) = self.license {
+            entry.license = text(license);
+        }
+        if let Some(sources) = self.sources {
+            entry.sources = sources.into_iter().filter(|s| !s.is_empty()).collect();
+        }
+        if let Some(weight) = self.weight {
+            entry.weight = weight;
         }
-    }};
+    }
 }
 
-const FRAME_SOURCES: &[fn(
-    Arc<Context>,
-) -> Pin<Box<dyn Future<Output = crate::Result> + Send + 'static>>] = &[
-    reddit_text_source!("AskReddit", 500, 200, 100, "day"),
-    //    reddit_text_source!("TalesFromTechSupport", 500, 100, 100, "week"),
-    //    reddit_text_source!("StoresAboutKevin", 250, 50, 50, "week"),
-    //    reddit_text_source!("NoSleep", 750, 200, 100, "week"),
-];
-
 #[inline]
-async fn create_video(homedir: PathBuf, datadir: PathBuf, upload: bool) -> crate::Result {
-    // create the context
-    let ctx = Arc::new(context::Context::default());
+async fn music_context(datadir: PathBuf) -> crate::Result<Context> {
+    let ctx = context::Context::default();
+    tokio::fs::create_dir_all(&datadir).await?;
+    ctx.set_datadir(datadir).await;
+    Ok(ctx)
+}
 
-    // create a base dir to use
-    let basedirname = tls_rng().generate::<usize>();
-    let basedir = homedir.join(format!("koti{}", basedirname));
-    log::info!("Setting up shop at {:?}", &basedir);
+#[inline]
+async fn read_attribution() -> crate::Result<String> {
+    let mut cout = io::stdout();
+    let mut cin = io::stdin();
+    let mut attribution = String::new();
 
-    // create the directory
-    tokio::fs::create_dir_all(&basedir).await?;
+    cout.write_all(b"Write the attribution for the music below:\n")
+        .await?;
+    cin.read_to_string(&mut attribution).await?;
+    Ok(attribution)
+}
 
-    ctx.set_basedir(basedir).await;
-    ctx.set_datadir(datadir).await;
+#[inline]
+async fn add_music_track(
+    datadir: PathBuf,
+    name: String,
+    musicpath: PathBuf,
+    changes: TrackChanges,
+) -> crate::Result {
+    let ctx = music_context(datadir).await?;
 
-    // create a guard that deletes the base directory on exit
-    //    struct DeleteTheBasedirOnExit(Arc<Context>);
+    let mut entry = music::MusicEntry::new(name, musicpath, String::new());
+    entry.verify().await?;
+    let mut m = music::Music::load(&ctx).await?;
+    if m.tracks().iter().any(|e| e.name == entry.name) {
+        return Err(crate::Error::Msg(format!(
+            "There is already a music track named {}",
+            &entry.name
+        )));
+    }
 
-    //    let _guard = DeleteTheBasedirOnExit(ctx.clone());
+    entry.attribution = read_attribution().await?;
+    changes.apply_tags(&mut entry);
+    m.add_track(entry)?;
+    m.save(&ctx).await?;
 
-    // select a random element from the array
-    let frame_source = FRAME_SOURCES[tls_rng().generate_range::<usize>(0, FRAME_SOURCES.len())];
+    io::stdout().write_all(b"Saved!\n").await?;
 
-    // spawn two tasks: one for creating the thumbnail and one for creating the video proper
-    let ctx_clone = ctx.clone();
-    let ctx_clone2 = ctx.clone();
-    let t1 = tokio::spawn(async move { frame_source(ctx_clone).await });
-    let t2 = tokio::spawn(async move {
-        let ctx = ctx_clone2;
-        thumbnail::create_thumbnail(ctx).await
-    });
+    Ok(())
+}
 
-    let (t1, t2) = futures_lite::future::zip(t1, t2).await;
-    t1??;
-    t2??;
-
-    // now that we have a video and a thumbnail, upload to YouTube
-    if upload {
-        youtube::upload_to_youtube(&ctx).await?
-    } else {
-        let viddir = dirs::video_dir().unwrap();
-        let vidpath = viddir.join(format!("koti{}.webm", basedirname));
-        tokio::fs::rename(ctx.take_video_path().await, &vidpath).await?;
-        log::info!("Moved video to {:?}", &vidpath);
-        let thumbpath = viddir.join(format!("koti{}.png", basedirname));
-        tokio::fs::rename(ctx.take_thumbnail_path().await, &thumbpath).await?;
-        log::info!("Moved thumbnail to {:?}", &thumbpath);
+#[inline]
+async fn list_music(datadir: PathBuf) -> crate::Result {
+    let ctx = music_context(datadir).await?;
+    let m = music::Music::load(&ctx).await?;
+    if m.tracks().is_empty() {
+        println!("There are no music tracks; add one with `koti music add`");
     }
 
-    tokio::fs::remove_dir_all(ctx.basedir().await).await?;
+    for entry in m.tracks() {
+        let mut tags = vec![];
+        if let Some(ref mood) = entry.mood {
+            tags.push(format!("mood: {}", mood));
+        }
+        if let Some(bpm) = entry.bpm {
+            tags.push(format!("{} BPM", bpm));
+        }
+        if let Some(ref license) = entry.license {
+            tags.push(format!("license: {}", license));
+        }
+        if !entry.sources.is_empty() {
+            tags.push(format!("sources: {}", entry.sources.join(", ")));
+        }
+        tags.push(format!("weight: {}", entry.weight));
+
+        println!("{}\t{:?}\t{}", &entry.name, &entry.path, tags.join("; "));
+    }
 
     Ok(())
 }
 
 #[inline]
-async fn add_music_track(datadir: PathBuf, name: String, musicpath: PathBuf) -> crate::Result {
-    let ctx = context::Context::default();
-    tokio::fs::create_dir_all(&datadir).await?;
-    ctx.set_datadir(datadir).await;
-
-    let mut cout = io::stdout();
-    let mut cin = io::stdin();
-    let mut attribution = String::new();
+async fn remove_music_track(datadir: PathBuf, name: String) -> crate::Result {
+    let ctx = music_context(datadir).await?;
+    let mut m = music::Music::load(&ctx).await?;
+    m.remove_track(&name)
+        .ok_or_else(|| crate::Error::Msg(format!("There is no music track named {}", &name)))?;
+    m.save(&ctx).await?;
 
-    cout.write_all(b"Write the attribution for the music below:\n")
-        .await?;
-    cin.read_to_string(&mut attribution).await?;
+    println!("Removed {}", &name);
+    Ok(())
+}
 
+#[inline]
+async fn edit_music_track(
+    datadir: PathBuf,
+    name: String,
+    mut changes: TrackChanges,
+) -> crate::Result {
+    let ctx = music_context(datadir).await?;
     let mut m = music::Music::load(&ctx).await?;
-    m.add_track(name, musicpath, attribution);
-    m.save(&ctx).await?;
+    let missing = || crate::Error::Msg(format!("There is no music track named {}", &name));
 
-    cout.write_all(b"Saved!\n").await?;
+    let entry = m.track_mut(&name).ok_or_else(missing)?;
+    if let Some(path) = changes.path.take() {
+        let mut moved = entry.clone();
+        moved.path = path;
+        moved.verify().await?;
+        *entry = moved;
+    }
+    if changes.attribution {
+        entry.attribution = read_attribution().await?;
+    }
+    let new_name = changes.name.take();
+    changes.apply_tags(entry);
+    if let Some(new_name) = new_name {
+        m.rename_track(&name, new_name)?;
+    }
+    m.save(&ctx).await?;
 
+    println!("Saved!");
     Ok(())
 }
 
+/// Make sure every music track exists and can be decoded.
+#[inline]
+async fn verify_music(datadir: PathBuf) -> crate::Result {
+    let ctx = music_context(datadir).await?;
+    let m = music::Music::load(&ctx).await?;
+
+    let mut failed = 0;
+    for entry in m.tracks() {
+        match entry.verify().await {
+            Ok(()) => println!("ok\t{}", &entry.name),
+            Err(e) => {
+                println!("FAILED\t{}: {}", &entry.name, e);
+                failed += 1;
+            }
+        }
+    }
+
+    match failed {
+        0 => Ok(()),
+        failed => Err(crate::Error::Msg(format!(
+            "{} of {} music tracks can't be used",
+            failed,
+            m.tracks().len()
+        ))),
+    }
+}
+
+/// Options for tagging a music track, shared by `music add` and `music edit`.
+#[inline]
+fn music_tag_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("mood")
+            .long("mood")
+            .value_name("MOOD")
+            .help("The mood of the track, e.g. upbeat"),
+        Arg::with_name("bpm")
+            .long("bpm")
+            .value_name("BPM")
+            .help("Beats per minute"),
+        Arg::with_name("license")
+            .long("license")
+            .value_name("LICENSE")
+            .help("License the track is used under, e.g. CC BY 4.0"),
+        Arg::with_name("source")
+            .long("source")
+            .value_name("SOURCE_NAME")
+            .multiple(true)
+            .number_of_values(1)
+            .help("Only use the track for videos from this frame source (repeatable)"),
+        Arg::with_name("weight")
+            .long("weight")
+            .value_name("WEIGHT")
+            .help("How likely the track is to be picked (default 1, 0 never picks it)"),
+    ]
+}
+
 #[inline]
 async fn add_thumbnail(
     datadir: PathBuf,
@@ -180,6 +314,119 @@ async fn add_thumbnail(
     Ok(())
 }
 
+#[inline]
+async fn list_thumbnails(datadir: PathBuf) -> crate::Result {
+    let ctx = context::Context::default();
+    ctx.set_datadir(datadir).await;
+
+    let thumbnails = thumbnail::load_thumbnails(&ctx).await?;
+    if thumbnails.is_empty() {
+        println!("There are no thumbnail templates; add one with `koti thumbnail add`");
+    }
+    for template in &thumbnails {
+        print!("{}\t{:?}", template.id(), template.path());
+        match template.validate().await {
+            Ok(()) => println!(),
+            Err(e) => println!("\tINVALID: {}", e),
+        }
+        for layer in template.describe_layers() {
+            println!("\t{}", layer);
+        }
+    }
+
+    Ok(())
+}
+
+#[inline]
+async fn remove_thumbnail(datadir: PathBuf, id: String) -> crate::Result {
+    let ctx = context::Context::default();
+    ctx.set_datadir(datadir).await;
+
+    thumbnail::remove_thumbnail(&ctx, &id).await?;
+    println!("Removed {}", &id);
+    Ok(())
+}
+
+/// List the thumbnail variants made for a job, or pick the one to upload.
+#[inline]
+async fn pick_thumbnail(homedir: PathBuf, id: String, variant: Option<String>) -> crate::Result {
+    let job = job::Job::open(&homedir, &id).await?;
+    match variant {
+        Some(name) => {
+            let variant = job.pick_thumbnail(&name).await?;
+            println!(
+                "Job {} will use thumbnail {} ({}); continue it with: koti resume {}",
+                &id,
+                &variant.name,
+                variant.describe(),
+                &id
+            );
+        }
+        None => {
+            let manifest = job.manifest().await;
+            if manifest.thumbnail_variants.is_empty() {
+                println!("Job {} hasn't made its thumbnails yet", &id);
+            }
+            for variant in &manifest.thumbnail_variants {
+                let using = manifest.thumbnail_path.as_ref() == Some(&variant.path);
+                println!(
+                    "{}{}\t{:?}\t{}",
+                    if using { "*" } else { " " },
+                    &variant.name,
+                    &variant.path,
+                    variant.describe()
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// List which thumbnail each video went out with.
+#[inline]
+async fn thumbnail_history(datadir: PathBuf) -> crate::Result {
+    let ctx = context::Context::default();
+    ctx.set_datadir(datadir).await;
+
+    for record in thumbnail::load_history(&ctx).await? {
+        println!(
+            "{}\t{}\t{}{} of {}\t{}\t{}",
+            record.time,
+            record.video_id.as_deref().unwrap_or("(not uploaded)"),
+            &record.used,
+            if record.picked { " (picked)" } else { "" },
+            record.variants.len(),
+            record
+                .used_variant()
+                .map_or_else(String::new, |v| v.describe()),
+            &record.title
+        );
+    }
+    Ok(())
+}
+
+#[inline]
+async fn auto_thumbnail(content: thumbnail::ThumbnailContent, path: PathBuf) -> crate::Result {
+    let path = thumbnail::auto_thumbnail(&content, [255, 255, 255], path).await?;
+    println!("Saved an automatic thumbnail to {:?}", path);
+    Ok(())
+}
+
+#[inline]
+async fn preview_thumbnail(
+    datadir: PathBuf,
+    id: String,
+    content: thumbnail::ThumbnailContent,
+    path: PathBuf,
+) -> crate::Result {
+    let ctx = context::Context::default();
+    ctx.set_datadir(datadir).await;
+
+    let path = thumbnail::preview_thumbnail(&ctx, &id, content, path).await?;
+    println!("Saved a preview of {} to {:?}", &id, path);
+    Ok(())
+}
+
 #[inline]
 async fn draw_text_image(txt: String, path: PathBuf) -> crate::Result {
     let (img, _, _) =
@@ -188,6 +435,19 @@ async fn draw_text_image(txt: String, path: PathBuf) -> crate::Result {
     Ok(())
 }
 
+#[inline]
+async fn draw_card(txt: String, path: PathBuf, theme: card::Theme) -> crate::Result {
+    let c = card::Card {
+        author: "u/koti".to_string(),
+        score: Some(1234),
+        age: Some(60 * 60 * 5),
+        depth: 1,
+        body: txt,
+    };
+    card::save_card(&c, 1536, theme, path).await?;
+    Ok(())
+}
+
 fn main() {
     // sets up the logging framework
     env_logger::init();
@@ -225,12 +485,43 @@ fn main() {
                 .takes_value(false)
                 .help("Upload to youtube?"),
         )
+        .arg(
+            Arg::with_name("source")
+                .short("s")
+                .long("source")
+                .value_name("SOURCE_NAME")
+                .help("Name of the frame source to use, instead of picking one at random")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("dry-run")
+                .long("dry-run")
+                .takes_value(false)
+                .help(
+                    "Stop before rendering and write out the MLT project and a plan of the video",
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("plan")
+                .about("same as --dry-run: plans out a video without rendering it"),
+        )
+        .subcommand(
+            SubCommand::with_name("resume")
+                .about("continues a job that failed partway through")
+                .arg(
+                    Arg::with_name("job")
+                        .index(1)
+                        .value_name("JOB_ID")
+                        .required(true)
+                        .help("ID of the job, e.g. koti1234"),
+                ),
+        )
         .subcommand(
             SubCommand::with_name("music")
                 .about("adds or removes music tracks to be selected in video")
                 .subcommand(
                     SubCommand::with_name("add")
-                        .about("adds a music track")
+                        .about("adds a music track, reading its attribution from stdin")
                         .arg(
                             Arg::with_name("trackname")
                                 .index(1)
@@ -244,37 +535,176 @@ fn main() {
                                 .index(2)
                                 .required(true)
                                 .help("Path to the track"),
+                        )
+                        .args(&music_tag_args()),
+                )
+                .subcommand(SubCommand::with_name("list").about("lists the music tracks"))
+                .subcommand(
+                    SubCommand::with_name("remove")
+                        .about("removes a music track")
+                        .arg(
+                            Arg::with_name("trackname")
+                                .index(1)
+                                .required(true)
+                                .value_name("TRACK_NAME"),
                         ),
+                )
+                .subcommand(
+                    SubCommand::with_name("edit")
+                        .about("changes a music track; empty tags clear them")
+                        .arg(
+                            Arg::with_name("trackname")
+                                .index(1)
+                                .required(true)
+                                .value_name("TRACK_NAME"),
+                        )
+                        .arg(
+                            Arg::with_name("name")
+                                .long("name")
+                                .value_name("NEW_NAME")
+                                .help("Rename the track"),
+                        )
+                        .arg(
+                            Arg::with_name("path")
+                                .long("path")
+                                .value_name("PATH")
+                                .help("Move the track to another file"),
+                        )
+                        .arg(
+                            Arg::with_name("attribution")
+                                .long("attribution")
+                                .takes_value(false)
+                                .help("Read a new attribution from stdin"),
+                        )
+                        .args(&music_tag_args()),
+                )
+                .subcommand(
+                    SubCommand::with_name("verify")
+                        .about("makes sure every music track exists and can be decoded"),
                 ),
         )
         .subcommand(
             SubCommand::with_name("thumbnail")
-                .about("adds or removes thumbnail templates")
-                .arg(
-                    Arg::with_name("id")
-                        .index(1)
-                        .required(true)
-                        .value_name("THUMBNAIL_ID"),
+                .about("manages thumbnail templates and the thumbnails made for videos")
+                .subcommand(
+                    SubCommand::with_name("add")
+                        .about("adds a thumbnail template, with text drawn in the given rectangle")
+                        .arg(
+                            Arg::with_name("id")
+                                .index(1)
+                                .required(true)
+                                .value_name("THUMBNAIL_ID"),
+                        )
+                        .arg(
+                            Arg::with_name("path")
+                                .index(2)
+                                .required(true)
+                                .value_name("BASE_IMAGE_PATH"),
+                        )
+                        .arg(Arg::with_name("x").index(3).required(true).value_name("X"))
+                        .arg(Arg::with_name("y").index(4).required(true).value_name("Y"))
+                        .arg(
+                            Arg::with_name("w")
+                                .index(5)
+                                .required(true)
+                                .value_name("WIDTH"),
+                        )
+                        .arg(
+                            Arg::with_name("h")
+                                .index(6)
+                                .required(true)
+                                .value_name("HEIGHT"),
+                        ),
                 )
-                .arg(
-                    Arg::with_name("path")
-                        .index(2)
-                        .required(true)
-                        .value_name("BASE_IMAGE_PATH"),
+                .subcommand(
+                    SubCommand::with_name("list")
+                        .about("lists the thumbnail templates and checks that they're usable"),
                 )
-                .arg(Arg::with_name("x").index(3).required(true).value_name("X"))
-                .arg(Arg::with_name("y").index(4).required(true).value_name("Y"))
-                .arg(
-                    Arg::with_name("w")
-                        .index(5)
-                        .required(true)
-                        .value_name("WIDTH"),
+                .subcommand(
+                    SubCommand::with_name("remove")
+                        .about("removes a thumbnail template")
+                        .arg(
+                            Arg::with_name("id")
+                                .index(1)
+                                .required(true)
+                                .value_name("THUMBNAIL_ID"),
+                        ),
                 )
-                .arg(
-                    Arg::with_name("h")
-                        .index(6)
-                        .required(true)
-                        .value_name("HEIGHT"),
+                .subcommand(
+                    SubCommand::with_name("pick")
+                        .about("lists the thumbnails made for a job, or picks the one to upload")
+                        .arg(
+                            Arg::with_name("job")
+                                .index(1)
+                                .required(true)
+                                .value_name("JOB_ID"),
+                        )
+                        .arg(Arg::with_name("variant").index(2).value_name("VARIANT")),
+                )
+                .subcommand(
+                    SubCommand::with_name("history")
+                        .about("lists which thumbnail each video went out with"),
+                )
+                .subcommand(
+                    SubCommand::with_name("auto")
+                        .about("makes a thumbnail without a template, as is done when a video's template is missing")
+                        .arg(
+                            Arg::with_name("path")
+                                .index(1)
+                                .required(true)
+                                .value_name("PNG_PATH"),
+                        )
+                        .arg(
+                            Arg::with_name("frames")
+                                .index(2)
+                                .multiple(true)
+                                .value_name("IMAGE_PATH")
+                                .help("Pictures from the video to pick the background from, in order"),
+                        )
+                        .arg(
+                            Arg::with_name("text")
+                                .long("text")
+                                .value_name("TEXT")
+                                .help("Text to draw, instead of the sample text"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("preview")
+                        .about("draws sample text on a thumbnail template")
+                        .arg(
+                            Arg::with_name("id")
+                                .index(1)
+                                .required(true)
+                                .value_name("THUMBNAIL_ID"),
+                        )
+                        .arg(
+                            Arg::with_name("path")
+                                .index(2)
+                                .required(true)
+                                .value_name("PNG_PATH"),
+                        )
+                        .arg(
+                            Arg::with_name("text")
+                                .long("text")
+                                .value_name("TEXT")
+                                .help("Text to draw, instead of the sample text"),
+                        )
+                        .arg(
+                            Arg::with_name("field")
+                                .long("field")
+                                .value_name("NAME=VALUE")
+                                .multiple(true)
+                                .number_of_values(1)
+                                .help("Value for a text field, e.g. subreddit=AskReddit (repeatable)"),
+                        )
+                        .arg(
+                            Arg::with_name("image")
+                                .long("image")
+                                .value_name("SLOT=PATH")
+                                .multiple(true)
+                                .number_of_values(1)
+                                .help("Picture for an image slot, e.g. frame=screenshot.png (repeatable)"),
+                        ),
                 ),
         )
         .subcommand(
@@ -293,6 +723,28 @@ fn main() {
                         .required(true),
                 ),
         )
+        .subcommand(
+            SubCommand::with_name("card")
+                .about("debug feature to render a comment card")
+                .arg(
+                    Arg::with_name("path")
+                        .index(1)
+                        .value_name("PATH")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("text")
+                        .index(2)
+                        .value_name("TEXT")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("light")
+                        .long("light")
+                        .takes_value(false)
+                        .help("Use the light theme"),
+                ),
+        )
         .subcommand(
             SubCommand::with_name("ytoken")
                 .about("Set the YouTube API token")
@@ -345,17 +797,46 @@ fn main() {
 
             // add a music track if need be
             if let Some(matches) = matches.subcommand_matches("music") {
-                if let Some(matches) = matches.subcommand_matches("add") {
-                    let trackname = matches.value_of("trackname").unwrap().to_string();
-                    let trackpath: PathBuf = matches.value_of_os("trackpath").unwrap().into();
-                    match tokio::spawn(add_music_track(datadir, trackname, trackpath)).await {
-                        Ok(Ok(())) => (),
-                        Err(e) => log::error!("A panick occurred: {:?}", e),
-                        Ok(Err(e)) => log::error!("Unable to save music track: {:?}", e),
+                let task = match matches.subcommand() {
+                    ("add", Some(matches)) => {
+                        let trackname = matches.value_of("trackname").unwrap().to_string();
+                        let trackpath: PathBuf = matches.value_of_os("trackpath").unwrap().into();
+                        TrackChanges::from_matches(matches).map(|changes| {
+                            tokio::spawn(add_music_track(datadir, trackname, trackpath, changes))
+                        })
+                    }
+                    ("list", Some(_)) => Ok(tokio::spawn(list_music(datadir))),
+                    ("remove", Some(matches)) => {
+                        let trackname = matches.value_of("trackname").unwrap().to_string();
+                        Ok(tokio::spawn(remove_music_track(datadir, trackname)))
+                    }
+                    ("edit", Some(matches)) => {
+                        let trackname = matches.value_of("trackname").unwrap().to_string();
+                        TrackChanges::from_matches(matches).map(|changes| {
+                            tokio::spawn(edit_music_track(datadir, trackname, changes))
+                        })
                     }
+                    ("verify", Some(_)) => Ok(tokio::spawn(verify_music(datadir))),
+                    _ => {
+                        eprintln!("{}", matches.usage());
+                        exit(1);
+                    }
+                };
 
-                    return;
+                let res = match task {
+                    Ok(task) => task.await,
+                    Err(e) => Ok(Err(e)),
+                };
+                match res {
+                    Ok(Ok(())) => (),
+                    Err(e) => log::error!("A panick occurred: {:?}", e),
+                    Ok(Err(e)) => {
+                        log::error!("Unable to manage music: {}", e);
+                        exit(1);
+                    }
                 }
+
+                return;
             } else if let Some(matches) = matches.subcommand_matches("imagetext") {
                 let path: PathBuf = matches.value_of_os("path").unwrap().into();
                 let text = matches.value_of("text").unwrap().to_string();
@@ -366,20 +847,103 @@ fn main() {
                 }
 
                 return;
-            } else if let Some(matches) = matches.subcommand_matches("thumbnail") {
-                let id = matches.value_of("id").unwrap().to_string();
+            } else if let Some(matches) = matches.subcommand_matches("card") {
                 let path: PathBuf = matches.value_of_os("path").unwrap().into();
-                let x = u32::from_str(matches.value_of("x").unwrap()).expect("X isn't a number");
-                let y = u32::from_str(matches.value_of("y").unwrap()).expect("Y isn't a number");
-                let w =
-                    u32::from_str(matches.value_of("w").unwrap()).expect("Width isn't a number");
-                let h =
-                    u32::from_str(matches.value_of("h").unwrap()).expect("Height isn't a number");
-
-                match tokio::spawn(add_thumbnail(datadir, id, path, x, y, w, h)).await {
+                let text = matches.value_of("text").unwrap().to_string();
+                let theme = if matches.is_present("light") {
+                    card::Theme::Light
+                } else {
+                    card::Theme::Dark
+                };
+                match tokio::spawn(draw_card(text, path, theme)).await {
                     Ok(Ok(())) => (),
                     Err(e) => log::error!("Panicked: {:?}", e),
-                    Ok(Err(e)) => log::error!("Unable to add thumbnail: {:?}", e),
+                    Ok(Err(e)) => log::error!("Unable to draw card: {:?}", e),
+                }
+
+                return;
+            } else if let Some(matches) = matches.subcommand_matches("thumbnail") {
+                let task = match matches.subcommand() {
+                    ("add", Some(matches)) => {
+                        let id = matches.value_of("id").unwrap().to_string();
+                        let path: PathBuf = matches.value_of_os("path").unwrap().into();
+                        let x = u32::from_str(matches.value_of("x").unwrap())
+                            .expect("X isn't a number");
+                        let y = u32::from_str(matches.value_of("y").unwrap())
+                            .expect("Y isn't a number");
+                        let w = u32::from_str(matches.value_of("w").unwrap())
+                            .expect("Width isn't a number");
+                        let h = u32::from_str(matches.value_of("h").unwrap())
+                            .expect("Height isn't a number");
+                        tokio::spawn(add_thumbnail(datadir, id, path, x, y, w, h))
+                    }
+                    ("list", Some(_)) => tokio::spawn(list_thumbnails(datadir)),
+                    ("remove", Some(matches)) => {
+                        let id = matches.value_of("id").unwrap().to_string();
+                        tokio::spawn(remove_thumbnail(datadir, id))
+                    }
+                    ("pick", Some(matches)) => {
+                        let id = matches.value_of("job").unwrap().to_string();
+                        let variant = matches.value_of("variant").map(str::to_string);
+                        tokio::spawn(pick_thumbnail(path.clone(), id, variant))
+                    }
+                    ("history", Some(_)) => tokio::spawn(thumbnail_history(datadir)),
+                    ("auto", Some(matches)) => {
+                        let path: PathBuf = matches.value_of_os("path").unwrap().into();
+                        let text = matches
+                            .value_of("text")
+                            .unwrap_or(SAMPLE_THUMBNAIL_TEXT)
+                            .to_string();
+                        let mut content = thumbnail::ThumbnailContent::new(text);
+                        content.frames = matches
+                            .values_of_os("frames")
+                            .into_iter()
+                            .flatten()
+                            .map(PathBuf::from)
+                            .collect();
+                        tokio::spawn(auto_thumbnail(content, path))
+                    }
+                    ("preview", Some(matches)) => {
+                        let id = matches.value_of("id").unwrap().to_string();
+                        let path: PathBuf = matches.value_of_os("path").unwrap().into();
+                        let text = matches
+                            .value_of("text")
+                            .unwrap_or(SAMPLE_THUMBNAIL_TEXT)
+                            .to_string();
+                        let mut content = thumbnail::ThumbnailContent::new(text);
+                        let pairs = |name: &str| {
+                            matches.values_of(name).into_iter().flatten().map(|pair| {
+                                let mut split = pair.splitn(2, '=');
+                                match (split.next(), split.next()) {
+                                    (Some(key), Some(value)) => {
+                                        (key.to_string(), value.to_string())
+                                    }
+                                    _ => {
+                                        eprintln!("Expected NAME=VALUE, got {}", pair);
+                                        exit(1);
+                                    }
+                                }
+                            })
+                        };
+                        content.fields.extend(pairs("field"));
+                        content
+                            .images
+                            .extend(pairs("image").map(|(slot, path)| (slot, path.into())));
+                        tokio::spawn(preview_thumbnail(datadir, id, content, path))
+                    }
+                    _ => {
+                        eprintln!("{}", matches.usage());
+                        exit(1);
+                    }
+                };
+
+                match task.await {
+                    Ok(Ok(())) => (),
+                    Err(e) => log::error!("Panicked: {:?}", e),
+                    Ok(Err(e)) => {
+                        log::error!("Unable to manage thumbnails: {}", e);
+                        exit(1);
+                    }
                 }
 
                 return;
@@ -421,12 +985,13 @@ fn main() {
                             ctx,
                             vidpath,
                             thumbpath,
+                            None,
                             "Test".to_string(),
                             "Test".to_string(),
                         ))
                         .await
                         {
-                            Ok(Ok(())) => (),
+                            Ok(Ok(video_id)) => log::info!("Uploaded video {}", video_id),
                             Err(e) => log::error!("Panicked: {:?}", e),
                             Ok(Err(e)) => log::error!("Unable to upload video: {:?}", e),
                         }
@@ -436,14 +1001,37 @@ fn main() {
                 return;
             }
 
+            // either pick up an old job or start a new one
+            let job = match matches.subcommand_matches("resume") {
+                Some(matches) => job::Job::open(&path, matches.value_of("job").unwrap()).await,
+                None => {
+                    job::Job::create(
+                        &path,
+                        matches.value_of("source").map(str::to_string),
+                        !matches.is_present("no-upload"),
+                    )
+                    .await
+                }
+            };
+            let job = match job {
+                Ok(job) => Arc::new(job),
+                Err(e) => {
+                    log::error!("Unable to set up job: {:?}", e);
+                    exit(1);
+                }
+            };
+
+            let dry_run =
+                matches.is_present("dry-run") || matches.subcommand_matches("plan").is_some();
+
             // try to create a video
             local
                 .run_until(async move {
                     for i in 0..10 {
-                        match tokio::task::spawn_local(create_video(
-                            path.clone(),
+                        match tokio::task::spawn_local(job::run(
+                            job.clone(),
                             datadir.clone(),
-                            !matches.is_present("no-upload"),
+                            dry_run,
                         ))
                         .await
                         {
@@ -456,10 +1044,13 @@ fn main() {
                                 log::error!("A fatal error occurred: {:?}", e);
                                 match i {
                                     i @ 9 => {
+                                        let id = job.manifest().await.id;
                                         log::error!(
-                                            "Tried to run program {} times and failed; stopping...",
+                                            "Tried to run job {} {} times and failed; stopping...",
+                                            &id,
                                             i
                                         );
+                                        log::error!("Continue it later with: koti resume {}", id);
                                         exit(1);
                                     }
                                     i => log::error!("Retrying for the {}nth time", i + 1),
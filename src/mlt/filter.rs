@@ -15,11 +15,11 @@
  * along with KOTI.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use crate::util::cow_str_into_bytes;
-use quick_xml::events::{attributes::Attribute, BytesEnd, BytesStart, BytesText, Event};
-use std::{array::IntoIter as ArrayIter, borrow::Cow, collections::HashMap, iter};
+use super::property_events;
+use quick_xml::events::{BytesEnd, BytesStart, Event};
+use std::{borrow::Cow, collections::HashMap, iter};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Filter {
     name: Cow<'static, str>,
     properties: HashMap<Cow<'static, str>, Cow<'static, str>>,
@@ -44,39 +44,31 @@ impl Filter {
         self
     }
 
+    #[inline]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    #[inline]
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.properties.get(key).map(|value| value.as_ref())
+    }
+
     #[inline]
     pub fn into_events(self) -> impl Iterator<Item = Event<'static>> {
         let Self { name, properties } = self;
 
         let opener = BytesStart::borrowed_name(b"filter");
         let closer = BytesEnd::borrowed(b"filter");
-        let mlt_service_opener = BytesStart::borrowed_name(b"property")
-            .with_attributes(iter::once(("name", "mlt_service")));
-        let mlt_service_closer = BytesEnd::borrowed(b"property");
-        let mlt_service_text = BytesText::from_escaped_str(name);
 
         // chain the iterator
-        ArrayIter::new([
-            Event::Start(opener),
-            Event::Start(mlt_service_opener),
-            Event::Text(mlt_service_text),
-            Event::End(mlt_service_closer),
-        ])
-        .chain(properties.into_iter().flat_map(|(propkey, propvalue)| {
-            let propopener =
-                BytesStart::borrowed_name(b"property").with_attributes(iter::once(Attribute {
-                    key: b"name",
-                    value: cow_str_into_bytes(propkey),
-                }));
-            let proptext = BytesText::from_escaped_str(propvalue);
-            let propcloser = BytesEnd::borrowed(b"property");
-
-            ArrayIter::new([
-                Event::Start(propopener),
-                Event::Text(proptext),
-                Event::End(propcloser),
-            ])
-        }))
-        .chain(iter::once(Event::End(closer)))
+        iter::once(Event::Start(opener))
+            .chain(property_events("mlt_service", name))
+            .chain(
+                properties
+                    .into_iter()
+                    .flat_map(|(propkey, propvalue)| property_events(propkey, propvalue)),
+            )
+            .chain(iter::once(Event::End(closer)))
     }
 }
@@ -0,0 +1,78 @@
+/*
+ * This file is part of KOTI.
+ *
+ * KOTI is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * KOTI is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Afero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with KOTI.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::fmt;
+
+macro_rules! id_type {
+    ($(#[$meta: meta])* $name: ident, $variant: ident, $prefix: literal) => {
+        $(#[$meta])*
+        #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+        pub struct $name(pub(super) usize);
+
+        impl fmt::Display for $name {
+            #[inline]
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, concat!($prefix, "{}"), self.0)
+            }
+        }
+
+        impl From<$name> for ServiceId {
+            #[inline]
+            fn from(id: $name) -> ServiceId {
+                ServiceId::$variant(id)
+            }
+        }
+    };
+}
+
+id_type!(
+    /// ID of a producer in the timeline.
+    ProducerId,
+    Producer,
+    "producer"
+);
+id_type!(
+    /// ID of a playlist in the timeline.
+    PlaylistId,
+    Playlist,
+    "playlist"
+);
+id_type!(
+    /// ID of a tractor in the timeline.
+    TractorId,
+    Tractor,
+    "tractor"
+);
+
+/// ID of anything that can be used as a playlist entry or a track.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ServiceId {
+    Producer(ProducerId),
+    Playlist(PlaylistId),
+    Tractor(TractorId),
+}
+
+impl fmt::Display for ServiceId {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Producer(id) => fmt::Display::fmt(id, f),
+            Self::Playlist(id) => fmt::Display::fmt(id, f),
+            Self::Tractor(id) => fmt::Display::fmt(id, f),
+        }
+    }
+}
@@ -16,30 +16,36 @@
  */
 
 mod filter;
+mod id;
+mod parse;
 mod playlist;
 mod producer;
 mod tractor;
 mod transition;
 
 pub use filter::Filter;
+pub use id::{PlaylistId, ProducerId, ServiceId, TractorId};
+pub use playlist::Playlist;
+pub use producer::Producer;
+pub use tractor::Tractor;
 pub use transition::Transition;
 
 use crate::process::FPS;
-use playlist::Playlist;
-use producer::Producer;
 use quick_xml::{
-    events::{attributes::Attribute, BytesDecl, BytesEnd, BytesStart, Event},
+    events::{attributes::Attribute, BytesDecl, BytesEnd, BytesStart, BytesText, Event},
     Writer,
 };
 use std::{
     array::IntoIter as ArrayIter,
-    io::BufWriter,
+    borrow::Cow,
+    collections::HashMap,
     iter, mem,
     path::{Path, PathBuf},
-    process::Stdio,
 };
-use tokio::{fs::File, process::Command};
-use tractor::Tractor;
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncWriteExt},
+};
 
 #[doc(hidden)]
 #[macro_export]
@@ -51,105 +57,261 @@ macro_rules! s {
 
 const FRAME_RATE_DEN: usize = 1001;
 
-/// Manager for the MLT xml file.
-pub struct Mlt<'a> {
-    basedir: &'a Path,
-    events: Vec<Event<'static>>,
+/// Typed model of an MLT project.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mlt {
+    basedir: PathBuf,
+    elements: Vec<Element>,
     video_size: (usize, usize),
+    producer_count: usize,
+    playlist_count: usize,
+    tractor_count: usize,
+}
+
+/// Something defined at the top level of the project. Elements can only refer to elements that
+/// come before them.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Element {
+    Producer(Producer),
+    Playlist(Playlist),
+    Tractor(Tractor),
 }
 
+#[derive(Debug, Clone, PartialEq)]
 pub enum PlaylistEntry {
     Blank(usize),
     Producer {
-        id: String,
+        id: ServiceId,
         start: usize,
         end: usize,
     },
 }
 
-impl<'a> Mlt<'a> {
+impl Mlt {
     #[inline]
-    pub fn new(basedir: &'a Path, video_width: usize, video_height: usize) -> Self {
+    pub fn new(basedir: &Path, video_width: usize, video_height: usize) -> Self {
         Self {
-            basedir,
-            events: vec![],
+            basedir: basedir.to_path_buf(),
+            elements: vec![],
             video_size: (video_width, video_height),
+            producer_count: 0,
+            playlist_count: 0,
+            tractor_count: 0,
         }
     }
 
-    /// Returns the ID
     #[inline]
-    pub fn add_producer(&mut self, resource: PathBuf) -> String {
-        let prod = Producer::new(resource);
-        let id = prod.id().to_string();
-        self.events.extend(prod.into_events());
+    pub fn basedir(&self) -> &Path {
+        &self.basedir
+    }
+
+    #[inline]
+    pub fn video_size(&self) -> (usize, usize) {
+        self.video_size
+    }
+
+    #[inline]
+    pub fn elements(&self) -> &[Element] {
+        &self.elements
+    }
+
+    #[inline]
+    fn next_producer_id(&mut self) -> ProducerId {
+        self.producer_count += 1;
+        ProducerId(self.producer_count - 1)
+    }
+
+    #[inline]
+    fn next_playlist_id(&mut self) -> PlaylistId {
+        self.playlist_count += 1;
+        PlaylistId(self.playlist_count - 1)
+    }
+
+    #[inline]
+    fn next_tractor_id(&mut self) -> TractorId {
+        self.tractor_count += 1;
+        TractorId(self.tractor_count - 1)
+    }
+
+    #[inline]
+    pub fn add_producer(&mut self, resource: PathBuf) -> ProducerId {
+        self.add_producer_with_length(resource, None)
+    }
+
+    /// Add a producer for a resource that has a known length, in frames.
+    #[inline]
+    pub fn add_producer_with_length(
+        &mut self,
+        resource: PathBuf,
+        length: Option<usize>,
+    ) -> ProducerId {
+        let id = self.next_producer_id();
+        self.elements
+            .push(Element::Producer(Producer::new(id, resource, length)));
         id
     }
 
-    /// Returns the ID
     #[inline]
-    pub fn add_playlist<I, J>(&mut self, producers: I, filters: J) -> String
+    pub fn add_playlist<I, J>(&mut self, producers: I, filters: J) -> PlaylistId
     where
         I: IntoIterator<Item = PlaylistEntry>,
         J: IntoIterator<Item = Filter>,
     {
-        let mut playlist = Playlist::new();
-        producers.into_iter().for_each(|p| match p {
-            PlaylistEntry::Blank(b) => playlist.push_blank(b),
-            PlaylistEntry::Producer { id, start, end } => playlist.push_entry(id, start, end),
-        });
+        let mut playlist = Playlist::new(self.next_playlist_id());
+        producers.into_iter().for_each(|p| playlist.push(p));
         filters.into_iter().for_each(|f| playlist.push_filter(f));
-        let id = playlist.id().to_string();
+        let id = playlist.id();
 
-        self.events.extend(playlist.into_events());
+        self.elements.push(Element::Playlist(playlist));
         id
     }
 
     #[inline]
-    pub fn add_tractor<I, J>(&mut self, tracks: I, filters: J) -> String
+    pub fn add_tractor<I, J>(&mut self, tracks: I, filters: J) -> TractorId
     where
-        I: IntoIterator<Item = String>,
+        I: IntoIterator,
+        I::Item: Into<ServiceId>,
         J: IntoIterator<Item = Filter>,
     {
         self.add_tractor_with_transitions(tracks, filters, iter::empty())
     }
 
-    /// Returns the ID
     #[inline]
     pub fn add_tractor_with_transitions<I, J, T>(
         &mut self,
         tracks: I,
         filters: J,
         transitions: T,
-    ) -> String
+    ) -> TractorId
     where
-        I: IntoIterator<Item = String>,
+        I: IntoIterator,
+        I::Item: Into<ServiceId>,
         J: IntoIterator<Item = Filter>,
         T: IntoIterator<Item = Transition>,
     {
-        let mut tractor = Tractor::new();
-        tracks.into_iter().for_each(|t| tractor.add_track(t));
+        let mut tractor = Tractor::new(self.next_tractor_id());
+        tracks.into_iter().for_each(|t| tractor.add_track(t.into()));
         filters.into_iter().for_each(|f| tractor.add_filter(f));
         transitions
             .into_iter()
             .for_each(|t| tractor.add_transition(t));
 
-        let id = tractor.id().to_string();
+        let id = tractor.id();
 
-        self.events.extend(tractor.into_events());
+        self.elements.push(Element::Tractor(tractor));
         id
     }
 
+    /// Make sure every ID refers to something defined earlier in the project, and that playlist
+    /// entries and transitions fit within what they refer to. Returns the length of the main
+    /// tractor in frames, if it is known.
+    #[inline]
+    pub fn validate(&self, main_tractor: TractorId) -> crate::Result<Option<usize>> {
+        let invalid = |msg: String| Err(crate::Error::InvalidTimeline(msg));
+
+        // length of each element defined so far, in frames
+        let mut lengths: HashMap<ServiceId, Option<usize>> = HashMap::new();
+        let lookup = |lengths: &HashMap<ServiceId, Option<usize>>,
+                      owner: &dyn std::fmt::Display,
+                      id: ServiceId| {
+            lengths.get(&id).copied().ok_or_else(|| {
+                crate::Error::InvalidTimeline(format!(
+                    "{} refers to {}, which isn't defined before it",
+                    owner, id
+                ))
+            })
+        };
+
+        for element in &self.elements {
+            let (id, length) = match element {
+                Element::Producer(producer) => (producer.id().into(), producer.length()),
+                Element::Playlist(playlist) => {
+                    let mut total = 0;
+                    for entry in playlist.entries() {
+                        match *entry {
+                            PlaylistEntry::Blank(length) => total += length,
+                            PlaylistEntry::Producer { id, start, end } => {
+                                let length = lookup(&lengths, &playlist.id(), id)?;
+                                if start > end {
+                                    return invalid(format!(
+                                        "{} plays {} from {} to {}, which is backwards",
+                                        playlist.id(),
+                                        id,
+                                        start,
+                                        end
+                                    ));
+                                }
+                                // out points are inclusive, but melt clamps an out point that
+                                // is one past the end
+                                match length {
+                                    Some(length) if end > length => {
+                                        return invalid(format!(
+                                            "{} plays {} up to frame {}, but it is only {} frames long",
+                                            playlist.id(),
+                                            id,
+                                            end,
+                                            length
+                                        ));
+                                    }
+                                    _ => total += end - start + 1,
+                                }
+                            }
+                        }
+                    }
+                    (playlist.id().into(), Some(total))
+                }
+                Element::Tractor(tractor) => {
+                    let tracks = tractor
+                        .tracks()
+                        .iter()
+                        .map(|&track| lookup(&lengths, &tractor.id(), track))
+                        .collect::<crate::Result<Vec<_>>>()?;
+                    for transition in tractor.transitions() {
+                        let (a_track, b_track) = transition.tracks();
+                        let (start, end) = transition.range();
+                        if a_track >= tracks.len() || b_track >= tracks.len() {
+                            return invalid(format!(
+                                "{} has a {} transition between tracks {} and {}, but only {} tracks",
+                                tractor.id(),
+                                transition.name(),
+                                a_track,
+                                b_track,
+                                tracks.len()
+                            ));
+                        }
+                        if start > end {
+                            return invalid(format!(
+                                "{} has a {} transition from {} to {}, which is backwards",
+                                tractor.id(),
+                                transition.name(),
+                                start,
+                                end
+                            ));
+                        }
+                    }
+                    (tractor.id().into(), tracks.into_iter().flatten().max())
+                }
+            };
+
+            if lengths.insert(id, length).is_some() {
+                return invalid(format!("{} is defined more than once", id));
+            }
+        }
+
+        lookup(&lengths, &"The project", main_tractor.into())
+    }
+
     #[inline]
     pub fn into_events(
         self,
-        main_tractor: String,
+        main_tractor: TractorId,
         duration: usize,
     ) -> (PathBuf, impl Iterator<Item = Event<'static>>) {
         let Self {
-            events,
+            elements,
             basedir,
             video_size: (video_width, video_height),
+            ..
         } = self;
         let videopath = basedir.join("koti.webm");
 
@@ -161,17 +323,17 @@ impl<'a> Mlt<'a> {
             },
             Attribute {
                 key: b"producer".as_ref(),
-                value: main_tractor.into_bytes().into(),
+                value: main_tractor.to_string().into_bytes().into(),
             },
             Attribute {
                 key: b"root".as_ref(),
-                value: path_to_utf8(basedir).to_string().into_bytes().into(),
+                value: escaped_value(path_to_utf8(&basedir)),
             },
         ]));
         let closer = BytesEnd::borrowed(b"mlt");
 
         // width/height/fps profile
-        let (frame_rate_num) = (FPS * (FRAME_RATE_DEN as f32)).ceil() as usize;
+        let frame_rate_num = (FPS * (FRAME_RATE_DEN as f32)).ceil() as usize;
         let (video_width, video_height) = (video_width.to_string(), video_height.to_string());
         let (frame_rate_num, frame_rate_den) =
             (frame_rate_num.to_string(), FRAME_RATE_DEN.to_string());
@@ -203,7 +365,7 @@ impl<'a> Mlt<'a> {
             },
             Attribute {
                 key: b"target".as_ref(),
-                value: path_to_utf8(&videopath).to_string().into_bytes().into(),
+                value: escaped_value(path_to_utf8(&videopath)),
             },
             Attribute {
                 key: b"in".as_ref(),
@@ -219,6 +381,17 @@ impl<'a> Mlt<'a> {
             },
         ]));
 
+        let elements =
+            elements
+                .into_iter()
+                .flat_map(|element| -> Box<dyn Iterator<Item = Event<'static>>> {
+                    match element {
+                        Element::Producer(producer) => Box::new(producer.into_events()),
+                        Element::Playlist(playlist) => Box::new(playlist.into_events()),
+                        Element::Tractor(tractor) => Box::new(tractor.into_events()),
+                    }
+                });
+
         (
             videopath,
             ArrayIter::new([
@@ -227,59 +400,84 @@ impl<'a> Mlt<'a> {
                 Event::Empty(profile),
                 Event::Empty(consumer),
             ])
-            .chain(events.into_iter())
+            .chain(elements)
             .chain(iter::once(Event::End(closer))),
         )
     }
 
+    /// Validate the project and serialize it into XML.
+    #[inline]
+    pub fn to_xml(self, main_tractor: TractorId, duration: usize) -> crate::Result<Vec<u8>> {
+        self.validate(main_tractor)?;
+
+        let (_, mut events) = self.into_events(main_tractor, duration);
+        let mut writer = Writer::new(vec![]);
+        events.try_for_each(|event| writer.write_event(event))?;
+        Ok(writer.into_inner())
+    }
+
+    /// Parse a project back out of its XML, along with its main tractor and its duration.
+    #[inline]
+    pub fn from_xml(data: &[u8]) -> crate::Result<(Self, TractorId, usize)> {
+        parse::parse(data)
+    }
+
+    /// Validate the project and save it as `project.mlt`, returning the path to the project and
+    /// the path the video will be rendered to.
     #[inline]
     pub async fn save(
         self,
-        main_tractor: String,
+        main_tractor: TractorId,
         duration: usize,
     ) -> crate::Result<(PathBuf, PathBuf)> {
         let outpath = self.basedir.join("project.mlt");
-        // collect the events into a vec
-        let (videopath, events) = self.into_events(main_tractor, duration);
+        let videopath = self.basedir.join("koti.webm");
 
-        // create the xml writer and write the events
-        let file = File::create(&outpath).await?;
-        let file = BufWriter::new(file.into_std().await);
-        let mut writer = Writer::new(file);
         log::info!("Writing XML describing video...");
-        tokio::task::spawn_blocking(move || {
-            events
-                .map(move |event| writer.write_event(event).map_err(|e| crate::Error::Xml(e)))
-                .collect::<crate::Result>()
-        })
-        .await??;
+        let data =
+            tokio::task::spawn_blocking(move || self.to_xml(main_tractor, duration)).await??;
+        let mut file = File::create(&outpath).await?;
+        file.write_all(&data).await?;
         Ok((outpath, videopath))
     }
 
+    /// Load a saved project.
     #[inline]
-    pub async fn run(self, main_tractor: String, duration: usize) -> crate::Result<PathBuf> {
-        let basedir = self.basedir;
-        let (outpath, videopath) = self.save(main_tractor, duration).await?;
-
-        // start the melt command with the outpath (xml) as the parameter
-        log::info!("Running melt...");
-        let mut output = Command::new("melt")
-            .current_dir(basedir)
-            .arg(outpath)
-            //            .stdout(Stdio::inherit())
-            //            .stderr(Stdio::inherit())
-            .output()
-            .await?;
-        log::info!("melt has finished!");
-
-        if !output.status.success() {
-            return Err(crate::Error::StaticMsg("Melt failed"));
-        }
+    pub async fn load(path: &Path) -> crate::Result<(Self, TractorId, usize)> {
+        let mut file = File::open(path).await?;
+        let mut data = vec![];
+        file.read_to_end(&mut data).await?;
+        mem::drop(file);
 
-        Ok(videopath)
+        Self::from_xml(&data)
     }
 }
 
+#[inline]
+fn escaped_value(value: &str) -> Cow<'static, [u8]> {
+    quick_xml::escape::escape(value.as_bytes())
+        .into_owned()
+        .into()
+}
+
+#[inline]
+fn property_events<K: Into<Cow<'static, str>>, V: AsRef<str>>(
+    name: K,
+    value: V,
+) -> impl Iterator<Item = Event<'static>> {
+    let property = BytesStart::borrowed_name(b"property").with_attributes(iter::once(Attribute {
+        key: b"name".as_ref(),
+        value: escaped_value(&name.into()),
+    }));
+    let property_end = BytesEnd::borrowed(b"property");
+
+    ArrayIter::new([
+        Event::Start(property),
+        Event::Text(BytesText::from_plain_str(value.as_ref()).into_owned()),
+        Event::End(property_end),
+    ])
+}
+
 /// Convert path to str convenience function.
 #[inline]
 pub fn path_to_utf8(basedir: &Path) -> &str {
@@ -305,3 +503,112 @@ pub fn subpath_to_utf8(basedir: &Path, filename: &str) -> String {
         .into_string()
         .expect("Path is not utf-8?")
 }
+
+#[test]
+fn test_round_trip() {
+    let mut mlt = Mlt::new(Path::new("/tmp/koti & co"), 1920, 1080);
+    let image = mlt.add_producer(PathBuf::from("/tmp/koti & co/card0.png"));
+    let audio = mlt.add_producer_with_length(PathBuf::from("/tmp/koti & co/tts0.wav"), Some(60));
+    let images = mlt.add_playlist(
+        vec![
+            PlaylistEntry::Producer {
+                id: image.into(),
+                start: 0,
+                end: 60,
+            },
+            PlaylistEntry::Blank(30),
+        ],
+        iter::once(Filter::new("resize")),
+    );
+    let sound = mlt.add_playlist(
+        iter::once(PlaylistEntry::Producer {
+            id: audio.into(),
+            start: 0,
+            end: 59,
+        }),
+        iter::once(Filter::new("volume").property("max_gain", "-10dB")),
+    );
+    let main = mlt.add_tractor_with_transitions(
+        ArrayIter::new([images, sound]),
+        iter::empty(),
+        iter::once(Transition::new("mix", 0, 1, 0, 90).property("always_active", "1")),
+    );
+    assert_eq!(mlt.validate(main).unwrap(), Some(91));
+
+    let xml = mlt.clone().to_xml(main, 90).unwrap();
+    let (parsed, parsed_main, duration) = Mlt::from_xml(&xml).unwrap();
+    assert_eq!(parsed, mlt);
+    assert_eq!(parsed_main, main);
+    assert_eq!(duration, 90);
+}
+
+#[test]
+fn test_parse_edited() {
+    let (mlt, main, duration) =
+        Mlt::from_xml(include_bytes!("../../fixtures/mlt/edited.mlt")).unwrap();
+    assert_eq!(mlt.video_size(), (1280, 720));
+    assert_eq!(mlt.basedir(), Path::new("/tmp/koti42"));
+    assert_eq!(duration, 150);
+    assert_eq!(mlt.elements().len(), 5);
+    assert_eq!(mlt.validate(main).unwrap(), Some(151));
+
+    match &mlt.elements()[4] {
+        Element::Tractor(tractor) => {
+            assert_eq!(tractor.id(), main);
+            assert_eq!(tractor.transitions()[0].tracks(), (0, 1));
+            assert_eq!(tractor.transitions()[0].get("always_active"), Some("1"));
+        }
+        e => panic!("expected the main tractor, got {:?}", e),
+    }
+}
+
+#[test]
+fn test_validate() {
+    // an entry can't play more of a producer than there is
+    let mut mlt = Mlt::new(Path::new("/tmp"), 1920, 1080);
+    let audio = mlt.add_producer_with_length(PathBuf::from("tts0.wav"), Some(30));
+    let sound = mlt.add_playlist(
+        iter::once(PlaylistEntry::Producer {
+            id: audio.into(),
+            start: 0,
+            end: 45,
+        }),
+        iter::empty(),
+    );
+    let main = mlt.add_tractor(iter::once(sound), iter::empty());
+    mlt.validate(main).unwrap_err();
+
+    // IDs from a different project don't exist in this one
+    let mut other = Mlt::new(Path::new("/tmp"), 1920, 1080);
+    other.add_producer(PathBuf::from("card0.png"));
+    let stray = other.add_producer(PathBuf::from("card1.png"));
+    let mut mlt = Mlt::new(Path::new("/tmp"), 1920, 1080);
+    let images = mlt.add_playlist(
+        iter::once(PlaylistEntry::Producer {
+            id: stray.into(),
+            start: 0,
+            end: 10,
+        }),
+        iter::empty(),
+    );
+    let main = mlt.add_tractor(iter::once(images), iter::empty());
+    mlt.validate(main).unwrap_err();
+    mlt.validate(TractorId(5)).unwrap_err();
+
+    // transitions have to be between tracks that exist
+    let mut mlt = Mlt::new(Path::new("/tmp"), 1920, 1080);
+    let image = mlt.add_producer(PathBuf::from("card0.png"));
+    let main = mlt.add_tractor_with_transitions(
+        iter::once(image),
+        iter::empty(),
+        iter::once(Transition::new("mix", 0, 1, 0, 10)),
+    );
+    mlt.validate(main).unwrap_err();
+
+    // forward references aren't allowed
+    let data = include_str!("../../fixtures/mlt/edited.mlt").replace(
+        r#"<track producer="pictures"/>"#,
+        r#"<track producer="later"/>"#,
+    );
+    Mlt::from_xml(data.as_bytes()).unwrap_err();
+}
@@ -0,0 +1,348 @@
+/*
+ * This file is part of KOTI.
+ *
+ * KOTI is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * KOTI is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Afero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with KOTI.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Parse an MLT project back into the typed model.
+
+use super::{
+    Element, Filter, Mlt, Playlist, PlaylistEntry, Producer, ServiceId, Tractor, TractorId,
+    Transition,
+};
+use quick_xml::{
+    events::{BytesStart, Event},
+    Reader,
+};
+use std::{collections::HashMap, path::PathBuf, str::FromStr};
+
+#[inline]
+fn invalid<T>(msg: String) -> crate::Result<T> {
+    Err(crate::Error::InvalidTimeline(msg))
+}
+
+#[inline]
+fn number(value: &str, what: &str) -> crate::Result<usize> {
+    usize::from_str(value.trim()).or_else(|_| {
+        invalid(format!(
+            "{} should be a number, but it is \"{}\"",
+            what, value
+        ))
+    })
+}
+
+/// Properties of a filter or transition, collected before we know everything about it.
+#[derive(Default)]
+struct Service {
+    range: Option<(usize, usize)>,
+    properties: Vec<(String, String)>,
+}
+
+impl Service {
+    #[inline]
+    fn take(&mut self, key: &str) -> Option<String> {
+        let index = self.properties.iter().position(|(k, _)| k == key)?;
+        Some(self.properties.remove(index).1)
+    }
+
+    #[inline]
+    fn into_filter(mut self) -> crate::Result<Filter> {
+        let name = match self.take("mlt_service") {
+            Some(name) => name,
+            None => return invalid("filter has no mlt_service".to_string()),
+        };
+        Ok(self
+            .properties
+            .into_iter()
+            .fold(Filter::new(name), |f, (k, v)| f.property(k, v)))
+    }
+
+    #[inline]
+    fn into_transition(mut self) -> crate::Result<Transition> {
+        let name = match self.take("mlt_service") {
+            Some(name) => name,
+            None => return invalid("transition has no mlt_service".to_string()),
+        };
+        let a_track = number(&self.take("a_track").unwrap_or_default(), "a_track")?;
+        let b_track = number(&self.take("b_track").unwrap_or_default(), "b_track")?;
+        let (start, end) = match self.range {
+            Some(range) => range,
+            None => return invalid(format!("{} transition has no in or out", name)),
+        };
+        Ok(self.properties.into_iter().fold(
+            Transition::new(name, a_track, b_track, start, end),
+            |t, (k, v)| t.property(k, v),
+        ))
+    }
+}
+
+enum Current {
+    Nothing,
+    Producer {
+        name: String,
+        resource: Option<PathBuf>,
+        length: Option<usize>,
+    },
+    Playlist(Playlist),
+    Tractor(Tractor),
+}
+
+struct Parser {
+    mlt: Mlt,
+    ids: HashMap<String, ServiceId>,
+    main_tractor: Option<String>,
+    duration: Option<usize>,
+    current: Current,
+    service: Option<Service>,
+    property: Option<(String, String)>,
+}
+
+impl Parser {
+    #[inline]
+    fn lookup(&self, name: &str) -> crate::Result<ServiceId> {
+        match self.ids.get(name) {
+            Some(id) => Ok(*id),
+            None => invalid(format!(
+                "\"{}\" is used before it is defined, or not defined at all",
+                name
+            )),
+        }
+    }
+
+    #[inline]
+    fn define(&mut self, name: String, id: ServiceId) -> crate::Result {
+        if self.ids.insert(name.clone(), id).is_some() {
+            return invalid(format!("\"{}\" is defined more than once", name));
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn open(&mut self, start: &BytesStart<'_>, reader: &Reader<&[u8]>) -> crate::Result {
+        let attrs = start
+            .attributes()
+            .map(|attr| {
+                let attr = attr?;
+                Ok((
+                    String::from_utf8_lossy(attr.key).into_owned(),
+                    attr.unescape_and_decode_value(reader)?,
+                ))
+            })
+            .collect::<crate::Result<HashMap<String, String>>>()?;
+        let attr = |key: &str| match attrs.get(key) {
+            Some(value) => Ok(value.clone()),
+            None => invalid(format!(
+                "<{}> is missing the {} attribute",
+                String::from_utf8_lossy(start.name()),
+                key
+            )),
+        };
+
+        match start.name() {
+            b"mlt" => {
+                self.main_tractor = attrs.get("producer").cloned();
+                if let Some(root) = attrs.get("root") {
+                    self.mlt.basedir = root.into();
+                }
+            }
+            b"profile" => {
+                self.mlt.video_size = (
+                    number(&attr("width")?, "profile width")?,
+                    number(&attr("height")?, "profile height")?,
+                );
+            }
+            b"consumer" => {
+                self.duration = attrs
+                    .get("out")
+                    .map(|out| number(out, "consumer out"))
+                    .transpose()?;
+            }
+            b"producer" => {
+                self.current = Current::Producer {
+                    name: attr("id")?,
+                    resource: None,
+                    length: None,
+                };
+            }
+            b"playlist" => {
+                let id = self.mlt.next_playlist_id();
+                self.define(attr("id")?, id.into())?;
+                self.current = Current::Playlist(Playlist::new(id));
+            }
+            b"tractor" => {
+                let id = self.mlt.next_tractor_id();
+                self.define(attr("id")?, id.into())?;
+                self.current = Current::Tractor(Tractor::new(id));
+            }
+            b"entry" => {
+                let entry = PlaylistEntry::Producer {
+                    id: self.lookup(&attr("producer")?)?,
+                    start: number(&attr("in")?, "entry in")?,
+                    end: number(&attr("out")?, "entry out")?,
+                };
+                match self.current {
+                    Current::Playlist(ref mut playlist) => playlist.push(entry),
+                    _ => return invalid("<entry> outside of a playlist".to_string()),
+                }
+            }
+            b"blank" => {
+                let length = number(&attr("length")?, "blank length")?;
+                match self.current {
+                    Current::Playlist(ref mut playlist) => {
+                        playlist.push(PlaylistEntry::Blank(length))
+                    }
+                    _ => return invalid("<blank> outside of a playlist".to_string()),
+                }
+            }
+            b"track" => {
+                let id = self.lookup(&attr("producer")?)?;
+                match self.current {
+                    Current::Tractor(ref mut tractor) => tractor.add_track(id),
+                    _ => return invalid("<track> outside of a tractor".to_string()),
+                }
+            }
+            b"filter" => self.service = Some(Service::default()),
+            b"transition" => {
+                self.service = Some(Service {
+                    range: Some((
+                        number(&attr("in")?, "transition in")?,
+                        number(&attr("out")?, "transition out")?,
+                    )),
+                    properties: vec![],
+                })
+            }
+            b"property" => self.property = Some((attr("name")?, String::new())),
+            _ => (),
+        }
+
+        Ok(())
+    }
+
+    #[inline]
+    fn close(&mut self, name: &[u8]) -> crate::Result {
+        match name {
+            b"property" => {
+                let (key, value) = match self.property.take() {
+                    Some(property) => property,
+                    None => return Ok(()),
+                };
+
+                match (&mut self.service, &mut self.current) {
+                    (Some(service), _) => service.properties.push((key, value)),
+                    (
+                        None,
+                        Current::Producer {
+                            resource, length, ..
+                        },
+                    ) => match key.as_str() {
+                        "resource" => *resource = Some(value.into()),
+                        "length" => *length = Some(number(&value, "producer length")?),
+                        _ => (),
+                    },
+                    _ => (),
+                }
+            }
+            b"filter" => {
+                let filter = self.service.take().unwrap_or_default().into_filter()?;
+                match self.current {
+                    Current::Playlist(ref mut playlist) => playlist.push_filter(filter),
+                    Current::Tractor(ref mut tractor) => tractor.add_filter(filter),
+                    _ => return invalid("<filter> outside of a playlist or tractor".to_string()),
+                }
+            }
+            b"transition" => {
+                let transition = self.service.take().unwrap_or_default().into_transition()?;
+                match self.current {
+                    Current::Tractor(ref mut tractor) => tractor.add_transition(transition),
+                    _ => return invalid("<transition> outside of a tractor".to_string()),
+                }
+            }
+            b"producer" | b"playlist" | b"tractor" => {
+                let element = match std::mem::replace(&mut self.current, Current::Nothing) {
+                    Current::Producer {
+                        name,
+                        resource,
+                        length,
+                    } => {
+                        let resource = match resource {
+                            Some(resource) => resource,
+                            None => return invalid(format!("{} has no resource", name)),
+                        };
+                        let id = self.mlt.next_producer_id();
+                        self.define(name, id.into())?;
+                        Element::Producer(Producer::new(id, resource, length))
+                    }
+                    Current::Playlist(playlist) => Element::Playlist(playlist),
+                    Current::Tractor(tractor) => Element::Tractor(tractor),
+                    Current::Nothing => return Ok(()),
+                };
+                self.mlt.elements.push(element);
+            }
+            _ => (),
+        }
+
+        Ok(())
+    }
+}
+
+#[inline]
+pub fn parse(data: &[u8]) -> crate::Result<(Mlt, TractorId, usize)> {
+    let mut reader = Reader::from_reader(data);
+    reader.trim_text(true);
+
+    let mut parser = Parser {
+        mlt: Mlt::new(&PathBuf::new(), 0, 0),
+        ids: HashMap::new(),
+        main_tractor: None,
+        duration: None,
+        current: Current::Nothing,
+        service: None,
+        property: None,
+    };
+    let mut buf = vec![];
+
+    loop {
+        match reader.read_event(&mut buf)? {
+            Event::Start(start) => parser.open(&start, &reader)?,
+            Event::Empty(start) => {
+                parser.open(&start, &reader)?;
+                parser.close(start.name())?;
+            }
+            Event::End(end) => parser.close(end.name())?,
+            Event::Text(text) => {
+                if let Some((_, ref mut value)) = parser.property {
+                    value.push_str(&text.unescape_and_decode(&reader)?);
+                }
+            }
+            Event::Eof => break,
+            _ => (),
+        }
+        buf.clear();
+    }
+
+    let main_tractor = match parser.main_tractor {
+        Some(ref name) => match parser.lookup(name)? {
+            ServiceId::Tractor(id) => id,
+            id => return invalid(format!("the main producer {} isn't a tractor", id)),
+        },
+        None => return invalid("the project has no main producer".to_string()),
+    };
+    let duration = match parser.duration {
+        Some(duration) => duration,
+        None => return invalid("the project has no consumer with an out point".to_string()),
+    };
+
+    parser.mlt.validate(main_tractor)?;
+    Ok((parser.mlt, main_tractor, duration))
+}
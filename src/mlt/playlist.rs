@@ -15,46 +15,45 @@
  * along with KOTI.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use super::Filter;
+use super::{Filter, PlaylistEntry, PlaylistId};
 use quick_xml::events::{attributes::Attribute, BytesEnd, BytesStart, Event};
-use std::{
-    array::IntoIter as ArrayIter,
-    iter,
-    sync::atomic::{AtomicUsize, Ordering},
-};
+use std::{array::IntoIter as ArrayIter, iter};
 
-static ID: AtomicUsize = AtomicUsize::new(0);
-
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Playlist {
     entries: Vec<PlaylistEntry>,
     filters: Vec<Filter>,
-    id: String,
+    id: PlaylistId,
 }
 
 impl Playlist {
     #[inline]
-    pub fn new() -> Self {
+    pub fn new(id: PlaylistId) -> Self {
         Self {
             entries: vec![],
             filters: vec![],
-            id: format!("playlist{}", ID.fetch_add(1, Ordering::SeqCst)),
+            id,
         }
     }
 
     #[inline]
-    pub fn id(&self) -> &str {
-        &self.id
+    pub fn id(&self) -> PlaylistId {
+        self.id
     }
 
     #[inline]
-    pub fn push_blank(&mut self, length: usize) {
-        self.entries.push(PlaylistEntry::Blank(length));
+    pub fn entries(&self) -> &[PlaylistEntry] {
+        &self.entries
     }
 
     #[inline]
-    pub fn push_entry(&mut self, id: String, start: usize, end: usize) {
-        self.entries.push(PlaylistEntry::Video { id, start, end });
+    pub fn filters(&self) -> &[Filter] {
+        &self.filters
+    }
+
+    #[inline]
+    pub fn push(&mut self, entry: PlaylistEntry) {
+        self.entries.push(entry);
     }
 
     #[inline]
@@ -72,7 +71,7 @@ impl Playlist {
         let playlist =
             BytesStart::borrowed_name(b"playlist").with_attributes(iter::once(Attribute {
                 key: b"id",
-                value: id.into_bytes().into(),
+                value: id.to_string().into_bytes().into(),
             }));
         let playlist_end = BytesEnd::borrowed(b"playlist");
 
@@ -84,11 +83,11 @@ impl Playlist {
                         value: length.to_string().into_bytes().into(),
                     })),
                 ),
-                PlaylistEntry::Video { id, start, end } => Event::Empty(
+                PlaylistEntry::Producer { id, start, end } => Event::Empty(
                     BytesStart::borrowed_name(b"entry").with_attributes(ArrayIter::new([
                         Attribute {
                             key: b"producer",
-                            value: id.into_bytes().into(),
+                            value: id.to_string().into_bytes().into(),
                         },
                         Attribute {
                             key: b"in",
@@ -105,13 +104,3 @@ impl Playlist {
             .chain(iter::once(Event::End(playlist_end)))
     }
 }
-
-#[derive(Debug)]
-enum PlaylistEntry {
-    Blank(usize),
-    Video {
-        id: String,
-        start: usize,
-        end: usize,
-    },
-}
@@ -15,35 +15,41 @@
  * along with KOTI.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use super::pathbuf_to_utf8;
-use quick_xml::events::{attributes::Attribute, BytesEnd, BytesStart, BytesText, Event};
-use std::{
-    array::IntoIter as ArrayIter,
-    iter,
-    path::PathBuf,
-    sync::atomic::{AtomicUsize, Ordering},
-};
+use super::{path_to_utf8, property_events, ProducerId};
+use quick_xml::events::{attributes::Attribute, BytesEnd, BytesStart, Event};
+use std::{iter, path::PathBuf};
 
-static ID: AtomicUsize = AtomicUsize::new(0);
-
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Producer {
+    id: ProducerId,
     resource: PathBuf,
-    id: String,
+    /// Length of the resource in frames, if it has one.
+    length: Option<usize>,
 }
 
 impl Producer {
     #[inline]
-    pub fn new(resource: PathBuf) -> Self {
+    pub fn new(id: ProducerId, resource: PathBuf, length: Option<usize>) -> Self {
         Self {
+            id,
             resource,
-            id: format!("producer{}", ID.fetch_add(1, Ordering::SeqCst)),
+            length,
         }
     }
 
     #[inline]
-    pub fn id(&self) -> &str {
-        &self.id
+    pub fn id(&self) -> ProducerId {
+        self.id
+    }
+
+    #[inline]
+    pub fn resource(&self) -> &PathBuf {
+        &self.resource
+    }
+
+    #[inline]
+    pub fn length(&self) -> Option<usize> {
+        self.length
     }
 
     #[inline]
@@ -51,21 +57,20 @@ impl Producer {
         let wrapper =
             BytesStart::borrowed_name(b"producer").with_attributes(iter::once(Attribute {
                 key: b"id",
-                value: self.id.clone().into_bytes().into(),
+                value: self.id.to_string().into_bytes().into(),
             }));
-        let property1 = BytesStart::borrowed_name(b"property")
-            .with_attributes(iter::once(("name", "resource")));
         let wrapper_end = BytesEnd::borrowed(b"producer");
-        let property1_end = BytesEnd::borrowed(b"property");
 
-        ArrayIter::new([
-            Event::Start(wrapper),
-            Event::Start(property1),
-            Event::Text(BytesText::from_escaped(
-                pathbuf_to_utf8(self.resource).into_bytes(),
-            )),
-            Event::End(property1_end),
-            Event::End(wrapper_end),
-        ])
+        iter::once(Event::Start(wrapper))
+            .chain(property_events(
+                "resource",
+                path_to_utf8(&self.resource).to_string(),
+            ))
+            .chain(
+                self.length
+                    .into_iter()
+                    .flat_map(|length| property_events("length", length.to_string())),
+            )
+            .chain(iter::once(Event::End(wrapper_end)))
     }
 }
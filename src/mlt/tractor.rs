@@ -15,42 +15,51 @@
  * along with KOTI.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use super::{Filter, Transition};
+use super::{Filter, ServiceId, TractorId, Transition};
 use quick_xml::events::{attributes::Attribute, BytesEnd, BytesStart, Event};
-use std::{
-    array::IntoIter as ArrayIter,
-    iter,
-    sync::atomic::{AtomicUsize, Ordering},
-};
+use std::{array::IntoIter as ArrayIter, iter};
 
-static ID: AtomicUsize = AtomicUsize::new(0);
-
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Tractor {
-    multitrack: Vec<String>,
+    multitrack: Vec<ServiceId>,
     filters: Vec<Filter>,
     transitions: Vec<Transition>,
-    id: String,
+    id: TractorId,
 }
 
 impl Tractor {
     #[inline]
-    pub fn new() -> Self {
+    pub fn new(id: TractorId) -> Self {
         Self {
             multitrack: vec![],
             filters: vec![],
             transitions: vec![],
-            id: format!("tractor{}", ID.fetch_add(1, Ordering::SeqCst)),
+            id,
         }
     }
 
     #[inline]
-    pub fn id(&self) -> &str {
-        &self.id
+    pub fn id(&self) -> TractorId {
+        self.id
+    }
+
+    #[inline]
+    pub fn tracks(&self) -> &[ServiceId] {
+        &self.multitrack
+    }
+
+    #[inline]
+    pub fn filters(&self) -> &[Filter] {
+        &self.filters
+    }
+
+    #[inline]
+    pub fn transitions(&self) -> &[Transition] {
+        &self.transitions
     }
 
     #[inline]
-    pub fn add_track(&mut self, track_id: String) {
+    pub fn add_track(&mut self, track_id: ServiceId) {
         self.multitrack.push(track_id);
     }
 
@@ -74,7 +83,7 @@ impl Tractor {
         } = self;
         let opener = BytesStart::borrowed_name(b"tractor").with_attributes(iter::once(Attribute {
             key: b"id".as_ref(),
-            value: id.into_bytes().into(),
+            value: id.to_string().into_bytes().into(),
         }));
         let closer = BytesEnd::borrowed(b"tractor");
         let mt_opener = BytesStart::borrowed_name(b"multitrack");
@@ -85,7 +94,7 @@ impl Tractor {
                 Event::Empty(
                     BytesStart::borrowed_name(b"track").with_attributes(iter::once(Attribute {
                         key: b"producer".as_ref(),
-                        value: track.into_bytes().into(),
+                        value: track.to_string().into_bytes().into(),
                     })),
                 )
             }))
@@ -15,15 +15,17 @@
  * along with KOTI.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use crate::util::cow_str_into_bytes;
-use quick_xml::events::{attributes::Attribute, BytesEnd, BytesStart, BytesText, Event};
+use super::property_events;
+use quick_xml::events::{attributes::Attribute, BytesEnd, BytesStart, Event};
 use std::{array::IntoIter as ArrayIter, borrow::Cow, collections::HashMap, iter};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Transition {
     name: Cow<'static, str>,
-    a_track: Cow<'static, str>,
-    b_track: Cow<'static, str>,
+    /// Index of the track in the tractor that is being transitioned from.
+    a_track: usize,
+    /// Index of the track in the tractor that is being transitioned to.
+    b_track: usize,
     start: usize,
     end: usize,
     properties: HashMap<Cow<'static, str>, Cow<'static, str>>,
@@ -31,21 +33,17 @@ pub struct Transition {
 
 impl Transition {
     #[inline]
-    pub fn new<
-        N: Into<Cow<'static, str>>,
-        A: Into<Cow<'static, str>>,
-        B: Into<Cow<'static, str>>,
-    >(
+    pub fn new<N: Into<Cow<'static, str>>>(
         name: N,
-        a_track: A,
-        b_track: B,
+        a_track: usize,
+        b_track: usize,
         start: usize,
         end: usize,
     ) -> Self {
         Self {
             name: name.into(),
-            a_track: a_track.into(),
-            b_track: b_track.into(),
+            a_track,
+            b_track,
             properties: HashMap::new(),
             start,
             end,
@@ -62,6 +60,26 @@ impl Transition {
         self
     }
 
+    #[inline]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    #[inline]
+    pub fn tracks(&self) -> (usize, usize) {
+        (self.a_track, self.b_track)
+    }
+
+    #[inline]
+    pub fn range(&self) -> (usize, usize) {
+        (self.start, self.end)
+    }
+
+    #[inline]
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.properties.get(key).map(|value| value.as_ref())
+    }
+
     #[inline]
     pub fn into_events(self) -> impl Iterator<Item = Event<'static>> {
         let opener = BytesStart::borrowed_name(b"transition").with_attributes(ArrayIter::new([
@@ -84,56 +102,15 @@ impl Transition {
             ..
         } = self;
 
-        let service_opener =
-            BytesStart::borrowed_name(b"property").with_attributes(iter::once(Attribute {
-                key: b"name".as_ref(),
-                value: b"mlt_service".as_ref().into(),
-            }));
-        let service_text = BytesText::from_escaped_str(name);
-        let service_closer = BytesEnd::borrowed(b"property");
-
-        let a_opener =
-            BytesStart::borrowed_name(b"property").with_attributes(iter::once(Attribute {
-                key: b"name".as_ref(),
-                value: b"a_track".as_ref().into(),
-            }));
-        let a_text = BytesText::from_escaped_str(a_track);
-        let a_closer = BytesEnd::borrowed(b"property");
-
-        let b_opener =
-            BytesStart::borrowed_name(b"property").with_attributes(iter::once(Attribute {
-                key: b"name".as_ref(),
-                value: b"b_track".as_ref().into(),
-            }));
-        let b_text = BytesText::from_escaped_str(b_track);
-        let b_closer = BytesEnd::borrowed(b"property");
-
-        ArrayIter::new([
-            Event::Start(opener),
-            Event::Start(service_opener),
-            Event::Text(service_text),
-            Event::End(service_closer),
-            Event::Start(a_opener),
-            Event::Text(a_text),
-            Event::End(a_closer),
-            Event::Start(b_opener),
-            Event::Text(b_text),
-            Event::End(b_closer),
-        ])
-        .chain(properties.into_iter().flat_map(|(propkey, propvalue)| {
-            let propopen =
-                BytesStart::borrowed_name(b"property").with_attributes(iter::once(Attribute {
-                    key: b"name".as_ref(),
-                    value: cow_str_into_bytes(propkey),
-                }));
-            let proptext = BytesText::from_escaped_str(propvalue);
-            let propend = BytesEnd::borrowed(b"property");
-            ArrayIter::new([
-                Event::Start(propopen),
-                Event::Text(proptext),
-                Event::End(propend),
-            ])
-        }))
-        .chain(iter::once(Event::End(closer)))
+        iter::once(Event::Start(opener))
+            .chain(property_events("mlt_service", name))
+            .chain(property_events("a_track", a_track.to_string()))
+            .chain(property_events("b_track", b_track.to_string()))
+            .chain(
+                properties
+                    .into_iter()
+                    .flat_map(|(propkey, propvalue)| property_events(propkey, propvalue)),
+            )
+            .chain(iter::once(Event::End(closer)))
     }
 }
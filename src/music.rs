@@ -15,28 +15,92 @@
  * along with KOTI.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use crate::Context;
+use crate::{probe, Context};
 use nanorand::{tls_rng, RNG};
-use std::{
-    io::ErrorKind,
-    mem,
-    path::{Path, PathBuf},
-};
+use std::{io::ErrorKind, mem, path::PathBuf};
 use tokio::{
     fs::File,
     io::{AsyncReadExt, AsyncWriteExt},
 };
 
+/// How many of the most recently used tracks to avoid picking again.
+const RECENT_TRACKS: usize = 3;
+
 #[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
 pub struct Music {
     entries: Vec<MusicEntry>,
+    /// Names of the tracks used for the last few videos, most recent last.
+    #[serde(default)]
+    recent: Vec<String>,
 }
 
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
 pub struct MusicEntry {
-    name: String,
-    path: PathBuf,
-    attribution: String,
+    pub name: String,
+    pub path: PathBuf,
+    pub attribution: String,
+    #[serde(default)]
+    pub mood: Option<String>,
+    /// Beats per minute.
+    #[serde(default)]
+    pub bpm: Option<u32>,
+    #[serde(default)]
+    pub license: Option<String>,
+    /// Names of the frame sources this track can be used with, or empty for all of them.
+    #[serde(default)]
+    pub sources: Vec<String>,
+    /// How likely this track is to be picked, compared to the others.
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+}
+
+#[inline]
+fn default_weight() -> u32 {
+    1
+}
+
+impl MusicEntry {
+    #[inline]
+    pub fn new(name: String, path: PathBuf, attribution: String) -> Self {
+        Self {
+            name,
+            path,
+            attribution,
+            mood: None,
+            bpm: None,
+            license: None,
+            sources: vec![],
+            weight: default_weight(),
+        }
+    }
+
+    /// Whether this track can be used for videos from the given frame source.
+    #[inline]
+    pub fn is_eligible(&self, source: Option<&str>) -> bool {
+        self.weight > 0
+            && (self.sources.is_empty()
+                || source.map_or(false, |source| self.sources.iter().any(|s| s == source)))
+    }
+
+    /// Make sure the track exists and can be decoded.
+    #[inline]
+    pub async fn verify(&self) -> crate::Result {
+        if let Err(e) = tokio::fs::metadata(&self.path).await {
+            return Err(crate::Error::ProbeFailed(self.path.clone(), e.to_string()));
+        }
+        let info = probe::probe(&self.path).await?;
+        match (info.audio, info.duration) {
+            (Some(_), Some(duration)) if duration > 0.0 => Ok(()),
+            (None, _) => Err(crate::Error::ProbeFailed(
+                self.path.clone(),
+                "it has no audio".to_string(),
+            )),
+            _ => Err(crate::Error::ProbeFailed(
+                self.path.clone(),
+                "it has no duration".to_string(),
+            )),
+        }
+    }
 }
 
 impl Music {
@@ -67,19 +131,101 @@ impl Music {
     }
 
     #[inline]
-    pub fn add_track(&mut self, name: String, path: PathBuf, attribution: String) {
-        self.entries.push(MusicEntry {
-            name,
-            path,
-            attribution,
-        });
+    pub fn tracks(&self) -> &[MusicEntry] {
+        &self.entries
+    }
+
+    #[inline]
+    pub fn track_mut(&mut self, name: &str) -> Option<&mut MusicEntry> {
+        self.entries.iter_mut().find(|e| e.name == name)
     }
 
-    /// Returns path to music and attribution string.
     #[inline]
-    pub fn random_track(&self) -> (&Path, &str) {
-        let entry = &self.entries[tls_rng().generate_range(0, self.entries.len())];
-        (&entry.path, &entry.attribution)
+    pub fn add_track(&mut self, entry: MusicEntry) -> crate::Result {
+        if self.entries.iter().any(|e| e.name == entry.name) {
+            return Err(crate::Error::Msg(format!(
+                "There is already a music track named {}",
+                &entry.name
+            )));
+        }
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    /// Remove a track, returning it if there was one with that name.
+    #[inline]
+    pub fn remove_track(&mut self, name: &str) -> Option<MusicEntry> {
+        let index = self.entries.iter().position(|e| e.name == name)?;
+        self.recent.retain(|recent| recent != name);
+        Some(self.entries.remove(index))
+    }
+
+    /// Rename a track, keeping track of whether it was used recently.
+    #[inline]
+    pub fn rename_track(&mut self, name: &str, new_name: String) -> crate::Result {
+        if self.entries.iter().any(|e| e.name == new_name) {
+            return Err(crate::Error::Msg(format!(
+                "There is already a music track named {}",
+                &new_name
+            )));
+        }
+        let entry = self
+            .track_mut(name)
+            .ok_or_else(|| crate::Error::Msg(format!("There is no music track named {}", name)))?;
+        entry.name = new_name.clone();
+        self.recent
+            .iter_mut()
+            .filter(|recent| *recent == name)
+            .for_each(|recent| *recent = new_name.clone());
+        Ok(())
+    }
+
+    /// Pick a track for a video from the given frame source at random, taking the weights into
+    /// account and avoiding the tracks that were used recently if there are others to use.
+    #[inline]
+    pub fn choose_track(&self, source: Option<&str>) -> crate::Result<&MusicEntry> {
+        let eligible: Vec<&MusicEntry> = self
+            .entries
+            .iter()
+            .filter(|e| e.is_eligible(source))
+            .collect();
+
+        // forget about the oldest tracks until there's something left to pick
+        let fresh = (0..=self.recent.len())
+            .map(|forgotten| {
+                let recent = &self.recent[forgotten..];
+                eligible
+                    .iter()
+                    .copied()
+                    .filter(|e| !recent.contains(&e.name))
+                    .collect::<Vec<_>>()
+            })
+            .find(|fresh| !fresh.is_empty())
+            .ok_or_else(|| crate::Error::NoMusic(source.map(str::to_string)))?;
+
+        let total: u32 = fresh.iter().map(|e| e.weight).sum();
+        let mut pick = tls_rng().generate_range::<u32>(0, total);
+        Ok(fresh
+            .iter()
+            .find(|e| match pick.checked_sub(e.weight) {
+                Some(rest) => {
+                    pick = rest;
+                    false
+                }
+                None => true,
+            })
+            .copied()
+            .unwrap_or(fresh[0]))
+    }
+
+    /// Remember that a track was used, so it isn't picked again for a while.
+    #[inline]
+    pub fn record_use(&mut self, name: &str) {
+        self.recent.retain(|recent| recent != name);
+        self.recent.push(name.to_string());
+        if self.recent.len() > RECENT_TRACKS {
+            self.recent.remove(0);
+        }
     }
 }
 
@@ -87,3 +233,47 @@ impl Music {
 async fn jsonpath(ctx: &Context) -> PathBuf {
     ctx.datadir().await.join("music.json")
 }
+
+#[test]
+fn test_choose_track() {
+    let mut music = Music::default();
+    music.choose_track(None).unwrap_err();
+
+    let track = |name: &str, sources: &[&str], weight: u32| MusicEntry {
+        sources: sources.iter().map(|s| s.to_string()).collect(),
+        weight,
+        ..MusicEntry::new(name.to_string(), PathBuf::from(name), String::new())
+    };
+    music.add_track(track("anything", &[], 1)).unwrap();
+    music.add_track(track("spooky", &["scp"], 1)).unwrap();
+    music.add_track(track("never", &[], 0)).unwrap();
+    music.add_track(track("spooky", &[], 1)).unwrap_err();
+
+    // tracks tied to a frame source are only used for it
+    for _ in 0..20 {
+        assert_eq!(music.choose_track(None).unwrap().name, "anything");
+        assert_eq!(
+            music.choose_track(Some("askreddit")).unwrap().name,
+            "anything"
+        );
+    }
+
+    // recently used tracks are avoided while there's something else to use
+    music.record_use("spooky");
+    for _ in 0..20 {
+        assert_eq!(music.choose_track(Some("scp")).unwrap().name, "anything");
+    }
+    music.record_use("anything");
+    for _ in 0..20 {
+        assert_eq!(music.choose_track(Some("scp")).unwrap().name, "spooky");
+    }
+
+    music.rename_track("spooky", "creepy".to_string()).unwrap();
+    assert_eq!(music.recent, vec!["creepy", "anything"]);
+    music.remove_track("anything").unwrap();
+    assert_eq!(music.recent, vec!["creepy"]);
+    assert!(matches!(
+        music.choose_track(Some("askreddit")),
+        Err(crate::Error::NoMusic(Some(_)))
+    ));
+}
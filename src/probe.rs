@@ -0,0 +1,339 @@
+/*
+ * This file is part of KOTI.
+ *
+ * KOTI is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * KOTI is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Afero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with KOTI.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Find out how long media files are and what's in them. WAV files are read directly, and
+//! everything else goes through `ffprobe`.
+
+use std::{fs, path::Path, process::Stdio};
+use tokio::process::Command;
+
+/// What's in a media file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MediaInfo {
+    /// Length of the file in seconds, or `None` for still images.
+    pub duration: Option<f32>,
+    pub audio: Option<AudioInfo>,
+    pub video: Option<VideoInfo>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AudioInfo {
+    pub sample_rate: u32,
+    pub channels: u16,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct VideoInfo {
+    pub width: usize,
+    pub height: usize,
+    /// Frames per second, or `None` if it isn't known.
+    pub frame_rate: Option<f32>,
+}
+
+/// Probe a media file.
+#[inline]
+pub async fn probe(path: &Path) -> crate::Result<MediaInfo> {
+    let is_wav = path
+        .extension()
+        .map_or(false, |ext| ext.eq_ignore_ascii_case("wav"));
+    if is_wav {
+        let wav = path.to_path_buf();
+        match tokio::task::spawn_blocking(move || probe_wav(&wav)).await? {
+            Ok(info) => return Ok(info),
+            // hound doesn't read every kind of WAV file, but ffprobe might
+            Err(e) => log::warn!("Unable to read {:?} as a WAV file: {}", path, e),
+        }
+    }
+
+    ffprobe(path).await
+}
+
+/// Get the length of a media file, in seconds.
+#[inline]
+pub async fn duration(path: &Path) -> crate::Result<f32> {
+    probe(path).await?.duration.ok_or_else(|| {
+        crate::Error::ProbeFailed(path.to_path_buf(), "it has no duration".to_string())
+    })
+}
+
+/// Read the header of a WAV file.
+#[inline]
+pub fn probe_wav(path: &Path) -> crate::Result<MediaInfo> {
+    let reader = hound::WavReader::open(path)?;
+    let spec = reader.spec();
+    if spec.sample_rate == 0 || spec.channels == 0 {
+        return Err(crate::Error::ProbeFailed(
+            path.to_path_buf(),
+            "the WAV header is empty".to_string(),
+        ));
+    }
+
+    // files that were streamed out sometimes have a placeholder for their length
+    let data_len = reader.len() as u64 * u64::from(spec.bits_per_sample / 8);
+    if data_len > fs::metadata(path)?.len() {
+        return Err(crate::Error::ProbeFailed(
+            path.to_path_buf(),
+            "the WAV header says it's longer than the file".to_string(),
+        ));
+    }
+
+    Ok(MediaInfo {
+        duration: Some(reader.duration() as f32 / spec.sample_rate as f32),
+        audio: Some(AudioInfo {
+            sample_rate: spec.sample_rate,
+            channels: spec.channels,
+        }),
+        video: None,
+    })
+}
+
+/// Ask `ffprobe` about a media file.
+#[inline]
+pub async fn ffprobe(path: &Path) -> crate::Result<MediaInfo> {
+    let output = Command::new("ffprobe")
+        .args(&[
+            "-v",
+            "error",
+            "-print_format",
+            "json",
+            "-show_format",
+            "-show_streams",
+        ])
+        .arg(path)
+        .stdin(Stdio::null())
+        .output()
+        .await?;
+    if !output.status.success() {
+        return Err(crate::Error::ProbeFailed(
+            path.to_path_buf(),
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+
+    parse_ffprobe(path, &output.stdout)
+}
+
+#[derive(serde::Deserialize)]
+struct FfprobeOutput {
+    #[serde(default)]
+    streams: Vec<FfprobeStream>,
+    format: Option<FfprobeFormat>,
+}
+
+#[derive(serde::Deserialize)]
+struct FfprobeStream {
+    codec_type: Option<String>,
+    sample_rate: Option<String>,
+    channels: Option<u16>,
+    width: Option<usize>,
+    height: Option<usize>,
+    r_frame_rate: Option<String>,
+    avg_frame_rate: Option<String>,
+    duration: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct FfprobeFormat {
+    duration: Option<String>,
+}
+
+/// Read the output of `ffprobe -print_format json -show_format -show_streams`.
+#[inline]
+fn parse_ffprobe(path: &Path, json: &[u8]) -> crate::Result<MediaInfo> {
+    let failed = |msg: String| crate::Error::ProbeFailed(path.to_path_buf(), msg);
+    let output: FfprobeOutput =
+        serde_json::from_slice(json).map_err(|e| failed(format!("bad ffprobe output: {}", e)))?;
+
+    let stream = |kind: &str| {
+        output
+            .streams
+            .iter()
+            .find(|s| s.codec_type.as_deref() == Some(kind))
+    };
+    let audio = stream("audio").map(|s| AudioInfo {
+        sample_rate: s
+            .sample_rate
+            .as_deref()
+            .and_then(|rate| rate.parse().ok())
+            .unwrap_or(0),
+        channels: s.channels.unwrap_or(0),
+    });
+    let video = match stream("video") {
+        Some(FfprobeStream {
+            width: Some(width),
+            height: Some(height),
+            r_frame_rate,
+            avg_frame_rate,
+            ..
+        }) => Some(VideoInfo {
+            width: *width,
+            height: *height,
+            frame_rate: avg_frame_rate
+                .as_deref()
+                .and_then(parse_rate)
+                .or_else(|| r_frame_rate.as_deref().and_then(parse_rate)),
+        }),
+        _ => None,
+    };
+
+    // still images have no duration at all, or a single frame's worth
+    let duration = output
+        .format
+        .as_ref()
+        .and_then(|f| f.duration.as_deref())
+        .or_else(|| output.streams.iter().find_map(|s| s.duration.as_deref()))
+        .and_then(|d| d.parse::<f32>().ok())
+        .filter(|d| d.is_finite() && *d > 0.0);
+
+    if audio.is_none() && video.is_none() {
+        return Err(failed("it has no audio or video".to_string()));
+    }
+
+    Ok(MediaInfo {
+        duration,
+        audio,
+        video,
+    })
+}
+
+/// Parse a frame rate like "30000/1001".
+#[inline]
+fn parse_rate(rate: &str) -> Option<f32> {
+    let rate = match rate.split_once('/') {
+        Some((num, den)) => num.parse::<f32>().ok()? / den.parse::<f32>().ok()?,
+        None => rate.parse().ok()?,
+    };
+    Some(rate).filter(|r| r.is_finite() && *r > 0.0)
+}
+
+#[test]
+fn test_probe_wav() {
+    let dir = std::env::temp_dir().join(format!("koti-probe-test-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("tone.wav");
+
+    // a second and a half of stereo audio
+    let spec = hound::WavSpec {
+        channels: 2,
+        sample_rate: 16000,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+    for i in 0..24000 {
+        let sample = ((i as f32 / 10.0).sin() * 8000.0) as i16;
+        writer.write_sample(sample).unwrap();
+        writer.write_sample(sample).unwrap();
+    }
+    writer.finalize().unwrap();
+
+    let info = probe_wav(&path).unwrap();
+    assert_eq!(info.duration, Some(1.5));
+    assert_eq!(
+        info.audio,
+        Some(AudioInfo {
+            sample_rate: 16000,
+            channels: 2
+        })
+    );
+    assert_eq!(info.video, None);
+
+    let info = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("Unable to construct Tokio runtime")
+        .block_on(probe(&path))
+        .unwrap();
+    assert_eq!(info.duration, Some(1.5));
+
+    // a header that promises more than the file has
+    let mut data = fs::read(&path).unwrap();
+    data.truncate(1000);
+    fs::write(&path, data).unwrap();
+    probe_wav(&path).unwrap_err();
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_parse_ffprobe() {
+    let path = Path::new("intro.mkv");
+    let info = parse_ffprobe(
+        path,
+        br#"{
+            "streams": [
+                {
+                    "index": 0,
+                    "codec_name": "h264",
+                    "codec_type": "video",
+                    "width": 1920,
+                    "height": 1080,
+                    "r_frame_rate": "30000/1001",
+                    "avg_frame_rate": "30000/1001"
+                },
+                {
+                    "index": 1,
+                    "codec_name": "opus",
+                    "codec_type": "audio",
+                    "sample_rate": "48000",
+                    "channels": 2
+                }
+            ],
+            "format": {
+                "filename": "intro.mkv",
+                "format_name": "matroska,webm",
+                "duration": "3725.480000"
+            }
+        }"#,
+    )
+    .unwrap();
+
+    // an hour, two minutes and a bit
+    assert_eq!(info.duration, Some(3725.48));
+    assert_eq!(
+        info.audio,
+        Some(AudioInfo {
+            sample_rate: 48000,
+            channels: 2
+        })
+    );
+    let video = info.video.unwrap();
+    assert_eq!((video.width, video.height), (1920, 1080));
+    assert!((video.frame_rate.unwrap() - 29.97).abs() < 0.01);
+
+    let info = parse_ffprobe(
+        Path::new("card.png"),
+        br#"{
+            "streams": [
+                {
+                    "codec_type": "video",
+                    "width": 640,
+                    "height": 480,
+                    "r_frame_rate": "25/1",
+                    "avg_frame_rate": "0/0"
+                }
+            ],
+            "format": {}
+        }"#,
+    )
+    .unwrap();
+    assert_eq!(info.duration, None);
+    assert_eq!(info.video.unwrap().frame_rate, Some(25.0));
+
+    parse_ffprobe(path, br#"{"streams": [], "format": {}}"#).unwrap_err();
+    parse_ffprobe(path, b"not json").unwrap_err();
+}
@@ -0,0 +1,173 @@
+/*
+ * This file is part of KOTI.
+ *
+ * KOTI is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * KOTI is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Afero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with KOTI.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Turn the background music down while something is being read out.
+
+use super::seconds_to_frames;
+use crate::mlt::Filter;
+use std::fmt::Write;
+
+/// How the music ducks under the narration.
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DuckingSettings {
+    /// How much quieter the music gets during narration, in decibels. Zero turns ducking off.
+    pub depth: f32,
+    /// How long the music takes to duck before the narration starts, in seconds.
+    pub attack: f32,
+    /// How long the music takes to come back up after the narration ends, in seconds.
+    pub release: f32,
+}
+
+impl Default for DuckingSettings {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            depth: 12.0,
+            attack: 0.3,
+            release: 0.8,
+        }
+    }
+}
+
+impl DuckingSettings {
+    #[inline]
+    pub fn validate(&self) -> crate::Result {
+        if !self.depth.is_finite() || !(0.0..=60.0).contains(&self.depth) {
+            return Err(crate::Error::InvalidConfig(format!(
+                "ducking depth {}dB is outside of the range 0dB to 60dB",
+                self.depth
+            )));
+        }
+        for (name, value) in [("attack", self.attack), ("release", self.release)] {
+            if !value.is_finite() || !(0.0..=10.0).contains(&value) {
+                return Err(crate::Error::InvalidConfig(format!(
+                    "ducking {} {}s is outside of the range 0s to 10s",
+                    name, value
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// The volume filter for the music, given the level it plays at in decibels and the spans of
+    /// frames where there's narration, in order.
+    #[inline]
+    pub fn music_filter(&self, volume: f32, narration: &[(usize, usize)]) -> Filter {
+        if self.depth == 0.0 {
+            return volume_keyframes(&[(0, volume)]);
+        }
+
+        volume_keyframes(&self.keyframes(volume, narration))
+    }
+
+    /// Keyframes for the music's level, in decibels.
+    #[inline]
+    fn keyframes(&self, volume: f32, narration: &[(usize, usize)]) -> Vec<(usize, f32)> {
+        // ramps take at least a frame, so the keyframes never land on top of each other
+        let attack = seconds_to_frames(self.attack).max(1);
+        let release = seconds_to_frames(self.release).max(1);
+        let ducked = volume - self.depth;
+
+        // if the music wouldn't have time to come back up between two bits of narration, it
+        // stays down through both
+        let mut spans: Vec<(usize, usize)> = vec![];
+        for &(start, end) in narration.iter().filter(|(start, end)| end > start) {
+            match spans.last_mut() {
+                Some(last) if start.saturating_sub(attack) <= last.1 + release => {
+                    last.1 = last.1.max(end)
+                }
+                _ => spans.push((start, end)),
+            }
+        }
+
+        let mut keyframes = vec![];
+        for (start, end) in spans {
+            let ramp = start.saturating_sub(attack);
+            if ramp < start {
+                keyframes.push((ramp, volume));
+            }
+            keyframes.push((start, ducked));
+            keyframes.push((end, ducked));
+            keyframes.push((end + release, volume));
+        }
+        if keyframes.first().map_or(true, |(frame, _)| *frame > 0) {
+            keyframes.insert(0, (0, volume));
+        }
+        keyframes
+    }
+}
+
+/// A volume filter that follows the keyframes, in decibels.
+#[inline]
+pub fn volume_keyframes(keyframes: &[(usize, f32)]) -> Filter {
+    let mut level = String::new();
+    for (i, (frame, value)) in keyframes.iter().enumerate() {
+        if i > 0 {
+            level.push(';');
+        }
+        let _ = write!(level, "{}={}", frame, value);
+    }
+    Filter::new("volume").property("level", level)
+}
+
+#[test]
+fn test_ducking_keyframes() {
+    let settings = DuckingSettings {
+        depth: 10.0,
+        attack: 1.0,
+        release: 2.0,
+    };
+    let (attack, release) = (seconds_to_frames(1.0), seconds_to_frames(2.0));
+
+    // the music starts ducked if the narration starts right away, and comes back up after it
+    assert_eq!(
+        settings.keyframes(-10.0, &[(0, 100)]),
+        vec![(0, -20.0), (100, -20.0), (100 + release, -10.0)]
+    );
+
+    // narration later on gets ducked into, and close narration is ducked through
+    assert_eq!(
+        settings.keyframes(-10.0, &[(300, 400), (420, 500), (1000, 1100)]),
+        vec![
+            (0, -10.0),
+            (300 - attack, -10.0),
+            (300, -20.0),
+            (500, -20.0),
+            (500 + release, -10.0),
+            (1000 - attack, -10.0),
+            (1000, -20.0),
+            (1100, -20.0),
+            (1100 + release, -10.0),
+        ]
+    );
+
+    // no narration means no ducking
+    assert_eq!(settings.keyframes(-10.0, &[(50, 50)]), vec![(0, -10.0)]);
+
+    let filter = settings.music_filter(-10.0, &[(0, 100)]);
+    assert_eq!(
+        filter.get("level"),
+        Some(format!("0=-20;100=-20;{}=-10", 100 + release).as_str())
+    );
+    let filter = DuckingSettings {
+        depth: 0.0,
+        ..settings
+    }
+    .music_filter(-10.0, &[(0, 100)]);
+    assert_eq!(filter.get("level"), Some("0=-10"));
+}
@@ -15,12 +15,15 @@
  * along with KOTI.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use super::{seconds_to_frames, tts::create_tts};
+use super::seconds_to_frames;
 use crate::{
+    captions::{self, Cue},
     context::Context,
     frame::Frame,
     image_size::image_size,
-    mlt::{Filter, Mlt, PlaylistEntry},
+    karaoke::{karaoke_image, read_wav, word_timings},
+    mlt::{Filter, Mlt, PlaylistEntry, TractorId},
+    process::tts::clean_text,
     text2image,
     util::{ImmediateOrTask, MapFuture},
 };
@@ -45,18 +48,47 @@ fn emptied(s: String) -> Option<String> {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
 pub struct ConvertedFrame {
     fg_image: Option<(PathBuf, u32, u32)>,
     fades_in_after: Option<f32>,
     text_overlay: Option<(PathBuf, u32, u32)>,
     tts_audio: Option<(PathBuf, f32)>,
     duration: f32,
+    /// Captions for the narration, timed from the start of the frame.
+    #[serde(default)]
+    captions: Vec<Cue>,
+    /// Drawings of the narration with each word lit up in turn, and when each one starts, in
+    /// seconds from the start of the frame.
+    #[serde(default)]
+    karaoke: Vec<(PathBuf, f32)>,
+}
+
+/// Summary of a converted frame, for reviewing a video before it's rendered.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct FramePlan {
+    pub tts: String,
+    pub speaker: Option<String>,
+    pub overlaid: String,
+    pub image: Option<PathBuf>,
+    pub text_overlay: Option<PathBuf>,
+    pub tts_audio: Option<PathBuf>,
+    /// Length of the TTS audio, in seconds.
+    pub tts_duration: f32,
+    /// Length of the frame, in seconds.
+    pub duration: f32,
+    pub duration_frames: usize,
+    /// Captions for the narration, timed from the start of the frame.
+    pub captions: Vec<Cue>,
 }
 
 impl ConvertedFrame {
     #[inline]
-    pub async fn from_frame(frame: Frame, ctx: Arc<Context>) -> crate::Result<Self> {
+    pub async fn from_frame(
+        frame: Frame,
+        tts_audio: Option<(PathBuf, f32)>,
+        ctx: Arc<Context>,
+    ) -> crate::Result<Self> {
         log::info!("Converting frame to finalized portion: {:?}", frame);
 
         let Frame {
@@ -65,17 +97,15 @@ impl ConvertedFrame {
             imagepath,
             imagefadesin,
             persists_after_tts,
+            ..
         } = frame;
 
-        // tts .wav file
-        let ctx_clone = ctx.clone();
-        let tts_audio: ImmediateOrTask<_> = match emptied(tts) {
-            Some(tts) => tokio::spawn(async move {
-                let (t, duration) = create_tts(&tts, &ctx_clone).await?;
-                crate::Result::Ok(Some((t, duration)))
-            })
-            .into(),
-            None => future::ready(Ok(None)).into(),
+        // the narration, with each word lit up as it's read
+        let karaoke: ImmediateOrTask<_> = match (ctx.config().karaoke, &tts_audio) {
+            (true, Some((audio, _))) => {
+                tokio::spawn(karaoke_images(clean_text(&tts), audio.clone(), ctx.clone())).into()
+            }
+            _ => future::ready(Ok(vec![])).into(),
         };
 
         // text overlay image file
@@ -122,9 +152,7 @@ impl ConvertedFrame {
         };
 
         // combine all the tasks
-        let ((tts_audio, text_overlay_path), fg_image) =
-            future::zip(future::zip(tts_audio, text_overlay), fg_image).await;
-        let tts_audio = tts_audio??;
+        let (text_overlay_path, fg_image) = future::zip(text_overlay, fg_image).await;
         let duration = if let Some((_, ref duration)) = tts_audio {
             *duration
         } else {
@@ -132,10 +160,18 @@ impl ConvertedFrame {
         };
         let text_overlay = text_overlay_path??;
         let fg_image = fg_image??;
+        let karaoke = karaoke.await??;
         log::info!("Finished converting frame");
 
+        let captions = match tts_audio {
+            Some((_, duration)) => captions::time_sentences(&tts, duration),
+            None => vec![],
+        };
+
         Ok(ConvertedFrame {
             fg_image,
+            captions,
+            karaoke,
             tts_audio,
             text_overlay,
             fades_in_after: if imagefadesin { Some(1.0) } else { None },
@@ -148,8 +184,47 @@ impl ConvertedFrame {
         self.duration
     }
 
+    /// How long the narration at the start of the frame goes on for, in seconds.
+    #[inline]
+    pub fn narration(&self) -> f32 {
+        self.tts_audio
+            .as_ref()
+            .map_or(0.0, |(_, duration)| *duration)
+    }
+
+    #[inline]
+    pub fn captions(&self) -> &[Cue] {
+        &self.captions
+    }
+
+    /// Describe what this frame will look and sound like, given the frame it was made from.
+    #[inline]
+    pub fn plan(&self, frame: &Frame) -> FramePlan {
+        let (tts_audio, tts_duration) = match self.tts_audio {
+            Some((ref path, duration)) => (Some(path.clone()), duration),
+            None => (None, 0.0),
+        };
+
+        FramePlan {
+            tts: frame.tts.clone(),
+            speaker: frame.speaker.clone(),
+            overlaid: frame.overlaid.clone(),
+            image: self.fg_image.as_ref().map(|(path, _, _)| path.clone()),
+            text_overlay: self.text_overlay.as_ref().map(|(path, _, _)| path.clone()),
+            tts_audio,
+            tts_duration,
+            duration: self.duration,
+            duration_frames: seconds_to_frames(self.duration),
+            captions: self.captions.clone(),
+        }
+    }
+
     #[inline]
-    pub fn into_tractor(mut self, mlt: &mut Mlt, ctx: &Context) -> crate::Result<(String, usize)> {
+    pub fn into_tractor(
+        mut self,
+        mlt: &mut Mlt,
+        ctx: &Context,
+    ) -> crate::Result<(TractorId, usize)> {
         // three ways of doing this:
         // we just have one image (either the overlay or the direct image)
         // we have both
@@ -157,6 +232,10 @@ impl ConvertedFrame {
         let text_overlay = self.text_overlay.take();
         let duration = seconds_to_frames(self.duration);
 
+        if !self.karaoke.is_empty() {
+            return Ok((self.into_tractor_karaoke(mlt), duration));
+        }
+
         Ok((
             match (fg_image, text_overlay) {
                 (None, None) => panic!("blank frame?"),
@@ -171,6 +250,57 @@ impl ConvertedFrame {
         ))
     }
 
+    /// Show each drawing of the narration until the next one starts.
+    #[inline]
+    fn into_tractor_karaoke(self, mlt: &mut Mlt) -> TractorId {
+        // the frame's out point is inclusive
+        let total_duration = seconds_to_frames(self.duration) + 1;
+        let starts: Vec<usize> = self
+            .karaoke
+            .iter()
+            .enumerate()
+            .map(|(i, (_, start))| match i {
+                0 => 0,
+                _ => seconds_to_frames(*start).min(total_duration),
+            })
+            .collect();
+
+        let entries: Vec<PlaylistEntry> = self
+            .karaoke
+            .into_iter()
+            .zip(starts.iter().enumerate())
+            .filter_map(|((image, _), (i, &start))| {
+                let end = starts.get(i + 1).copied().unwrap_or(total_duration);
+                if end <= start {
+                    return None;
+                }
+                Some(PlaylistEntry::Producer {
+                    id: mlt.add_producer(image).into(),
+                    start: 0,
+                    end: end - start - 1,
+                })
+            })
+            .collect();
+        let images = mlt.add_playlist(entries, iter::empty());
+
+        let audio = self.tts_audio.map(|(audio, _)| {
+            let audio = mlt.add_producer(audio);
+            mlt.add_playlist(
+                iter::once(PlaylistEntry::Producer {
+                    id: audio.into(),
+                    start: 0,
+                    end: total_duration - 1,
+                }),
+                iter::empty(),
+            )
+        });
+
+        mlt.add_tractor(
+            iter::once(images).chain(audio),
+            iter::once(volume_filter(20)),
+        )
+    }
+
     #[inline]
     fn into_tractor_1_image(
         mut self,
@@ -179,7 +309,7 @@ impl ConvertedFrame {
         w: u32,
         h: u32,
         ctx: &Context,
-    ) -> crate::Result<String> {
+    ) -> crate::Result<TractorId> {
         // producers:
         //  * the raw image
         //  * the audio
@@ -196,47 +326,45 @@ impl ConvertedFrame {
         let audio: Option<PathBuf> = self.tts_audio.map(|(t, _)| t);
 
         // producers
-        let producer1 = mlt.add_producer(img).to_string();
-        let producer2 = audio.map(|audio| mlt.add_producer(audio).to_string());
+        let producer1 = mlt.add_producer(img);
+        let producer2 = audio.map(|audio| mlt.add_producer(audio));
 
         // playlists
-        let playlist1 = mlt
-            .add_playlist(
-                iter::once(PlaylistEntry::Producer {
-                    id: producer1,
-                    start: 0,
-                    end: total_duration,
-                }),
-                iter::once(Filter::new("resize")),
-                /*                iter::once({
-                    // use an affine transform to make sure the image fits
-                    let w = w as f32;
-                    let h = h as f32;
-                    let ratio = w / h;
-                    let (w, h) = if ratio > (vw / vh) {
-                        (vw, h * (vh / vw))
-                    } else {
-                        // height is 1080, adjust width to fit
-                        (w * (vw / vh), vh)
-                    };
-                    let (x, y) = ((((2.0*vw) - w)/2.0), ((2.0*vh) - h)/2.0);
-                    let (x, y, w, h) = (x as u32, y as u32, w as u32, h as u32);
-
-                    Filter::new("affine".to_string())
-                        .property("background".to_string(), "colour:0".to_string())
-                        .property(
-                            "transition.geometry".to_string(),
-                            format!("0={} {} {} {}", x, y, w, h),
-                        )
-                        .property("transition.distort".to_string(), "0".to_string())
-                }),*/
-                //iter::empty(),
-            )
-            .to_string();
+        let playlist1 = mlt.add_playlist(
+            iter::once(PlaylistEntry::Producer {
+                id: producer1.into(),
+                start: 0,
+                end: total_duration,
+            }),
+            iter::once(Filter::new("resize")),
+            /*                iter::once({
+                // use an affine transform to make sure the image fits
+                let w = w as f32;
+                let h = h as f32;
+                let ratio = w / h;
+                let (w, h) = if ratio > (vw / vh) {
+                    (vw, h * (vh / vw))
+                } else {
+                    // height is 1080, adjust width to fit
+                    (w * (vw / vh), vh)
+                };
+                let (x, y) = ((((2.0*vw) - w)/2.0), ((2.0*vh) - h)/2.0);
+                let (x, y, w, h) = (x as u32, y as u32, w as u32, h as u32);
+
+                Filter::new("affine".to_string())
+                    .property("background".to_string(), "colour:0".to_string())
+                    .property(
+                        "transition.geometry".to_string(),
+                        format!("0={} {} {} {}", x, y, w, h),
+                    )
+                    .property("transition.distort".to_string(), "0".to_string())
+            }),*/
+            //iter::empty(),
+        );
         let playlist2 = producer2.map(|producer2| {
             mlt.add_playlist(
                 iter::once(PlaylistEntry::Producer {
-                    id: producer2,
+                    id: producer2.into(),
                     start: 0,
                     end: total_duration,
                 }),
@@ -246,16 +374,13 @@ impl ConvertedFrame {
                         .property("start".to_string(), "0.5".to_string()),
                 ),
             )
-            .to_string()
         });
 
         // tractor
-        let tractor = mlt
-            .add_tractor(
-                iter::once(playlist1).chain(playlist2),
-                iter::once(volume_filter(20)),
-            )
-            .to_string();
+        let tractor = mlt.add_tractor(
+            iter::once(playlist1).chain(playlist2),
+            iter::once(volume_filter(20)),
+        );
 
         Ok(tractor)
     }
@@ -271,7 +396,7 @@ impl ConvertedFrame {
         w2: u32,
         h2: u32,
         ctx: &Context,
-    ) -> crate::Result<String> {
+    ) -> crate::Result<TractorId> {
         // producers:
         //  * first image
         //  * second image
@@ -300,72 +425,136 @@ impl ConvertedFrame {
         let y2 = y1 + ch2;
 
         // set up the producers
-        let img1 = mlt.add_producer(img1).to_string();
-        let img2 = mlt.add_producer(img2).to_string();
-        let audio = audio.map(|audio| mlt.add_producer(audio).to_string());
+        let img1 = mlt.add_producer(img1);
+        let img2 = mlt.add_producer(img2);
+        let audio = audio.map(|audio| mlt.add_producer(audio));
 
         // playlists use an affine transform
-        let img1 = mlt
-            .add_playlist(
-                iter::once(PlaylistEntry::Producer {
-                    id: img1,
-                    start: 0,
-                    end: duration,
-                }),
-                iter::once(
-                    Filter::new("affine".to_string())
-                        .property("background".to_string(), "colour:0".to_string())
-                        .property(
-                            "transition.geometry".to_string(),
-                            format!("0=0 {} {} {}", y1, cw1, ch1),
-                        )
-                        .property("transition.distort".to_string(), "0".to_string()),
-                ),
-            )
-            .to_string();
-        let img2 = mlt
-            .add_playlist(
-                iter::once(PlaylistEntry::Producer {
-                    id: img2,
-                    start: 0,
-                    end: duration,
-                }),
-                iter::once(
-                    Filter::new("affine".to_string())
-                        .property("background".to_string(), "colour:0".to_string())
-                        .property(
-                            "transition.geometry".to_string(),
-                            format!("0=0 {} {} {}", y2, cw2, ch2),
-                        )
-                        .property("transition.distort".to_string(), "0".to_string()),
-                ),
-            )
-            .to_string();
+        let img1 = mlt.add_playlist(
+            iter::once(PlaylistEntry::Producer {
+                id: img1.into(),
+                start: 0,
+                end: duration,
+            }),
+            iter::once(
+                Filter::new("affine".to_string())
+                    .property("background".to_string(), "colour:0".to_string())
+                    .property(
+                        "transition.geometry".to_string(),
+                        format!("0=0 {} {} {}", y1, cw1, ch1),
+                    )
+                    .property("transition.distort".to_string(), "0".to_string()),
+            ),
+        );
+        let img2 = mlt.add_playlist(
+            iter::once(PlaylistEntry::Producer {
+                id: img2.into(),
+                start: 0,
+                end: duration,
+            }),
+            iter::once(
+                Filter::new("affine".to_string())
+                    .property("background".to_string(), "colour:0".to_string())
+                    .property(
+                        "transition.geometry".to_string(),
+                        format!("0=0 {} {} {}", y2, cw2, ch2),
+                    )
+                    .property("transition.distort".to_string(), "0".to_string()),
+            ),
+        );
         let audio = audio.map(|audio| {
             mlt.add_playlist(
                 iter::once(PlaylistEntry::Producer {
-                    id: audio,
+                    id: audio.into(),
                     start: 0,
                     end: duration,
                 }),
                 iter::empty(),
             )
-            .to_string()
         });
 
         // set up the tractor
-        let tractor = mlt
-            .add_tractor(
-                ArrayIter::new([img1, img2]).chain(audio),
-                iter::once(volume_filter(20)),
-            )
-            .to_string();
+        let tractor = mlt.add_tractor(
+            ArrayIter::new([img1, img2]).chain(audio),
+            iter::once(volume_filter(20)),
+        );
 
         Ok(tractor)
     }
 }
 
+/// Draw the narration once for each word, with that word lit up, along with when each drawing
+/// should start showing. The last drawing shows every word read, for after the narration ends.
+#[inline]
+async fn karaoke_images(
+    text: String,
+    audio: PathBuf,
+    ctx: Arc<Context>,
+) -> crate::Result<Vec<(PathBuf, f32)>> {
+    static KARAOKE_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+    let words: Vec<&str> = text.split_whitespace().collect();
+    if words.is_empty() {
+        return Ok(vec![]);
+    }
+    let (samples, sample_rate) = tokio::task::spawn_blocking(move || read_wav(&audio)).await??;
+    let timings = word_timings(&words, &samples, sample_rate);
+
+    let (video_width, video_height) = ctx.video_size();
+    let basedir = ctx.basedir().await;
+    let mut images = vec![];
+    for highlight in (0..words.len()).map(Some).chain(iter::once(None)) {
+        let img = karaoke_image(&words, highlight, video_width as _, video_height as _).await?;
+        let path = basedir.join(format!(
+            "karaoke{}.png",
+            KARAOKE_COUNT.fetch_add(1, Ordering::SeqCst)
+        ));
+        let path = tokio::task::spawn_blocking(move || {
+            img.save_with_format(&path, image::ImageFormat::Png)?;
+            crate::Result::Ok(path)
+        })
+        .await??;
+
+        let start = match highlight {
+            Some(i) => timings[i].0,
+            None => timings[words.len() - 1].1,
+        };
+        images.push((path, start));
+    }
+
+    Ok(images)
+}
+
 #[inline]
 pub fn volume_filter(level: u32) -> Filter {
     Filter::new("volume").property("max_gain", format!("{}dB", level))
 }
+
+#[test]
+fn test_plan() {
+    let frame = Frame {
+        tts: "Comments".to_string(),
+        overlaid: "Comments".to_string(),
+        imagepath: None,
+        imagefadesin: false,
+        persists_after_tts: 1.5,
+        speaker: None,
+    };
+    let converted = ConvertedFrame {
+        fg_image: None,
+        fades_in_after: None,
+        text_overlay: Some((PathBuf::from("text_overlay0.png"), 640, 80)),
+        tts_audio: Some((PathBuf::from("tts0.wav"), 0.5)),
+        duration: 2.0,
+        captions: vec![],
+        karaoke: vec![],
+    };
+
+    let plan = converted.plan(&frame);
+    assert_eq!(plan.tts, "Comments");
+    assert_eq!(plan.image, None);
+    assert_eq!(plan.text_overlay, Some(PathBuf::from("text_overlay0.png")));
+    assert_eq!(plan.tts_audio, Some(PathBuf::from("tts0.wav")));
+    assert_eq!(plan.tts_duration, 0.5);
+    assert_eq!(plan.duration_frames, 59);
+}
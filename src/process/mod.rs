@@ -16,9 +16,11 @@
  */
 
 use crate::{
+    captions::{self, Cue},
     context::Context,
-    mlt::{Filter, PlaylistEntry, Transition},
-    util::{video_length, ImmediateOrTask, MapFuture},
+    mlt::{Mlt, PlaylistEntry, PlaylistId, ServiceId, Transition},
+    probe,
+    util::{ImmediateOrTask, MapFuture},
     Frame,
 };
 use futures_lite::{
@@ -32,17 +34,29 @@ use quick_xml::{
 };
 use regex::Regex;
 use std::{
-    array::IntoIter as ArrayIter, io::BufWriter, iter, mem, os::unix::ffi::OsStrExt, path::Path,
-    process::Stdio, str::FromStr, sync::Arc,
+    io::BufWriter,
+    iter,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+    process::Stdio,
+    str::FromStr,
+    sync::Arc,
 };
 use tokio::{
     fs::{self, File},
     process::Command,
 };
 
+mod ducking;
 mod frame;
+mod music_bed;
 pub mod tts;
 
+pub use ducking::DuckingSettings;
+pub use frame::{ConvertedFrame, FramePlan};
+use music_bed::music_bed;
+use tts::{create_tts, TtsCache, TtsEngine, TtsSettings, VoicePool};
+
 pub const FPS: f32 = 29.97;
 
 #[inline]
@@ -56,40 +70,157 @@ macro_rules! s {
     }};
 }
 
+/// The MLT project for a video, ready to be rendered.
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct Project {
+    pub path: PathBuf,
+    pub video_path: PathBuf,
+    /// Length of the video, in frames.
+    pub duration: usize,
+    pub music_credits: String,
+    /// The music tracks playing under the video.
+    #[serde(default)]
+    pub music_paths: Vec<PathBuf>,
+    /// Caption files to go along with the video.
+    #[serde(default)]
+    pub caption_files: Vec<PathBuf>,
+}
+
+/// Everything that is going to go into a video, written out before rendering it.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Plan {
+    pub project: PathBuf,
+    pub video_path: PathBuf,
+    /// Length of the video, in seconds.
+    pub duration: f32,
+    pub duration_frames: usize,
+    pub music_credits: String,
+    pub frames: Vec<FramePlan>,
+}
+
+impl Plan {
+    #[inline]
+    pub fn new(frames: &[Frame], converted: &[ConvertedFrame], project: &Project) -> Self {
+        Self {
+            project: project.path.clone(),
+            video_path: project.video_path.clone(),
+            duration: (project.duration as f32) / FPS,
+            duration_frames: project.duration,
+            music_credits: project.music_credits.clone(),
+            frames: frames
+                .iter()
+                .zip(converted)
+                .map(|(frame, converted)| converted.plan(frame))
+                .collect(),
+        }
+    }
+}
+
+/// Create the TTS audio for each frame that has any text to read.
 #[inline]
-pub async fn process<S: Stream<Item = Frame>>(frames: S, ctx: Arc<Context>) -> crate::Result {
-    let basedir = ctx.basedir().await;
-    let datadir = ctx.datadir().await;
+pub async fn synthesize(
+    frames: &[Frame],
+    settings: &TtsSettings,
+    ctx: Arc<Context>,
+) -> crate::Result<Vec<Option<(PathBuf, f32)>>> {
+    let narrator: Arc<dyn TtsEngine> = settings.engine()?.into();
+    log::info!("Narrating with {}", narrator.name());
+    let voices = settings
+        .voices
+        .iter()
+        .map(|voice| {
+            let settings = TtsSettings {
+                voice: Some(voice.clone()),
+                ..settings.clone()
+            };
+            settings.engine().map(Arc::<dyn TtsEngine>::from)
+        })
+        .collect::<crate::Result<Vec<_>>>()?;
+    let mut pool = VoicePool::new(voices.len());
+    let cache = TtsCache::new(
+        ctx.datadir().await.join("tts_cache"),
+        &ctx.config().tts_cache,
+    );
+
+    let tasks: Vec<ImmediateOrTask<_>> = frames
+        .iter()
+        .map(|frame| match frame.tts.is_empty() {
+            true => future::ready(Ok(None)).into(),
+            false => {
+                let tts = frame.tts.clone();
+                let engine = match pool.voice_for(frame.speaker.as_deref()) {
+                    Some(index) => voices[index].clone(),
+                    None => narrator.clone(),
+                };
+                let ctx = ctx.clone();
+                let cache = cache.clone();
+                tokio::spawn(async move {
+                    create_tts(&tts, &*engine, cache.as_ref(), &ctx)
+                        .await
+                        .map(Some)
+                })
+                .into()
+            }
+        })
+        .collect();
+
+    let tts = stream::iter(tasks)
+        .then(|task| async move { task.await? })
+        .try_collect()
+        .await?;
+
+    if let Some(cache) = cache {
+        match cache.evict().await {
+            Ok(0) => {}
+            Ok(removed) => log::info!("Removed {} old clips from the TTS cache", removed),
+            Err(e) => log::warn!("Unable to clean up the TTS cache: {}", e),
+        }
+    }
+
+    Ok(tts)
+}
+
+/// Render the overlays for each frame and figure out how long each one lasts.
+#[inline]
+pub async fn render_overlays(
+    frames: &[Frame],
+    tts: &[Option<(PathBuf, f32)>],
+    ctx: Arc<Context>,
+) -> crate::Result<Vec<ConvertedFrame>> {
+    if frames.len() != tts.len() {
+        return Err(crate::Error::StaticMsg(
+            "Number of TTS clips doesn't match the number of frames",
+        ));
+    }
 
-    // launch an alternate task to choose a piece of music
-    let ctx_clone = ctx.clone();
-    let musictask = tokio::spawn(async move {
-        let music = crate::music::Music::load(&ctx_clone).await?;
-        let (path, attr) = music.random_track();
-        let total = video_length(path).await?;
+    let tasks: Vec<_> = frames
+        .iter()
+        .cloned()
+        .zip(tts.iter().cloned())
+        .map(|(frame, tts)| tokio::spawn(ConvertedFrame::from_frame(frame, tts, ctx.clone())))
+        .collect();
 
-        crate::Result::Ok((path.to_path_buf(), attr.to_string(), total))
-    });
+    stream::iter(tasks)
+        .then(|task| async move { task.await? })
+        .try_collect()
+        .await
+}
 
-    // collect all of the frames into a vector of tasks that are turning those frames into real stuff
-    let ctx_clone = ctx.clone();
-    let frames_tasks: Vec<_> = frames
-        .map(move |f| tokio::spawn(frame::ConvertedFrame::from_frame(f, ctx_clone.clone())))
-        .collect()
-        .await;
+/// Lay out the converted frames, along with the intro, outro and music, into an MLT project.
+#[inline]
+pub async fn build_project(
+    frames: Vec<ConvertedFrame>,
+    source: Option<String>,
+    ctx: Arc<Context>,
+) -> crate::Result<Project> {
+    let basedir = ctx.basedir().await;
+    let datadir = ctx.datadir().await;
 
-    // collect all of the converted frames and add them to the melt command
     let mut duration: usize = 0;
-    let frames = stream::iter(frames_tasks.into_iter())
-        .then(std::convert::identity)
-        .map(|e| match e {
-            Ok(e) => e,
-            Err(e) => Err(crate::Error::Join(e)),
-        });
 
     // configure melt to use these frames
     let (video_width, video_height) = ctx.video_size();
-    let mut mlt = crate::mlt::Mlt::new(&basedir, video_width, video_height);
+    let mut mlt = Mlt::new(&basedir, video_width, video_height);
 
     let blacktrack = mlt.add_producer(Path::new("black").to_path_buf());
 
@@ -101,10 +232,10 @@ pub async fn process<S: Stream<Item = Frame>>(frames: S, ctx: Arc<Context>) -> c
             None
         }
         Ok(_) => {
-            let total = (video_length(&intro_path).await? * FPS) as usize;
+            let total = (probe::duration(&intro_path).await? * FPS) as usize;
             duration += total;
-            let intro_producer = mlt.add_producer(intro_path);
-            Some((intro_producer, total))
+            let intro_producer = mlt.add_producer_with_length(intro_path, Some(total));
+            Some((intro_producer.into(), total))
         }
     };
 
@@ -116,103 +247,155 @@ pub async fn process<S: Stream<Item = Frame>>(frames: S, ctx: Arc<Context>) -> c
             None
         }
         Ok(_) => {
-            let total = (video_length(&outro_path).await? * FPS) as usize;
+            let total = (probe::duration(&outro_path).await? * FPS) as usize;
             duration += total;
-            let outro_producer = mlt.add_producer(outro_path);
-            Some((outro_producer, total))
+            let outro_producer = mlt.add_producer_with_length(outro_path, Some(total));
+            Some((outro_producer.into(), total))
         }
     };
 
-    // map each frame into an mlt action
-    log::info!("Resolving all of the converted frames from their tasks...");
-    let frame_tractors: Vec<(String, usize)> =
-        stream::iter(intro_frame.into_iter().map(Result::Ok))
-            .chain(frames.map(|frame| match frame {
-                Ok(frame) => match frame.into_tractor(&mut mlt, &ctx) {
-                    Ok((tractor, dur)) => {
-                        duration += dur;
-                        Ok((tractor, dur))
-                    }
-                    Err(e) => Err(e),
-                },
-                Err(e) => Err(e),
-            }))
-            .chain(stream::iter(outro_frame.into_iter().map(Result::Ok)))
-            .try_collect()
-            .await?;
+    // map each frame into an mlt action, keeping track of where each frame's captions and
+    // narration end up. playlist entries include their out point, so each one is a frame longer
+    // than it says
+    let mut cues: Vec<Cue> = vec![];
+    let mut narration: Vec<(usize, usize)> = vec![];
+    let mut position = intro_frame.as_ref().map_or(0, |(_, total)| total + 1);
+    let frame_tractors: Vec<(ServiceId, usize)> = intro_frame
+        .into_iter()
+        .map(Ok)
+        .chain(frames.into_iter().map(|frame| {
+            let offset = position as f32 / FPS;
+            cues.extend(frame.captions().iter().map(|cue| cue.shifted(offset)));
+            narration.push((position, position + seconds_to_frames(frame.narration())));
+            let (tractor, dur) = frame.into_tractor(&mut mlt, &ctx)?;
+            duration += dur;
+            position += dur + 1;
+            Ok((tractor.into(), dur))
+        }))
+        .chain(outro_frame.into_iter().map(Ok))
+        .collect::<crate::Result<_>>()?;
 
     // combine the tractors into a single playlist
-    let frame_playlist = mlt
-        .add_playlist(
-            frame_tractors
-                .into_iter()
-                .map(|(tractor, dur)| PlaylistEntry::Producer {
-                    id: tractor,
-                    start: 0,
-                    end: dur,
-                }),
-            iter::empty(),
-        )
-        .to_string();
+    let frame_playlist = mlt.add_playlist(
+        frame_tractors
+            .into_iter()
+            .map(|(tractor, dur)| PlaylistEntry::Producer {
+                id: tractor,
+                start: 0,
+                end: dur,
+            }),
+        iter::empty(),
+    );
 
     // if the total duration is less than a minute, something is wrong
     if duration < seconds_to_frames(60.0) {
-        return Err(crate::Error::StaticMsg("duration is less than a minute"));
+        return Err(crate::Error::TooShort);
     }
 
-    // by now, we should be done choosing a music entry
-    let (musicpath, attr, musicdur) = musictask.await??;
-    let musicdur = seconds_to_frames(musicdur);
-    ctx.append_to_description(format!("Music Credits:\n{}\n", attr))
-        .await;
-
-    // create a producer for the music and a playlist that plays the music over and over until we reach the total duration
-    let music_producer = mlt.add_producer(musicpath).to_string();
-    let music_playlist = mlt
-        .add_playlist(
-            iter::repeat(music_producer).scan(duration, |duration, prod| {
-                let len = match duration.checked_sub(musicdur) {
-                    Some(newdur) => {
-                        *duration = newdur;
-                        musicdur
-                    }
-                    None if *duration == 0 => {
-                        return None;
-                    }
-                    None => {
-                        let d = mem::replace(duration, 0);
-                        d
-                    }
-                };
-                Some(PlaylistEntry::Producer {
-                    id: prod,
-                    start: 0,
-                    end: len,
-                })
-            }),
-            iter::once(
-                Filter::new("volume".to_string()).property("max_gain".to_string(), {
-                    // change to taste
-                    "-10dB".to_string()
-                }),
-            ),
-        )
-        .to_string();
+    // pick enough music to cover the video, ducking it under the narration
+    let ducking = ctx
+        .config()
+        .ducking
+        .music_filter(ctx.config().music_volume, &narration);
+    let music = music_bed(&mut mlt, source.as_deref(), duration, ducking, &ctx).await?;
+
+    // captions go on their own track, drawn over the frames
+    let settings = &ctx.config().captions;
+    let caption_playlist = match settings.burn {
+        true => caption_track(&mut mlt, &cues, &basedir, ctx.video_size()).await?,
+        false => None,
+    };
 
     // use both as tracks
-    let main_tractor = mlt
-        .add_tractor_with_transitions(
-            ArrayIter::new([/*blacktrack,*/ music_playlist, frame_playlist]),
-            iter::empty(),
-            iter::once(Transition::new("mix", "0", "1", 0, duration)),
-        )
-        .to_string();
+    let main_tractor = mlt.add_tractor_with_transitions(
+        vec![
+            /*blacktrack.into(),*/ ServiceId::from(music.tractor),
+            frame_playlist.into(),
+        ]
+        .into_iter()
+        .chain(caption_playlist.map(Into::into)),
+        iter::empty(),
+        iter::once(Transition::new("mix", 0, 1, 0, duration))
+            .chain(caption_playlist.map(|_| Transition::new("affine", 1, 2, 0, duration))),
+    );
 
-    // run mlt
-    let outvideo = mlt.run(main_tractor, duration).await?;
+    let (path, video_path) = mlt.save(main_tractor, duration).await?;
 
-    // register the outvideo in the context and return
-    ctx.set_video_path(outvideo).await;
+    let mut caption_files = vec![];
+    if settings.files && !cues.is_empty() {
+        let files = vec![
+            ("srt", captions::to_srt(&cues)),
+            ("vtt", captions::to_vtt(&cues)),
+        ];
+        for (extension, contents) in files {
+            let path = video_path.with_extension(extension);
+            fs::write(&path, contents).await?;
+            caption_files.push(path);
+        }
+    }
+
+    Ok(Project {
+        path,
+        video_path,
+        duration,
+        music_credits: music.credits,
+        music_paths: music.paths,
+        caption_files,
+    })
+}
+
+/// Draw each caption and lay them out on a track, or return `None` if there's nothing to show.
+#[inline]
+async fn caption_track(
+    mlt: &mut Mlt,
+    cues: &[Cue],
+    basedir: &Path,
+    (width, height): (usize, usize),
+) -> crate::Result<Option<PlaylistId>> {
+    let tasks: Vec<_> = cues
+        .iter()
+        .enumerate()
+        .map(|(i, cue)| {
+            let text = cue.text.clone();
+            let path = basedir.join(format!("caption{}.png", i));
+            tokio::spawn(async move {
+                let img = captions::caption_image(&text, width as u32, height as u32).await?;
+                tokio::task::spawn_blocking(move || {
+                    img.save_with_format(&path, image::ImageFormat::Png)?;
+                    crate::Result::Ok(path)
+                })
+                .await?
+            })
+        })
+        .collect();
+    let paths: Vec<PathBuf> = stream::iter(tasks)
+        .then(|task| async move { task.await? })
+        .try_collect()
+        .await?;
+
+    // fill the gaps between captions with blanks
+    let mut entries = vec![];
+    let mut position = 0;
+    for (cue, path) in cues.iter().zip(paths) {
+        let start = seconds_to_frames(cue.start).max(position);
+        let end = seconds_to_frames(cue.end);
+        if end <= start {
+            continue;
+        }
+        if start > position {
+            entries.push(PlaylistEntry::Blank(start - position));
+        }
+        let producer = mlt.add_producer(path);
+        entries.push(PlaylistEntry::Producer {
+            id: producer.into(),
+            start: 0,
+            end: end - start - 1,
+        });
+        position = end;
+    }
 
-    Ok(())
+    Ok(match entries.is_empty() {
+        true => None,
+        false => Some(mlt.add_playlist(entries, iter::empty())),
+    })
 }
@@ -0,0 +1,286 @@
+/*
+ * This file is part of KOTI.
+ *
+ * KOTI is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * KOTI is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Afero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with KOTI.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Lay out the background music as a run of tracks, each one crossfading into the next, that
+//! fades out at the end of the video.
+
+use super::{ducking::volume_keyframes, seconds_to_frames};
+use crate::{
+    context::Context,
+    mlt::{Filter, Mlt, PlaylistEntry, PlaylistId, ProducerId, TractorId, Transition},
+    music::{Music, MusicEntry},
+    probe,
+};
+use std::{iter, path::PathBuf};
+
+/// How long one track takes to fade into the next, in seconds.
+const CROSSFADE: f32 = 3.0;
+/// How long the music takes to fade out at the end of the video, in seconds.
+const FADE_OUT: f32 = 4.0;
+/// Level the music fades from and to, in decibels.
+const SILENT: f32 = -60.0;
+
+/// A track's place in the music bed, in frames.
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Piece {
+    start: usize,
+    length: usize,
+    fade_in: usize,
+    fade_out: usize,
+}
+
+impl Piece {
+    #[inline]
+    fn end(&self) -> usize {
+        self.start + self.length
+    }
+}
+
+/// Where each track plays. Each one starts while the one before it is fading out.
+#[derive(Debug, Default)]
+struct Layout {
+    pieces: Vec<Piece>,
+}
+
+impl Layout {
+    #[inline]
+    fn end(&self) -> usize {
+        self.pieces.last().map_or(0, Piece::end)
+    }
+
+    /// Add a track that is `length` frames long after the others.
+    #[inline]
+    fn push(&mut self, length: usize, crossfade: usize) {
+        let (start, fade_in) = match self.pieces.last_mut() {
+            Some(last) => {
+                // a short track can't spend more than half of itself fading
+                let overlap = crossfade.min(last.length / 2).min(length / 2);
+                last.fade_out = overlap;
+                (last.end() - overlap, overlap)
+            }
+            None => (0, 0),
+        };
+        self.pieces.push(Piece {
+            start,
+            length,
+            fade_in,
+            fade_out: 0,
+        });
+    }
+
+    /// Cut the last track off at the end of the video.
+    #[inline]
+    fn finish(&mut self, duration: usize) {
+        if let Some(last) = self.pieces.last_mut() {
+            last.length = duration.saturating_sub(last.start).min(last.length);
+            last.fade_in = last.fade_in.min(last.length);
+            last.fade_out = 0;
+        }
+    }
+
+    /// The pieces that go on one of the two alternating lanes, and the keyframes for their
+    /// volume, in decibels.
+    #[inline]
+    fn lane(&self, lane: usize) -> (Vec<(usize, Piece)>, Vec<(usize, f32)>) {
+        let pieces: Vec<(usize, Piece)> = self
+            .pieces
+            .iter()
+            .copied()
+            .enumerate()
+            .skip(lane)
+            .step_by(2)
+            .collect();
+
+        let mut keyframes: Vec<(usize, f32)> = vec![];
+        let mut push = |frame: usize, level: f32| match keyframes.last_mut() {
+            Some(last) if last.0 == frame => last.1 = level,
+            _ => keyframes.push((frame, level)),
+        };
+        for (_, piece) in &pieces {
+            push(piece.start, if piece.fade_in > 0 { SILENT } else { 0.0 });
+            push(piece.start + piece.fade_in, 0.0);
+            push(piece.end() - piece.fade_out, 0.0);
+            push(piece.end(), if piece.fade_out > 0 { SILENT } else { 0.0 });
+        }
+        (pieces, keyframes)
+    }
+}
+
+/// The music under a video.
+#[derive(Debug)]
+pub struct MusicBed {
+    pub tractor: TractorId,
+    /// Attribution for each track that was used, without repeats.
+    pub credits: String,
+    /// Each track that was used, without repeats.
+    pub paths: Vec<PathBuf>,
+}
+
+/// Pick tracks until they cover the `duration` frames of the video, and lay them out in a tractor
+/// that crossfades between them, ducks under the narration and fades out at the end.
+#[inline]
+pub async fn music_bed(
+    mlt: &mut Mlt,
+    source: Option<&str>,
+    duration: usize,
+    ducking: Filter,
+    ctx: &Context,
+) -> crate::Result<MusicBed> {
+    let crossfade = seconds_to_frames(CROSSFADE);
+    let mut music = Music::load(ctx).await?;
+
+    let mut layout = Layout::default();
+    let mut tracks: Vec<(MusicEntry, usize)> = vec![];
+    while layout.end() < duration {
+        let track = music.choose_track(source)?.clone();
+        let length = seconds_to_frames(probe::duration(&track.path).await?).max(1);
+        log::info!("Using music track {} ({} frames)", &track.name, length);
+        music.record_use(&track.name);
+        layout.push(length, crossfade);
+        tracks.push((track, length));
+    }
+    layout.finish(duration);
+    music.save(ctx).await?;
+
+    // a track that's used more than once only needs one producer
+    let mut unique: Vec<(&MusicEntry, ProducerId)> = vec![];
+    for (track, length) in &tracks {
+        if !unique.iter().any(|(t, _)| t.name == track.name) {
+            let producer = mlt.add_producer_with_length(track.path.clone(), Some(*length));
+            unique.push((track, producer));
+        }
+    }
+
+    // tracks alternate between two lanes, so each one can fade into the next
+    let lanes: Vec<PlaylistId> = (0..2)
+        .map(|lane| layout.lane(lane))
+        .filter(|(pieces, _)| !pieces.is_empty())
+        .map(|(pieces, keyframes)| {
+            let mut position = 0;
+            let mut entries = vec![];
+            for (i, piece) in pieces {
+                if piece.start > position {
+                    entries.push(PlaylistEntry::Blank(piece.start - position));
+                }
+                let (_, producer) = unique
+                    .iter()
+                    .find(|(t, _)| t.name == tracks[i].0.name)
+                    .expect("every track has a producer");
+                entries.push(PlaylistEntry::Producer {
+                    id: (*producer).into(),
+                    start: 0,
+                    end: piece.length - 1,
+                });
+                position = piece.end();
+            }
+            mlt.add_playlist(entries, iter::once(volume_keyframes(&keyframes)))
+        })
+        .collect();
+
+    let fade_out = duration.saturating_sub(seconds_to_frames(FADE_OUT));
+    let mix = match lanes.len() {
+        2 => Some(Transition::new("mix", 0, 1, 0, duration)),
+        _ => None,
+    };
+    let tractor = mlt.add_tractor_with_transitions(
+        lanes,
+        vec![
+            ducking,
+            volume_keyframes(&[(0, 0.0), (fade_out, 0.0), (duration, SILENT)]),
+        ],
+        mix,
+    );
+
+    Ok(MusicBed {
+        tractor,
+        credits: unique
+            .iter()
+            .map(|(track, _)| track.attribution.trim())
+            .collect::<Vec<_>>()
+            .join("\n"),
+        paths: unique.iter().map(|(track, _)| track.path.clone()).collect(),
+    })
+}
+
+#[test]
+fn test_music_layout() {
+    let mut layout = Layout::default();
+    layout.push(1000, 90);
+    layout.push(1000, 90);
+    layout.push(100, 90);
+    assert_eq!(layout.end(), 1000 + 910 + 50);
+
+    // short tracks don't spend more than half of themselves fading
+    assert_eq!(
+        layout.pieces,
+        vec![
+            Piece {
+                start: 0,
+                length: 1000,
+                fade_in: 0,
+                fade_out: 90
+            },
+            Piece {
+                start: 910,
+                length: 1000,
+                fade_in: 90,
+                fade_out: 50
+            },
+            Piece {
+                start: 1860,
+                length: 100,
+                fade_in: 50,
+                fade_out: 0
+            },
+        ]
+    );
+
+    // the last track is cut off at the end of the video, even partway through fading in
+    layout.finish(1900);
+    assert_eq!(layout.pieces[2].length, 40);
+    assert_eq!(layout.pieces[2].fade_in, 40);
+    layout.pieces[2] = Piece {
+        length: 100,
+        fade_in: 50,
+        ..layout.pieces[2]
+    };
+    layout.finish(1950);
+    assert_eq!(layout.pieces[2].length, 90);
+
+    // the lanes alternate, fading in and out where they overlap
+    let (pieces, keyframes) = layout.lane(0);
+    assert_eq!(
+        pieces.iter().map(|(i, _)| *i).collect::<Vec<_>>(),
+        vec![0, 2]
+    );
+    assert_eq!(
+        keyframes,
+        vec![
+            (0, 0.0),
+            (910, 0.0),
+            (1000, SILENT),
+            (1860, SILENT),
+            (1910, 0.0),
+            (1950, 0.0),
+        ]
+    );
+    let (_, keyframes) = layout.lane(1);
+    assert_eq!(
+        keyframes,
+        vec![(910, SILENT), (1000, 0.0), (1860, 0.0), (1910, SILENT)]
+    );
+}
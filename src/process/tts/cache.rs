@@ -0,0 +1,254 @@
+/*
+ * This file is part of KOTI.
+ *
+ * KOTI is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * KOTI is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Afero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with KOTI.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! A cache of synthesized audio in the data directory, so the same text in the same voice is only
+//! synthesized once.
+
+use super::TtsEngine;
+use sha2::{Digest, Sha256};
+use std::{
+    fmt::Write,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+use tokio::fs;
+
+/// How big the cache can get and how long things stay in it.
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TtsCacheConfig {
+    /// Largest the cache can get, in megabytes. The cache is turned off if this is zero.
+    pub max_size: u64,
+    /// Throw out audio that hasn't been used for this many days, or keep it forever if it's null.
+    pub max_age: Option<u64>,
+}
+
+impl Default for TtsCacheConfig {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            max_size: 512,
+            max_age: Some(30),
+        }
+    }
+}
+
+/// WAV files named after a hash of the engine's cache key and the text they read out. Using a
+/// file counts as touching it, and the files that were used least recently are thrown out first.
+#[derive(Debug, Clone)]
+pub struct TtsCache {
+    dir: PathBuf,
+    max_bytes: u64,
+    max_age: Option<Duration>,
+}
+
+impl TtsCache {
+    /// Get the cache in `dir`, or `None` if it's turned off.
+    #[inline]
+    pub fn new(dir: PathBuf, config: &TtsCacheConfig) -> Option<Self> {
+        match config.max_size {
+            0 => None,
+            max_size => Some(Self {
+                dir,
+                max_bytes: max_size * 1024 * 1024,
+                max_age: config
+                    .max_age
+                    .map(|days| Duration::from_secs(days * 24 * 60 * 60)),
+            }),
+        }
+    }
+
+    /// The key for some text read out by an engine.
+    #[inline]
+    pub fn key(engine: &dyn TtsEngine, text: &str) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(engine.cache_key().as_bytes());
+        hasher.update(b"\n");
+        hasher.update(text.as_bytes());
+
+        hasher
+            .finalize()
+            .iter()
+            .fold(String::with_capacity(64), |mut hex, byte| {
+                let _ = write!(hex, "{:02x}", byte);
+                hex
+            })
+    }
+
+    #[inline]
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.wav", key))
+    }
+
+    /// Copy the audio for `key` to `out`, if there is any. Returns whether it was there.
+    #[inline]
+    pub async fn fetch(&self, key: &str, out: &Path) -> crate::Result<bool> {
+        let path = self.path(key);
+        match fs::copy(&path, out).await {
+            Ok(_) => {}
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e.into()),
+        }
+
+        // mark it as recently used so it's the last to go
+        tokio::task::spawn_blocking(move || {
+            std::fs::File::options()
+                .write(true)
+                .open(&path)?
+                .set_modified(SystemTime::now())
+        })
+        .await??;
+        Ok(true)
+    }
+
+    /// Save the audio at `wav` under `key`.
+    #[inline]
+    pub async fn store(&self, key: &str, wav: &Path) -> crate::Result {
+        fs::create_dir_all(&self.dir).await?;
+
+        // copy it in under another name first, so a half-written file is never used
+        let partial = self.dir.join(format!(
+            "{}.{}.partial",
+            key,
+            wav.file_stem().unwrap_or_default().to_string_lossy()
+        ));
+        fs::copy(wav, &partial).await?;
+        fs::rename(&partial, self.path(key)).await?;
+        Ok(())
+    }
+
+    /// Throw out audio that's too old, and then the least recently used audio until the cache is
+    /// small enough. Returns how many files were removed.
+    #[inline]
+    pub async fn evict(&self) -> crate::Result<usize> {
+        let mut entries = match fs::read_dir(&self.dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e.into()),
+        };
+
+        let now = SystemTime::now();
+        let mut files = vec![];
+        let mut removed = 0;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.extension().map_or(true, |ext| ext != "wav") {
+                continue;
+            }
+
+            let metadata = entry.metadata().await?;
+            let modified = metadata.modified()?;
+            let age = now.duration_since(modified).unwrap_or_default();
+            if self.max_age.map_or(false, |max_age| age > max_age) {
+                fs::remove_file(&path).await?;
+                removed += 1;
+            } else {
+                files.push((modified, metadata.len(), path));
+            }
+        }
+
+        let mut size: u64 = files.iter().map(|(_, len, _)| len).sum();
+        files.sort();
+        for (_, len, path) in files {
+            if size <= self.max_bytes {
+                break;
+            }
+            fs::remove_file(&path).await?;
+            size -= len;
+            removed += 1;
+        }
+
+        Ok(removed)
+    }
+}
+
+#[test]
+fn test_cache() {
+    use super::{EspeakNg, Festival};
+
+    let festival = Festival::new(None, 1.0, 1.0);
+    let key = TtsCache::key(&festival, "Comments");
+    assert_eq!(key.len(), 64);
+    assert_eq!(key, TtsCache::key(&festival, "Comments"));
+    assert_ne!(key, TtsCache::key(&festival, "Comment"));
+    assert_ne!(
+        key,
+        TtsCache::key(&Festival::new(None, 1.5, 1.0), "Comments")
+    );
+    assert_ne!(
+        key,
+        TtsCache::key(&EspeakNg::new(None, 1.0, 1.0), "Comments")
+    );
+
+    let config = TtsCacheConfig {
+        max_size: 0,
+        ..TtsCacheConfig::default()
+    };
+    assert!(TtsCache::new(PathBuf::new(), &config).is_none());
+
+    let dir = std::env::temp_dir().join(format!("koti-tts-cache-test-{}", std::process::id()));
+    let cache = TtsCache {
+        dir: dir.join("cache"),
+        max_bytes: 2500,
+        max_age: Some(Duration::from_secs(60 * 60)),
+    };
+
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("Unable to construct Tokio runtime")
+        .block_on(async {
+            fs::create_dir_all(&dir).await.unwrap();
+            let out = dir.join("tts0.wav");
+            assert!(!cache.fetch("a", &out).await.unwrap());
+
+            // store three files, the first of which was used a while ago
+            for (key, age) in [("a", 60), ("b", 0), ("c", 30)].iter() {
+                let wav = dir.join(format!("{}.wav", key));
+                fs::write(&wav, vec![0; 1000]).await.unwrap();
+                cache.store(key, &wav).await.unwrap();
+
+                std::fs::File::options()
+                    .write(true)
+                    .open(cache.path(key))
+                    .unwrap()
+                    .set_modified(SystemTime::now() - Duration::from_secs(*age))
+                    .unwrap();
+            }
+
+            // using the oldest file saves it from eviction
+            assert!(cache.fetch("a", &out).await.unwrap());
+            assert_eq!(fs::read(&out).await.unwrap().len(), 1000);
+            assert_eq!(cache.evict().await.unwrap(), 1);
+            assert!(cache.path("a").exists());
+            assert!(cache.path("b").exists());
+            assert!(!cache.path("c").exists());
+
+            // anything too old goes regardless of size
+            std::fs::File::options()
+                .write(true)
+                .open(cache.path("b"))
+                .unwrap()
+                .set_modified(SystemTime::now() - Duration::from_secs(2 * 60 * 60))
+                .unwrap();
+            assert_eq!(cache.evict().await.unwrap(), 1);
+            assert!(!cache.path("b").exists());
+        });
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
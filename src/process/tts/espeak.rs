@@ -0,0 +1,78 @@
+/*
+ * This file is part of KOTI.
+ *
+ * KOTI is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * KOTI is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Afero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with KOTI.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use super::{run_command, write_text, SynthesizeFuture, TtsEngine};
+use std::path::Path;
+use tokio::process::Command;
+
+const DEFAULT_VOICE: &str = "en-us";
+const DEFAULT_WORDS_PER_MINUTE: f32 = 175.0;
+const DEFAULT_PITCH: f32 = 50.0;
+
+/// espeak-ng.
+#[derive(Debug)]
+pub struct EspeakNg {
+    voice: String,
+    rate: f32,
+    pitch: f32,
+}
+
+impl EspeakNg {
+    #[inline]
+    pub fn new(voice: Option<String>, rate: f32, pitch: f32) -> Self {
+        Self {
+            voice: voice.unwrap_or_else(|| DEFAULT_VOICE.to_string()),
+            rate,
+            pitch,
+        }
+    }
+}
+
+impl TtsEngine for EspeakNg {
+    #[inline]
+    fn name(&self) -> &str {
+        "espeak_ng"
+    }
+
+    #[inline]
+    fn cache_key(&self) -> String {
+        format!("espeak_ng {} {} {}", &self.voice, self.rate, self.pitch)
+    }
+
+    #[inline]
+    fn synthesize<'a>(&'a self, text: &'a str, out: &'a Path) -> SynthesizeFuture<'a> {
+        Box::pin(async move {
+            let source = write_text(text, out).await?;
+
+            // pitch goes from 0 to 99
+            let mut espeak = Command::new("espeak-ng");
+            espeak
+                .arg("-v")
+                .arg(&self.voice)
+                .arg("-s")
+                .arg(((DEFAULT_WORDS_PER_MINUTE * self.rate) as u32).to_string())
+                .arg("-p")
+                .arg(((DEFAULT_PITCH * self.pitch).min(99.0) as u32).to_string())
+                .arg("-w")
+                .arg(out)
+                .arg("-f")
+                .arg(source);
+
+            run_command("espeak_ng", espeak).await
+        })
+    }
+}
@@ -0,0 +1,77 @@
+/*
+ * This file is part of KOTI.
+ *
+ * KOTI is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * KOTI is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Afero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with KOTI.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use super::{run_command, write_text, SynthesizeFuture, TtsEngine};
+use std::path::Path;
+use tokio::process::Command;
+
+const DEFAULT_VOICE: &str = "cmu_us_slt_arctic_hts";
+
+/// Festival, through its `text2wave` script.
+#[derive(Debug)]
+pub struct Festival {
+    voice: String,
+    rate: f32,
+}
+
+impl Festival {
+    #[inline]
+    pub fn new(voice: Option<String>, rate: f32, pitch: f32) -> Self {
+        if (pitch - 1.0).abs() > f32::EPSILON {
+            log::warn!("Festival voices can't change their pitch, ignoring it");
+        }
+
+        Self {
+            voice: voice.unwrap_or_else(|| DEFAULT_VOICE.to_string()),
+            rate,
+        }
+    }
+}
+
+impl TtsEngine for Festival {
+    #[inline]
+    fn name(&self) -> &str {
+        "festival"
+    }
+
+    #[inline]
+    fn cache_key(&self) -> String {
+        format!("festival {} {}", &self.voice, self.rate)
+    }
+
+    #[inline]
+    fn synthesize<'a>(&'a self, text: &'a str, out: &'a Path) -> SynthesizeFuture<'a> {
+        Box::pin(async move {
+            let source = write_text(text, out).await?;
+
+            // select the voice and stretch how long each sound lasts to get the rate
+            let mut text2wave = Command::new("text2wave");
+            text2wave
+                .arg("-eval")
+                .arg(format!(
+                    "(voice_{})\n(Parameter.set 'Duration_Stretch {})",
+                    &self.voice,
+                    1.0 / self.rate
+                ))
+                .arg(source)
+                .arg("-o")
+                .arg(out);
+
+            run_command("festival", text2wave).await
+        })
+    }
+}
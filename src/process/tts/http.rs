@@ -0,0 +1,103 @@
+/*
+ * This file is part of KOTI.
+ *
+ * KOTI is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * KOTI is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Afero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with KOTI.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use super::{SynthesizeFuture, TtsEngine};
+use crate::util::{http_get, percent_encode};
+use std::path::Path;
+use tokio::{fs::File, io::AsyncWriteExt};
+
+/// A local TTS server, such as Piper's or Coqui's, that returns a WAV file for
+/// `GET <url>?text=...`.
+#[derive(Debug)]
+pub struct HttpEngine {
+    url: String,
+    voice: Option<String>,
+    rate: f32,
+}
+
+impl HttpEngine {
+    #[inline]
+    pub fn new(url: String, voice: Option<String>, rate: f32, pitch: f32) -> Self {
+        if (pitch - 1.0).abs() > f32::EPSILON {
+            log::warn!("The HTTP TTS engine can't change its pitch, ignoring it");
+        }
+
+        Self { url, voice, rate }
+    }
+
+    /// The voice is passed as a speaker ID, and the rate as how long each sound lasts.
+    #[inline]
+    fn request_url(&self, text: &str) -> String {
+        let mut url = format!(
+            "{}{}text={}",
+            &self.url,
+            if self.url.contains('?') { '&' } else { '?' },
+            percent_encode(text)
+        );
+        if let Some(voice) = &self.voice {
+            url.push_str(&format!("&speaker_id={}", percent_encode(voice)));
+        }
+        if (self.rate - 1.0).abs() > f32::EPSILON {
+            url.push_str(&format!("&length_scale={}", 1.0 / self.rate));
+        }
+        url
+    }
+}
+
+impl TtsEngine for HttpEngine {
+    #[inline]
+    fn name(&self) -> &str {
+        "http"
+    }
+
+    #[inline]
+    fn cache_key(&self) -> String {
+        format!("http {} {:?} {}", &self.url, &self.voice, self.rate)
+    }
+
+    #[inline]
+    fn synthesize<'a>(&'a self, text: &'a str, out: &'a Path) -> SynthesizeFuture<'a> {
+        Box::pin(async move {
+            let data = http_get(&self.request_url(text)).await?;
+            if !data.starts_with(b"RIFF") {
+                return Err(crate::Error::TtsFailed(
+                    "http",
+                    None,
+                    Some(String::from_utf8_lossy(&data).into_owned()),
+                ));
+            }
+
+            let mut f = File::create(out).await?;
+            f.write_all(&data).await?;
+            Ok(())
+        })
+    }
+}
+
+#[test]
+fn test_request_url() {
+    let engine = HttpEngine::new(
+        "http://localhost:5002/api/tts".to_string(),
+        Some("p225".to_string()),
+        2.0,
+        1.0,
+    );
+    assert_eq!(
+        engine.request_url("Hello, world & co."),
+        "http://localhost:5002/api/tts?text=Hello%2C%20world%20%26%20co.&speaker_id=p225&length_scale=0.5"
+    );
+}
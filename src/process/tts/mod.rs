@@ -0,0 +1,336 @@
+/*
+ * This file is part of KOTI.
+ *
+ * KOTI is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * KOTI is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Afero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with KOTI.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+mod cache;
+mod espeak;
+mod festival;
+mod http;
+mod normalize;
+mod pico;
+
+pub use cache::{TtsCache, TtsCacheConfig};
+pub use espeak::EspeakNg;
+pub use festival::Festival;
+pub use http::HttpEngine;
+pub use normalize::{clean_text, normalize};
+pub use pico::Pico2Wave;
+
+use crate::{context::Context, probe};
+use std::{
+    collections::HashMap,
+    future::Future,
+    path::{Path, PathBuf},
+    pin::Pin,
+    process::Output,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use tokio::{fs::File, io::AsyncWriteExt, process::Command};
+
+static TTS_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+pub type SynthesizeFuture<'a> = Pin<Box<dyn Future<Output = crate::Result> + Send + 'a>>;
+
+/// Something that can read text out loud.
+pub trait TtsEngine: Send + Sync {
+    /// The kind of engine this is, e.g. "festival".
+    fn name(&self) -> &str;
+
+    /// The engine, voice and settings, which together with the text decide what the audio sounds
+    /// like. Used to look up audio in the cache.
+    fn cache_key(&self) -> String;
+
+    /// Read `text` out into a WAV file at `out`.
+    fn synthesize<'a>(&'a self, text: &'a str, out: &'a Path) -> SynthesizeFuture<'a>;
+}
+
+/// The TTS engines that can be chosen in the channel configuration.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TtsEngineKind {
+    Festival,
+    EspeakNg,
+    Pico2wave,
+    Http,
+}
+
+/// Which TTS engine to use and how it should sound.
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TtsSettings {
+    pub engine: TtsEngineKind,
+    /// Voice to use, or the engine's usual voice if there isn't one.
+    pub voice: Option<String>,
+    /// Voices handed out to the people speaking in a video, e.g. each commenter in a thread. The
+    /// narrator always uses `voice`.
+    pub voices: Vec<String>,
+    /// How fast to talk, where 1.0 is the engine's normal speed.
+    pub rate: f32,
+    /// How high to talk, where 1.0 is the engine's normal pitch.
+    pub pitch: f32,
+    /// Address of the server for the HTTP engine.
+    pub url: Option<String>,
+}
+
+impl Default for TtsSettings {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            engine: TtsEngineKind::Festival,
+            voice: None,
+            voices: vec![],
+            rate: 1.0,
+            pitch: 1.0,
+            url: None,
+        }
+    }
+}
+
+impl TtsSettings {
+    /// Make sure the settings make sense for the engine.
+    #[inline]
+    pub fn validate(&self) -> crate::Result {
+        let invalid = |msg: String| Err(crate::Error::InvalidConfig(msg));
+
+        for (name, value) in [("rate", self.rate), ("pitch", self.pitch)].iter() {
+            if !value.is_finite() || !(0.25..=4.0).contains(value) {
+                return invalid(format!(
+                    "TTS {} {} is outside of the range 0.25 to 4",
+                    name, value
+                ));
+            }
+        }
+
+        // festival voices end up in a scheme expression, so keep them to a plain identifier
+        let allowed = |c: char| match self.engine {
+            TtsEngineKind::Festival => c.is_ascii_alphanumeric() || c == '_',
+            _ => c.is_ascii_alphanumeric() || "_-+.".contains(c),
+        };
+        for voice in self.voice.iter().chain(&self.voices) {
+            if voice.is_empty() || !voice.chars().all(allowed) {
+                return invalid(format!("\"{}\" is not a valid TTS voice", voice));
+            }
+        }
+
+        match (self.engine, &self.url) {
+            (TtsEngineKind::Http, None) => invalid("the HTTP TTS engine needs a url".to_string()),
+            (TtsEngineKind::Http, Some(url))
+                if !url.starts_with("http://") && !url.starts_with("https://") =>
+            {
+                invalid(format!("\"{}\" is not an HTTP url", url))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Create the engine these settings describe.
+    #[inline]
+    pub fn engine(&self) -> crate::Result<Box<dyn TtsEngine>> {
+        self.validate()?;
+
+        let voice = self.voice.clone();
+        Ok(match self.engine {
+            TtsEngineKind::Festival => Box::new(Festival::new(voice, self.rate, self.pitch)),
+            TtsEngineKind::EspeakNg => Box::new(EspeakNg::new(voice, self.rate, self.pitch)),
+            TtsEngineKind::Pico2wave => Box::new(Pico2Wave::new(voice, self.rate, self.pitch)),
+            TtsEngineKind::Http => Box::new(HttpEngine::new(
+                self.url.clone().unwrap_or_default(),
+                voice,
+                self.rate,
+                self.pitch,
+            )),
+        })
+    }
+}
+
+/// Hands out voices from the pool to speakers in the order they first speak, so each speaker
+/// keeps the same voice throughout a video. Once the pool runs out, it starts over.
+#[derive(Debug, Default)]
+pub struct VoicePool {
+    size: usize,
+    speakers: HashMap<String, usize>,
+}
+
+impl VoicePool {
+    #[inline]
+    pub fn new(size: usize) -> Self {
+        Self {
+            size,
+            speakers: HashMap::new(),
+        }
+    }
+
+    /// Get the index of the speaker's voice in the pool, or `None` if they should use the
+    /// narrator's voice.
+    #[inline]
+    pub fn voice_for(&mut self, speaker: Option<&str>) -> Option<usize> {
+        let speaker = speaker?;
+        if self.size == 0 {
+            return None;
+        }
+
+        let next = self.speakers.len() % self.size;
+        Some(*self.speakers.entry(speaker.to_string()).or_insert(next))
+    }
+}
+
+/// Write the text out next to where the audio is going, for engines that read from a file.
+#[inline]
+async fn write_text(text: &str, out: &Path) -> crate::Result<PathBuf> {
+    let source = out.with_extension("txt");
+    let mut f = File::create(&source).await?;
+    f.write_all(text.as_bytes()).await?;
+    Ok(source)
+}
+
+/// Run a command-line TTS engine, logging anything it prints.
+#[inline]
+async fn run_command(engine: &'static str, mut command: Command) -> crate::Result {
+    log::info!("Running TTS command: {:?}", &command);
+
+    // wait for the command to go
+    let Output {
+        status,
+        stdout,
+        stderr,
+    } = command.output().await?;
+    let stderr = String::from_utf8(stderr).ok();
+
+    // if the exit status is bad, error out
+    if !status.success() {
+        return Err(crate::Error::TtsFailed(engine, status.code(), stderr));
+    }
+
+    // output the stderr and stdout
+    match stderr {
+        Some(stderr) if stderr.is_empty() => (),
+        Some(stderr) => {
+            log::error!("{} stderr: {}", engine, stderr);
+        }
+        None => {
+            log::error!("{} stderr: <not utf-8>", engine);
+        }
+    }
+
+    match String::from_utf8(stdout) {
+        Ok(stdout) if stdout.is_empty() => (),
+        Ok(stdout) => {
+            log::warn!("{} stdout: {}", engine, stdout);
+        }
+        Err(_) => {
+            log::warn!("{} stdout: <not utf-8>", engine);
+        }
+    }
+
+    Ok(())
+}
+
+#[inline]
+pub async fn create_tts(
+    s: &str,
+    engine: &dyn TtsEngine,
+    cache: Option<&TtsCache>,
+    ctx: &Context,
+) -> crate::Result<(PathBuf, f32)> {
+    // engines trip over markdown, links and anything that isn't ascii
+    let s = normalize(s);
+
+    let basedir = ctx.basedir().await;
+    let outpath = basedir.join(format!(
+        "tts{}.wav",
+        TTS_COUNTER.fetch_add(1, Ordering::SeqCst)
+    ));
+
+    let key = TtsCache::key(engine, &s);
+    let cached = match cache {
+        Some(cache) => cache.fetch(&key, &outpath).await.unwrap_or_else(|e| {
+            log::warn!("Unable to read TTS audio from the cache: {}", e);
+            false
+        }),
+        None => false,
+    };
+    if !cached {
+        engine.synthesize(&s, &outpath).await?;
+        if let Some(cache) = cache {
+            if let Err(e) = cache.store(&key, &outpath).await {
+                log::warn!("Unable to save TTS audio to the cache: {}", e);
+            }
+        }
+    }
+
+    // get the duration of the file
+    let duration = probe::duration(&outpath).await?;
+
+    Ok((outpath, duration))
+}
+
+#[test]
+fn test_settings() {
+    TtsSettings::default().validate().unwrap();
+    assert_eq!(TtsSettings::default().engine().unwrap().name(), "festival");
+
+    let settings: TtsSettings = serde_json::from_str(
+        r#"{"engine": "espeak_ng", "voice": "en-us+f3", "rate": 1.25, "pitch": 0.8}"#,
+    )
+    .unwrap();
+    assert_eq!(settings.engine().unwrap().name(), "espeak_ng");
+
+    // festival voices end up in scheme
+    let settings = TtsSettings {
+        voice: Some("kal_diphone) (quit".to_string()),
+        ..TtsSettings::default()
+    };
+    settings.validate().unwrap_err();
+
+    let settings = TtsSettings {
+        rate: 10.0,
+        ..TtsSettings::default()
+    };
+    settings.validate().unwrap_err();
+
+    let settings = TtsSettings {
+        engine: TtsEngineKind::Http,
+        ..TtsSettings::default()
+    };
+    settings.validate().unwrap_err();
+    let settings = TtsSettings {
+        url: Some("http://localhost:5000/api/tts".to_string()),
+        ..settings
+    };
+    assert_eq!(settings.engine().unwrap().name(), "http");
+}
+
+#[test]
+fn test_voice_pool() {
+    let mut pool = VoicePool::new(2);
+    let voices: Vec<_> = [
+        None,
+        Some("op"),
+        Some("a"),
+        Some("op"),
+        Some("b"),
+        Some("a"),
+    ]
+    .iter()
+    .map(|speaker| pool.voice_for(*speaker))
+    .collect();
+    assert_eq!(
+        voices,
+        vec![None, Some(0), Some(1), Some(0), Some(0), Some(1)]
+    );
+
+    assert_eq!(VoicePool::new(0).voice_for(Some("op")), None);
+}
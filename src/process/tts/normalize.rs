@@ -0,0 +1,608 @@
+/*
+ * This file is part of KOTI.
+ *
+ * KOTI is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * KOTI is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Afero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with KOTI.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Turn the text people write on the internet into something a TTS engine can read out.
+
+use crate::util::{decode_html_entities, strip_html_tags};
+use once_cell::sync::Lazy;
+use regex::{Captures, Regex};
+
+const ONES: [&str; 20] = [
+    "zero",
+    "one",
+    "two",
+    "three",
+    "four",
+    "five",
+    "six",
+    "seven",
+    "eight",
+    "nine",
+    "ten",
+    "eleven",
+    "twelve",
+    "thirteen",
+    "fourteen",
+    "fifteen",
+    "sixteen",
+    "seventeen",
+    "eighteen",
+    "nineteen",
+];
+const TENS: [&str; 10] = [
+    "", "", "twenty", "thirty", "forty", "fifty", "sixty", "seventy", "eighty", "ninety",
+];
+const SCALES: [(u64, &str); 6] = [
+    (1_000_000_000_000_000_000, "quintillion"),
+    (1_000_000_000_000_000, "quadrillion"),
+    (1_000_000_000_000, "trillion"),
+    (1_000_000_000, "billion"),
+    (1_000_000, "million"),
+    (1_000, "thousand"),
+];
+const MONTHS: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+/// Abbreviations that people use on reddit, and what they stand for.
+const ABBREVIATIONS: &[(&str, &str)] = &[
+    ("AFAIK", "as far as I know"),
+    ("AMA", "ask me anything"),
+    ("ELI5", "explain like I'm five"),
+    ("FWIW", "for what it's worth"),
+    ("IANAL", "I am not a lawyer"),
+    ("IIRC", "if I remember correctly"),
+    ("IMHO", "in my humble opinion"),
+    ("IMO", "in my opinion"),
+    ("IRL", "in real life"),
+    ("LPT", "life pro tip"),
+    ("NSFW", "not safe for work"),
+    ("OP", "O P"),
+    ("PSA", "P S A"),
+    ("SMH", "shaking my head"),
+    ("TBH", "to be honest"),
+    ("TIL", "today I learned"),
+    ("TLDR", "too long, didn't read"),
+    ("YMMV", "your mileage may vary"),
+];
+
+/// Shorthand that isn't a word on its own.
+const SHORTHAND: &[(&str, &str)] = &[
+    ("w/o", "without"),
+    ("w/", "with"),
+    ("b/c", "because"),
+    ("e.g.", "for example"),
+    ("i.e.", "that is"),
+    ("etc.", "etcetera"),
+    ("vs.", "versus"),
+];
+
+/// Symbols that are read out wherever they are.
+const SYMBOLS: &[(&str, &str)] = &[("&", " and "), ("@", " at ")];
+
+/// Normalize text before it is read out: strip markdown, expand abbreviations, describe links and
+/// usernames, read out numbers, and transliterate anything that isn't ASCII.
+#[inline]
+pub fn normalize(text: &str) -> String {
+    let text = decode_html_entities(text);
+    let text = strip_html_tags(&text);
+    let text = strip_markdown(&text);
+    let text = links_and_names(&text);
+    let text = abbreviations(&text);
+    let text = numbers(&text);
+    // emoji would be read out by name, e.g. "joy", so leave them out
+    let text: String = text.chars().filter(|c| !is_emoji(*c)).collect();
+    let text = deunicode::deunicode(&text);
+
+    // tidy up the whitespace left behind
+    static SPACE_BEFORE_PUNCTUATION: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r"\s+([.,!?;:])").expect("Regex failed to compile"));
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    SPACE_BEFORE_PUNCTUATION
+        .replace_all(&text, "$1")
+        .into_owned()
+}
+
+/// Strip the markup out of text without changing the words, for showing it as captions.
+#[inline]
+pub fn clean_text(text: &str) -> String {
+    let text = decode_html_entities(text);
+    let text = strip_html_tags(&text);
+    strip_markdown(&text)
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[inline]
+fn is_emoji(c: char) -> bool {
+    matches!(c as u32,
+        0x1F000..=0x1FAFF // pictographs, emoticons, flags and skin tones
+        | 0x2600..=0x27BF // miscellaneous symbols and dingbats
+        | 0x2B00..=0x2BFF // arrows and stars
+        | 0xFE0E..=0xFE0F // variation selectors
+        | 0x200D // zero width joiner
+    )
+}
+
+#[inline]
+fn strip_markdown(text: &str) -> String {
+    static LINK: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r"\[([^\]]*)\]\([^)]*\)").expect("Regex failed to compile"));
+    static SPOILER: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r">!(.*?)!<").expect("Regex failed to compile"));
+    static EMPHASIS: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r"\*{1,3}|_{2,3}|~~|`+|\^\(|\^").expect("Regex failed to compile"));
+    static LINE_START: Lazy<Regex> = Lazy::new(|| {
+        Regex::new(r"^\s*(?:#{1,6}|>+|[-*+]|\d+[.)])\s+").expect("Regex failed to compile")
+    });
+
+    let text = LINK.replace_all(text, "$1");
+    let text = SPOILER.replace_all(&text, "$1");
+
+    let lines: Vec<String> = text
+        .lines()
+        .map(|line| LINE_START.replace(line, ""))
+        .map(|line| EMPHASIS.replace_all(&line, "").trim().to_string())
+        .filter(|line| !line.is_empty())
+        .collect();
+
+    // lines are read out as one, so give each a pause at the end
+    let last = lines.len().saturating_sub(1);
+    lines
+        .into_iter()
+        .enumerate()
+        .map(|(i, mut line)| {
+            if i < last && !line.ends_with(|c: char| ".!?,;:".contains(c)) {
+                line.push('.');
+            }
+            line
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[inline]
+fn links_and_names(text: &str) -> String {
+    static URL: Lazy<Regex> = Lazy::new(|| {
+        Regex::new(r"(?i)\b(?:https?://(?:www\.)?|www\.)((?:[a-z0-9-]+\.)+[a-z]{2,})(?:[/?#]\S*)?")
+            .expect("Regex failed to compile")
+    });
+    static USER: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r"(?:\B/|\b)u/([\w-]+)").expect("Regex failed to compile"));
+    static SUBREDDIT: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r"(?:\B/|\b)r/(\w+)").expect("Regex failed to compile"));
+
+    let text = URL.replace_all(text, |caps: &Captures<'_>| {
+        // the end of the sentence can get caught up in the link
+        let trailing = caps[0]
+            .chars()
+            .last()
+            .filter(|c| ".,!?;:)".contains(*c))
+            .map(String::from)
+            .unwrap_or_default();
+        format!("a link to {}{}", caps[1].replace('.', " dot "), trailing)
+    });
+    let text = USER.replace_all(&text, |caps: &Captures<'_>| {
+        format!("user {}", caps[1].replace(|c| c == '_' || c == '-', " "))
+    });
+    SUBREDDIT.replace_all(&text, "r slash $1").into_owned()
+}
+
+#[inline]
+fn abbreviations(text: &str) -> String {
+    static ABBREVIATION: Lazy<Regex> = Lazy::new(|| {
+        let words: Vec<&str> = ABBREVIATIONS
+            .iter()
+            .map(|(abbreviation, _)| *abbreviation)
+            .collect();
+        Regex::new(&format!(r"\b(?:TL;DR|{})\b", words.join("|"))).expect("Regex failed to compile")
+    });
+
+    let text = ABBREVIATION.replace_all(text, |caps: &Captures<'_>| {
+        let word = caps[0].replace(';', "");
+        ABBREVIATIONS
+            .iter()
+            .find(|(abbreviation, _)| *abbreviation == word)
+            .map_or(word, |(_, expansion)| expansion.to_string())
+    });
+
+    // shorthand only counts on its own, so "how/why" is left alone
+    static SHORTHAND_WORD: Lazy<Regex> = Lazy::new(|| {
+        let words: Vec<String> = SHORTHAND
+            .iter()
+            .map(|(shorthand, _)| regex::escape(shorthand))
+            .collect();
+        Regex::new(&format!(r"(^|\s)({})(\s|$|[,;:!?)])", words.join("|")))
+            .expect("Regex failed to compile")
+    });
+    let text = SHORTHAND_WORD.replace_all(&text, |caps: &Captures<'_>| {
+        let expansion = SHORTHAND
+            .iter()
+            .find(|(shorthand, _)| *shorthand == &caps[2])
+            .map_or(&caps[2], |(_, expansion)| *expansion);
+        format!("{}{}{}", &caps[1], expansion, &caps[3])
+    });
+
+    SYMBOLS
+        .iter()
+        .fold(text.into_owned(), |text, (symbol, expansion)| {
+            text.replace(symbol, expansion)
+        })
+}
+
+#[inline]
+fn numbers(text: &str) -> String {
+    static CURRENCY: Lazy<Regex> = Lazy::new(|| {
+        Regex::new(r"([$£€])(\d{1,3}(?:,\d{3})+|\d+)(?:\.(\d{1,2}))?(?:\s?([kK]\b|thousand|million|billion))?")
+            .expect("Regex failed to compile")
+    });
+    static ISO_DATE: Lazy<Regex> = Lazy::new(|| {
+        Regex::new(r"\b(\d{4})-(\d{1,2})-(\d{1,2})\b").expect("Regex failed to compile")
+    });
+    static US_DATE: Lazy<Regex> = Lazy::new(|| {
+        Regex::new(r"\b(\d{1,2})/(\d{1,2})/(\d{4})\b").expect("Regex failed to compile")
+    });
+    static TIME: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r"\b(\d{1,2}):(\d{2})\b").expect("Regex failed to compile"));
+    static PERCENT: Lazy<Regex> = Lazy::new(|| {
+        Regex::new(r"(\d{1,3}(?:,\d{3})+|\d+)(?:\.(\d+))?\s?%").expect("Regex failed to compile")
+    });
+    static ORDINAL: Lazy<Regex> = Lazy::new(|| {
+        Regex::new(r"\b(\d{1,3}(?:,\d{3})+|\d+)(?:st|nd|rd|th)\b").expect("Regex failed to compile")
+    });
+    static YEAR: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r"\b(1[1-9]\d\d|20\d\d)(s?)\b").expect("Regex failed to compile"));
+    static NUMBER: Lazy<Regex> = Lazy::new(|| {
+        Regex::new(r"(\d{1,3}(?:,\d{3})+|\d+)(?:\.(\d+))?").expect("Regex failed to compile")
+    });
+
+    let text = CURRENCY.replace_all(text, |caps: &Captures<'_>| {
+        let (unit, units, cent, cents) = match &caps[1] {
+            "$" => ("dollar", "dollars", "cent", "cents"),
+            "£" => ("pound", "pounds", "penny", "pence"),
+            _ => ("euro", "euros", "cent", "cents"),
+        };
+        let amount = integer_words(&caps[2]);
+        match caps.get(4).map(|m| m.as_str()) {
+            Some(scale) => {
+                let scale = match scale {
+                    "k" | "K" => "thousand",
+                    scale => scale,
+                };
+                let amount = match caps.get(3) {
+                    Some(fraction) => {
+                        format!("{} point {}", amount, digit_words(fraction.as_str()))
+                    }
+                    None => amount,
+                };
+                format!("{} {} {}", amount, scale, units)
+            }
+            None => {
+                let whole = if &caps[2] == "1" { unit } else { units };
+                match caps.get(3).map(|m| format!("{:0<2}", m.as_str())) {
+                    Some(fraction) if fraction != "00" => {
+                        let fraction: u64 = fraction.parse().unwrap_or(0);
+                        format!(
+                            "{} {} and {} {}",
+                            amount,
+                            whole,
+                            number_words(fraction),
+                            if fraction == 1 { cent } else { cents }
+                        )
+                    }
+                    _ => format!("{} {}", amount, whole),
+                }
+            }
+        }
+    });
+
+    let date = |year: &str, month: &str, day: &str| -> Option<String> {
+        let month: usize = month.parse().ok()?;
+        let day: u64 = day.parse().ok()?;
+        if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+            return None;
+        }
+        Some(format!(
+            "{} {}, {}",
+            MONTHS[month - 1],
+            ordinal_words(day),
+            year_words(year.parse().ok()?)
+        ))
+    };
+    let text = ISO_DATE.replace_all(&text, |caps: &Captures<'_>| {
+        date(&caps[1], &caps[2], &caps[3]).unwrap_or_else(|| caps[0].to_string())
+    });
+    let text = US_DATE.replace_all(&text, |caps: &Captures<'_>| {
+        date(&caps[3], &caps[1], &caps[2]).unwrap_or_else(|| caps[0].to_string())
+    });
+
+    let text = TIME.replace_all(&text, |caps: &Captures<'_>| {
+        let hours: u64 = caps[1].parse().unwrap_or(0);
+        let minutes: u64 = caps[2].parse().unwrap_or(0);
+        match minutes {
+            _ if hours > 23 || minutes > 59 => caps[0].to_string(),
+            0 => format!("{} o'clock", number_words(hours)),
+            1..=9 => format!("{} oh {}", number_words(hours), number_words(minutes)),
+            _ => format!("{} {}", number_words(hours), number_words(minutes)),
+        }
+    });
+
+    let text = PERCENT.replace_all(&text, |caps: &Captures<'_>| {
+        format!(
+            "{} percent",
+            decimal_words(&caps[1], caps.get(2).map(|m| m.as_str()))
+        )
+    });
+    let text = ORDINAL.replace_all(&text, |caps: &Captures<'_>| {
+        match caps[1].replace(',', "").parse() {
+            Ok(n) => ordinal_words(n),
+            Err(_) => caps[0].to_string(),
+        }
+    });
+    let text = YEAR.replace_all(&text, |caps: &Captures<'_>| {
+        let year = year_words(caps[1].parse().unwrap_or(0));
+        match &caps[2] {
+            // "the nineteen nineties"
+            "s" => match year.strip_suffix('y') {
+                Some(stem) => format!("{}ies", stem),
+                None => format!("{}s", year),
+            },
+            _ => year,
+        }
+    });
+    NUMBER
+        .replace_all(&text, |caps: &Captures<'_>| {
+            decimal_words(&caps[1], caps.get(2).map(|m| m.as_str()))
+        })
+        .into_owned()
+}
+
+/// Read out a whole number, which may have commas in it.
+#[inline]
+fn integer_words(digits: &str) -> String {
+    match digits.replace(',', "").parse() {
+        Ok(n) => number_words(n),
+        // too big to be read out as a number
+        Err(_) => digit_words(digits),
+    }
+}
+
+#[inline]
+fn decimal_words(whole: &str, fraction: Option<&str>) -> String {
+    match fraction {
+        Some(fraction) => format!("{} point {}", integer_words(whole), digit_words(fraction)),
+        None => integer_words(whole),
+    }
+}
+
+/// Read out each digit on its own.
+#[inline]
+fn digit_words(digits: &str) -> String {
+    digits
+        .chars()
+        .filter_map(|c| c.to_digit(10))
+        .map(|d| ONES[d as usize])
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[inline]
+pub fn number_words(n: u64) -> String {
+    if n < 20 {
+        return ONES[n as usize].to_string();
+    }
+    if n < 100 {
+        return match n % 10 {
+            0 => TENS[(n / 10) as usize].to_string(),
+            ones => format!("{}-{}", TENS[(n / 10) as usize], ONES[ones as usize]),
+        };
+    }
+    if n < 1000 {
+        return match n % 100 {
+            0 => format!("{} hundred", ONES[(n / 100) as usize]),
+            rest => format!(
+                "{} hundred {}",
+                ONES[(n / 100) as usize],
+                number_words(rest)
+            ),
+        };
+    }
+
+    let (scale, name) = SCALES
+        .iter()
+        .find(|(scale, _)| n >= *scale)
+        .copied()
+        .expect("numbers over a thousand have a scale");
+    match n % scale {
+        0 => format!("{} {}", number_words(n / scale), name),
+        rest => format!(
+            "{} {} {}",
+            number_words(n / scale),
+            name,
+            number_words(rest)
+        ),
+    }
+}
+
+#[inline]
+pub fn ordinal_words(n: u64) -> String {
+    let words = number_words(n);
+    let irregular = [
+        ("one", "first"),
+        ("two", "second"),
+        ("three", "third"),
+        ("five", "fifth"),
+        ("eight", "eighth"),
+        ("nine", "ninth"),
+        ("twelve", "twelfth"),
+    ];
+
+    // only the last word changes, e.g. "twenty-first"
+    let split = words.rfind(|c| c == ' ' || c == '-').map_or(0, |i| i + 1);
+    let (start, last) = words.split_at(split);
+    match irregular.iter().find(|(cardinal, _)| *cardinal == last) {
+        Some((_, ordinal)) => format!("{}{}", start, ordinal),
+        None => match last.strip_suffix('y') {
+            Some(stem) => format!("{}{}ieth", start, stem),
+            None => format!("{}th", words),
+        },
+    }
+}
+
+/// Read out a year the way people say them, e.g. "nineteen oh five".
+#[inline]
+pub fn year_words(year: u64) -> String {
+    let (century, rest) = (year / 100, year % 100);
+    match (century, rest) {
+        _ if year < 1000 || year >= 10000 => number_words(year),
+        (_, 0) if century % 10 == 0 => number_words(year),
+        (20, 1..=9) => number_words(year),
+        (_, 0) => format!("{} hundred", number_words(century)),
+        (_, 1..=9) => format!("{} oh {}", number_words(century), number_words(rest)),
+        _ => format!("{} {}", number_words(century), number_words(rest)),
+    }
+}
+
+#[test]
+fn test_number_words() {
+    let cases: &[(u64, &str)] = &[
+        (0, "zero"),
+        (13, "thirteen"),
+        (40, "forty"),
+        (42, "forty-two"),
+        (100, "one hundred"),
+        (305, "three hundred five"),
+        (1000, "one thousand"),
+        (
+            1_234_567,
+            "one million two hundred thirty-four thousand five hundred sixty-seven",
+        ),
+    ];
+    for (n, expected) in cases {
+        assert_eq!(number_words(*n), *expected, "reading {}", n);
+    }
+
+    let cases: &[(u64, &str)] = &[
+        (1, "first"),
+        (2, "second"),
+        (12, "twelfth"),
+        (20, "twentieth"),
+        (21, "twenty-first"),
+        (100, "one hundredth"),
+    ];
+    for (n, expected) in cases {
+        assert_eq!(ordinal_words(*n), *expected, "reading {}", n);
+    }
+
+    let cases: &[(u64, &str)] = &[
+        (1905, "nineteen oh five"),
+        (1999, "nineteen ninety-nine"),
+        (1900, "nineteen hundred"),
+        (2000, "two thousand"),
+        (2008, "two thousand eight"),
+        (2021, "twenty twenty-one"),
+    ];
+    for (n, expected) in cases {
+        assert_eq!(year_words(*n), *expected, "reading {}", n);
+    }
+}
+
+#[test]
+fn test_normalize() {
+    let cases: &[(&str, &str)] = &[
+        // reddit abbreviations
+        (
+            "TIL that honey never spoils",
+            "today I learned that honey never spoils",
+        ),
+        (
+            "TL;DR: IMO it's fine",
+            "too long, didn't read: in my opinion it's fine",
+        ),
+        ("Thanks OP!", "Thanks O P!"),
+        ("Tea w/ milk & sugar", "Tea with milk and sugar"),
+        (
+            "It's cheap b/c it's old, vs. new",
+            "It's cheap because it's old, versus new",
+        ),
+        (
+            "Ask how/why, and/or now/then",
+            "Ask how/why, and/or now/then",
+        ),
+        ("The rvs. and w/o", "The rvs. and without"),
+        // links and usernames
+        (
+            "See https://www.example.com/some/page?x=1.",
+            "See a link to example dot com.",
+        ),
+        (
+            "Ask /u/cool_guy_42 about it",
+            "Ask user cool guy forty-two about it",
+        ),
+        ("Post it to r/AskReddit", "Post it to r slash AskReddit"),
+        // markdown
+        (
+            "This is **really** [important](https://example.com)",
+            "This is really important",
+        ),
+        ("> quoted\n\n* one\n* two", "quoted. one. two"),
+        (
+            "It was >!Bruce Willis!< all along",
+            "It was Bruce Willis all along",
+        ),
+        // numbers, dates and currency
+        ("It cost $1,000", "It cost one thousand dollars"),
+        ("It cost $1.50", "It cost one dollar and fifty cents"),
+        ("Worth $5k", "Worth five thousand dollars"),
+        (
+            "About 3.5% of people",
+            "About three point five percent of people",
+        ),
+        ("She came 2nd", "She came second"),
+        ("Back in 1999", "Back in nineteen ninety-nine"),
+        ("Music from the 1980s", "Music from the nineteen eighties"),
+        (
+            "On 2021-05-04 at 5:30",
+            "On May fourth, twenty twenty-one at five thirty",
+        ),
+        (
+            "Over 1,234 upvotes",
+            "Over one thousand two hundred thirty-four upvotes",
+        ),
+        // unicode and html
+        ("Café Zoë – “great”", "Cafe Zoe - \"great\""),
+        ("lol 😂 that's great 👍🏽", "lol that's great"),
+        ("Fish &amp; chips", "Fish and chips"),
+        ("<b>bold</b> move", "bold move"),
+    ];
+
+    for (input, expected) in cases {
+        assert_eq!(normalize(input), *expected, "normalizing {:?}", input);
+    }
+}
@@ -0,0 +1,90 @@
+/*
+ * This file is part of KOTI.
+ *
+ * KOTI is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * KOTI is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Afero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with KOTI.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use super::{run_command, SynthesizeFuture, TtsEngine};
+use std::path::Path;
+use tokio::process::Command;
+
+const DEFAULT_VOICE: &str = "en-US";
+
+/// SVOX Pico, through `pico2wave`.
+#[derive(Debug)]
+pub struct Pico2Wave {
+    language: String,
+    rate: f32,
+    pitch: f32,
+}
+
+impl Pico2Wave {
+    #[inline]
+    pub fn new(voice: Option<String>, rate: f32, pitch: f32) -> Self {
+        Self {
+            language: voice.unwrap_or_else(|| DEFAULT_VOICE.to_string()),
+            rate,
+            pitch,
+        }
+    }
+
+    /// Pico takes the rate and pitch as markup around the text, in percent.
+    #[inline]
+    fn markup(&self, text: &str) -> String {
+        let text = text.replace('<', " ").replace('>', " ");
+        format!(
+            "<speed level=\"{}\"><pitch level=\"{}\">{}</pitch></speed>",
+            (self.rate * 100.0) as u32,
+            (self.pitch * 100.0) as u32,
+            text
+        )
+    }
+}
+
+impl TtsEngine for Pico2Wave {
+    #[inline]
+    fn name(&self) -> &str {
+        "pico2wave"
+    }
+
+    #[inline]
+    fn cache_key(&self) -> String {
+        format!("pico2wave {} {} {}", &self.language, self.rate, self.pitch)
+    }
+
+    #[inline]
+    fn synthesize<'a>(&'a self, text: &'a str, out: &'a Path) -> SynthesizeFuture<'a> {
+        Box::pin(async move {
+            // pico2wave only takes the text as an argument
+            let mut pico = Command::new("pico2wave");
+            pico.arg("-l")
+                .arg(&self.language)
+                .arg("-w")
+                .arg(out)
+                .arg("--")
+                .arg(self.markup(text));
+
+            run_command("pico2wave", pico).await
+        })
+    }
+}
+
+#[test]
+fn test_markup() {
+    let pico = Pico2Wave::new(None, 1.5, 0.8);
+    assert_eq!(
+        pico.markup("a <b> c"),
+        "<speed level=\"150\"><pitch level=\"80\">a  b  c</pitch></speed>"
+    );
+}
@@ -0,0 +1,447 @@
+/*
+ * This file is part of KOTI.
+ *
+ * KOTI is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * KOTI is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Afero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with KOTI.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Reddit frame source that uses the JSON API instead of driving a browser.
+
+use super::RedditTextParams;
+use crate::{
+    card::{save_card, Card, Theme},
+    context::Context,
+    filter::{filter_pass, filter_text},
+    frame::Frame,
+    frame_source::{FrameSource, ProduceFuture, SourceOutput, VideoMetadata},
+    util::{self, http_get},
+};
+use futures_lite::stream::{self, StreamExt};
+use nanorand::{tls_rng, RNG};
+use std::{
+    path::Path,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const REDDIT_URL: &str = "https://www.reddit.com";
+
+static CARD_NUMBER: AtomicUsize = AtomicUsize::new(0);
+
+/// A post on a subreddit.
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct Post {
+    pub id: String,
+    pub title: String,
+    pub author: String,
+    pub score: i64,
+    #[serde(default)]
+    pub selftext: String,
+    pub created_utc: f64,
+    #[serde(default)]
+    pub stickied: bool,
+    #[serde(default)]
+    pub over_18: bool,
+}
+
+/// A comment, along with the replies to it.
+#[derive(Debug, Clone)]
+pub struct Comment {
+    pub author: String,
+    pub body: String,
+    pub score: i64,
+    pub created_utc: f64,
+    pub replies: Vec<Comment>,
+}
+
+impl Post {
+    /// Undo the escaping reddit does to `&`, `<` and `>` in the text of the post.
+    #[inline]
+    fn decoded(mut self) -> Self {
+        self.title = util::decode_html_entities(&self.title).into_owned();
+        self.selftext = util::decode_html_entities(&self.selftext).into_owned();
+        self
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct Listing {
+    data: ListingData,
+}
+
+#[derive(serde::Deserialize)]
+struct ListingData {
+    children: Vec<Thing>,
+}
+
+#[derive(serde::Deserialize)]
+struct Thing {
+    kind: String,
+    data: serde_json::Value,
+}
+
+#[derive(serde::Deserialize)]
+struct RawComment {
+    author: String,
+    body: String,
+    score: i64,
+    created_utc: f64,
+    // reddit uses an empty string when there are no replies
+    #[serde(default)]
+    replies: serde_json::Value,
+}
+
+impl From<RawComment> for Comment {
+    #[inline]
+    fn from(raw: RawComment) -> Self {
+        let RawComment {
+            author,
+            body,
+            score,
+            created_utc,
+            replies,
+        } = raw;
+        let replies = match replies {
+            replies @ serde_json::Value::Object(_) => serde_json::from_value(replies)
+                .map(comments_in)
+                .unwrap_or_default(),
+            _ => vec![],
+        };
+
+        Self {
+            author,
+            body: util::decode_html_entities(&body).into_owned(),
+            score,
+            created_utc,
+            replies,
+        }
+    }
+}
+
+#[inline]
+fn comments_in(listing: Listing) -> Vec<Comment> {
+    things_of_kind::<RawComment>(listing, "t1")
+        .map(Comment::from)
+        .collect()
+}
+
+#[inline]
+fn things_of_kind<T: serde::de::DeserializeOwned>(
+    listing: Listing,
+    kind: &'static str,
+) -> impl Iterator<Item = T> {
+    listing
+        .data
+        .children
+        .into_iter()
+        .filter(move |thing| thing.kind == kind)
+        .filter_map(|thing| util::ok_log(serde_json::from_value(thing.data)))
+}
+
+/// Parse the posts out of the `top.json` listing for a subreddit.
+#[inline]
+pub fn parse_posts(data: &[u8]) -> crate::Result<Vec<Post>> {
+    let listing: Listing = serde_json::from_slice(data)?;
+    Ok(things_of_kind(listing, "t3").map(Post::decoded).collect())
+}
+
+/// Parse the post and its comment tree out of a `comments/<id>.json` response.
+#[inline]
+pub fn parse_thread(data: &[u8]) -> crate::Result<(Post, Vec<Comment>)> {
+    let [post, comments]: [Listing; 2] = serde_json::from_slice(data)?;
+    let post = things_of_kind(post, "t3")
+        .map(Post::decoded)
+        .next()
+        .ok_or(crate::Error::StaticMsg("Thread does not contain a post"))?;
+    Ok((post, comments_in(comments)))
+}
+
+/// Get the posts that are eligible to be made into a video.
+#[inline]
+pub fn eligible_posts(posts: Vec<Post>, upvote_threshold: i64) -> Vec<Post> {
+    posts
+        .into_iter()
+        .filter(|p| {
+            p.score >= upvote_threshold
+                && !p.stickied
+                && !p.over_18
+                && filter_text(&p.title).is_ok()
+        })
+        .collect()
+}
+
+/// Walk the comment tree and get the comments that should be read, in the order they should be
+/// read, along with their depth in the tree.
+#[inline]
+pub fn selected_comments(
+    comments: &[Comment],
+    comment_threshold: i64,
+    reply_threshold: i64,
+) -> Vec<(usize, &Comment)> {
+    fn walk<'a>(
+        comment: &'a Comment,
+        depth: usize,
+        reply_threshold: i64,
+        out: &mut Vec<(usize, &'a Comment)>,
+    ) {
+        out.push((depth, comment));
+        comment
+            .replies
+            .iter()
+            .filter(|reply| reply.score >= reply_threshold)
+            .for_each(|reply| walk(reply, depth + 1, reply_threshold, out));
+    }
+
+    let mut out = vec![];
+    comments
+        .iter()
+        .filter(|c| c.score >= comment_threshold)
+        .for_each(|c| walk(c, 0, reply_threshold, &mut out));
+    out
+}
+
+/// Piece of text that should be turned into a card and narrated.
+struct CardItem {
+    card: Card,
+    persists_after_tts: f32,
+}
+
+#[inline]
+fn age(created_utc: f64, now: f64) -> Option<u64> {
+    if now >= created_utc {
+        Some((now - created_utc) as u64)
+    } else {
+        None
+    }
+}
+
+/// Frame source that reads a thread and its comments using reddit's JSON API.
+#[derive(Debug, Default)]
+pub struct RedditJsonSource {
+    params: RedditTextParams,
+}
+
+impl FrameSource for RedditJsonSource {
+    #[inline]
+    fn name(&self) -> &str {
+        "reddit_json"
+    }
+
+    #[inline]
+    fn configure(&mut self, params: &serde_json::Value) -> crate::Result {
+        if !params.is_null() {
+            self.params = serde_json::from_value(params.clone())?;
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn produce<'a>(&'a self, ctx: &'a Context) -> ProduceFuture<'a> {
+        Box::pin(async move {
+            let RedditTextParams {
+                subreddit,
+                upvote_threshold,
+                comment_threshold,
+                reply_threshold,
+                time_window,
+            } = &self.params;
+
+            // pick out a random post
+            let posts = parse_posts(
+                &http_get(&format!(
+                    "{}/r/{}/top.json?t={}&limit=50",
+                    REDDIT_URL, subreddit, time_window
+                ))
+                .await?,
+            )?;
+            let mut posts = eligible_posts(posts, *upvote_threshold);
+            if posts.is_empty() {
+                return Err(crate::Error::Msg(format!(
+                    "No posts on /r/{} have at least {} upvotes",
+                    subreddit, upvote_threshold
+                )));
+            }
+            let post = posts.swap_remove(tls_rng().generate_range(0, posts.len()));
+
+            // load the comments for the post
+            let (post, comments) = parse_thread(
+                &http_get(&format!(
+                    "{}/comments/{}.json?sort=top&limit=500",
+                    REDDIT_URL, &post.id
+                ))
+                .await?,
+            )?;
+
+            let title = filter_pass(post.title.clone())?;
+            let thumbnail_fields = [
+                ("title", title.clone()),
+                ("subreddit", subreddit.to_string()),
+                ("author", post.author.clone()),
+                ("score", post.score.to_string()),
+            ]
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.clone()))
+            .collect();
+            let metadata = VideoMetadata {
+                title: format!("{} - /r/{}", title.to_uppercase(), subreddit),
+                thumbnail_text: format!("/r/{} - {}", subreddit, &title),
+                thumbnail_template: "reddit_text".to_string(),
+                thumbnail_fields,
+                description: String::new(),
+            };
+
+            // figure out which pieces of text we want to read out
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs_f64())
+                .unwrap_or(0.0);
+            let mut items = vec![CardItem {
+                card: Card {
+                    author: format!("u/{}", &post.author),
+                    score: Some(post.score),
+                    age: age(post.created_utc, now),
+                    depth: 0,
+                    body: title,
+                },
+                persists_after_tts: 1.5,
+            }];
+            items.extend(
+                post.selftext
+                    .split("\n\n")
+                    .map(str::trim)
+                    .filter(|p| !p.is_empty())
+                    .filter_map(|p| util::ok_log(filter_pass(p.to_string())))
+                    .map(|body| CardItem {
+                        card: Card {
+                            author: format!("u/{}", &post.author),
+                            score: None,
+                            age: None,
+                            depth: 0,
+                            body,
+                        },
+                        persists_after_tts: 1.5,
+                    }),
+            );
+            let comment_items: Vec<_> =
+                selected_comments(&comments, *comment_threshold, *reply_threshold)
+                    .into_iter()
+                    .filter_map(|(depth, c)| {
+                        let body = util::ok_log(filter_pass(c.body.clone()))?;
+                        Some(CardItem {
+                            card: Card {
+                                author: format!("u/{}", &c.author),
+                                score: Some(c.score),
+                                age: age(c.created_utc, now),
+                                depth,
+                                body,
+                            },
+                            persists_after_tts: 0.5,
+                        })
+                    })
+                    .collect();
+
+            let basedir: Arc<Path> = ctx.basedir().await.into_boxed_path().into();
+            let (video_width, _) = ctx.video_size();
+            let card_width = (video_width as u32) * 4 / 5;
+            let theme = ctx.config().card_theme;
+
+            let cards = stream::iter(items)
+                .then({
+                    let basedir = basedir.clone();
+                    move |item| card_frame(item, basedir.clone(), card_width, theme)
+                })
+                .filter_map(util::ok_log);
+            let comments_frame = Frame {
+                tts: "Comments".to_string(),
+                overlaid: "Comments".to_string(),
+                imagepath: None,
+                imagefadesin: false,
+                persists_after_tts: 1.5,
+                speaker: None,
+            };
+            let comment_cards = stream::iter(comment_items)
+                .then(move |item| card_frame(item, basedir.clone(), card_width, theme))
+                .filter_map(util::ok_log);
+
+            Ok(SourceOutput {
+                metadata,
+                frames: Box::pin(
+                    cards
+                        .chain(stream::once(comments_frame))
+                        .chain(comment_cards),
+                ),
+            })
+        })
+    }
+}
+
+#[inline]
+async fn card_frame(
+    item: CardItem,
+    basedir: Arc<Path>,
+    card_width: u32,
+    theme: Theme,
+) -> crate::Result<Frame> {
+    let CardItem {
+        card,
+        persists_after_tts,
+    } = item;
+    let path = basedir.join(format!(
+        "card{}.png",
+        CARD_NUMBER.fetch_add(1, Ordering::SeqCst)
+    ));
+    let imagepath = save_card(&card, card_width, theme, path).await?;
+    Ok(Frame {
+        tts: card.body,
+        overlaid: String::new(),
+        imagepath: Some(imagepath),
+        imagefadesin: false,
+        persists_after_tts,
+        speaker: Some(card.author),
+    })
+}
+
+#[test]
+fn test_parse_posts() {
+    let posts = parse_posts(include_bytes!("../../fixtures/reddit/top.json")).unwrap();
+    assert_eq!(posts.len(), 4);
+    assert_eq!(
+        posts[3].title,
+        "What small thing made your day better & brighter today?"
+    );
+
+    let posts = eligible_posts(posts, 500);
+    assert_eq!(posts.len(), 1);
+    assert_eq!(posts[0].id, "n2bbbb");
+}
+
+#[test]
+fn test_parse_thread() {
+    let (post, comments) =
+        parse_thread(include_bytes!("../../fixtures/reddit/thread.json")).unwrap();
+    assert_eq!(post.id, "n2bbbb");
+    assert_eq!(post.selftext.split("\n\n").count(), 2);
+    assert!(post.selftext.contains("cook & clean"));
+    assert_eq!(comments.len(), 3);
+    assert_eq!(comments[0].replies.len(), 2);
+    assert!(comments[0].replies[0].body.starts_with("> owning"));
+
+    let selected: Vec<_> = selected_comments(&comments, 200, 100)
+        .into_iter()
+        .map(|(depth, c)| (depth, c.score))
+        .collect();
+    assert_eq!(selected, vec![(0, 9120), (1, 2311), (2, 804), (0, 7004)]);
+}
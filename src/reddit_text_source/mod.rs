@@ -19,6 +19,7 @@ use crate::{
     context::Context,
     filter::{filter_pass, filter_text},
     frame::Frame,
+    frame_source::{FrameSource, ProduceFuture, SourceOutput, VideoMetadata},
     util::{self, strip_html_tags, timeout, ArcWebElement},
 };
 use futures_lite::{
@@ -30,6 +31,7 @@ use once_cell::sync::OnceCell;
 use std::{
     array::IntoIter as ArrayIter,
     cmp,
+    collections::BTreeMap,
     future::Future,
     mem,
     path::{Path, PathBuf},
@@ -43,7 +45,10 @@ use std::{
 };
 use thirtyfour::{common::types::ElementRect, error::WebDriverResult, prelude::*};
 
+mod api;
 mod direct_children;
+
+pub use api::RedditJsonSource;
 use direct_children::direct_children;
 
 static GLOBAL_NUMBER: AtomicUsize = AtomicUsize::new(0);
@@ -188,6 +193,11 @@ impl Comment {
         )
     }
 
+    #[inline]
+    async fn author(&self) -> Option<String> {
+        author_of(&self.commentbody).await
+    }
+
     #[inline]
     async fn scroll(&self) -> crate::Result<()> {
         self.elem.elem().scroll_into_view().await?;
@@ -205,6 +215,7 @@ impl Comment {
             imagepath: Some(sspath),
             imagefadesin: false,
             persists_after_tts: 0.5,
+            speaker: self.author().await,
         })
     }
 
@@ -309,6 +320,17 @@ impl Comment {
     }
 }
 
+/// Get the username out of a post or comment, if it still has one.
+#[inline]
+async fn author_of(elem: &ArcWebElement) -> Option<String> {
+    let author = elem
+        .elem()
+        .find_element(By::ClassName("author"))
+        .await
+        .ok()?;
+    author.text().await.ok()
+}
+
 #[inline]
 async fn report_on_comment(
     elem: ArcWebElement,
@@ -435,6 +457,11 @@ impl ThreadHeader {
         }
     }
 
+    #[inline]
+    async fn author(&self) -> Option<String> {
+        author_of(&self.elem).await
+    }
+
     #[inline]
     async fn text(&self) -> crate::Result<String> {
         filter_pass(
@@ -495,12 +522,13 @@ impl RedditThread {
     async fn paragraph_frames(
         &self,
         basedir: Arc<Path>,
+        author: Option<String>,
     ) -> crate::Result<impl Stream<Item = Frame> + Send + 'static> {
         let paragraphs = self.paragraphs().await?;
         log::info!("Globbed paragraphs!");
         Ok(stream::iter(paragraphs.into_iter())
-            .map(move |item| (basedir.clone(), item))
-            .then(|(basedir, item)| async move {
+            .map(move |item| (basedir.clone(), author.clone(), item))
+            .then(|(basedir, author, item)| async move {
                 let driver = item.as_owner();
                 let iteme = item.elem();
                 let parscreename = format!(
@@ -517,6 +545,7 @@ impl RedditThread {
                     imagepath: Some(parscreename),
                     imagefadesin: false,
                     persists_after_tts: 1.5,
+                    speaker: author,
                 }
             }))
     }
@@ -537,6 +566,78 @@ impl RedditThread {
     }
 }
 
+/// Parameters for the reddit text frame source.
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(default)]
+struct RedditTextParams {
+    subreddit: String,
+    upvote_threshold: i64,
+    comment_threshold: i64,
+    reply_threshold: i64,
+    time_window: String,
+}
+
+impl Default for RedditTextParams {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            subreddit: "AskReddit".to_string(),
+            upvote_threshold: 500,
+            comment_threshold: 200,
+            reply_threshold: 100,
+            time_window: "day".to_string(),
+        }
+    }
+}
+
+/// Frame source that reads a thread and its comments off of a subreddit.
+#[derive(Debug, Default)]
+pub struct RedditTextSource {
+    params: RedditTextParams,
+}
+
+impl FrameSource for RedditTextSource {
+    #[inline]
+    fn name(&self) -> &str {
+        "reddit_text"
+    }
+
+    #[inline]
+    fn configure(&mut self, params: &serde_json::Value) -> crate::Result {
+        if !params.is_null() {
+            self.params = serde_json::from_value(params.clone())?;
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn produce<'a>(&'a self, ctx: &'a Context) -> ProduceFuture<'a> {
+        Box::pin(async move {
+            let RedditTextParams {
+                subreddit,
+                upvote_threshold,
+                comment_threshold,
+                reply_threshold,
+                time_window,
+            } = &self.params;
+            let (metadata, frames) = reddit_text_source(
+                subreddit,
+                *upvote_threshold,
+                *comment_threshold,
+                *reply_threshold,
+                time_window,
+                ctx,
+            )
+            .await?;
+
+            Ok(SourceOutput {
+                metadata,
+                frames: Box::pin(frames),
+            })
+        })
+    }
+}
+
 pub async fn reddit_text_source(
     subreddit: &str,
     upvote_threshold: i64,
@@ -544,7 +645,7 @@ pub async fn reddit_text_source(
     reply_threshold: i64,
     net: &str,
     context: &Context,
-) -> crate::Result<impl Stream<Item = Frame> + Send + 'static> {
+) -> crate::Result<(VideoMetadata, impl Stream<Item = Frame> + Send + 'static)> {
     let basedir = context.basedir().await;
 
     // pick out a random thread header
@@ -568,16 +669,21 @@ pub async fn reddit_text_source(
     let item = items.into_iter().nth(randindex).unwrap();
 
     let title = item.text().await?;
+    let author = item.author().await;
 
-    context
-        .set_thumbnail(
-            format!("/r/{} - {}", subreddit, &title),
-            "reddit_text".to_string(),
-        )
-        .await;
-    context
-        .set_video_title(format!("{} - /r/{}", title.to_uppercase(), subreddit))
-        .await;
+    let mut thumbnail_fields = BTreeMap::new();
+    thumbnail_fields.insert("title".to_string(), title.clone());
+    thumbnail_fields.insert("subreddit".to_string(), subreddit.to_string());
+    if let Some(author) = &author {
+        thumbnail_fields.insert("author".to_string(), author.clone());
+    }
+    let metadata = VideoMetadata {
+        title: format!("{} - /r/{}", title.to_uppercase(), subreddit),
+        thumbnail_text: format!("/r/{} - {}", subreddit, &title),
+        thumbnail_template: "reddit_text".to_string(),
+        thumbnail_fields,
+        description: String::new(),
+    };
 
     // take a screenshot of that item and use it as a frame
     let titlescreenname = item.screenshot(&basedir).await?;
@@ -587,6 +693,7 @@ pub async fn reddit_text_source(
         imagepath: Some(titlescreenname),
         imagefadesin: false,
         persists_after_tts: 1.5,
+        speaker: author.clone(),
     };
 
     // tell driver to go to that index
@@ -595,7 +702,7 @@ pub async fn reddit_text_source(
 
     // within the post, there will be paragraphs, turn each of these into a frame
     let basedir: Arc<Path> = basedir.into_boxed_path().into();
-    let parframes = item.paragraph_frames(basedir.clone()).await?;
+    let parframes = item.paragraph_frames(basedir.clone(), author).await?;
 
     // add a frame for the comments
     let comments_frame = Frame {
@@ -604,6 +711,7 @@ pub async fn reddit_text_source(
         imagepath: None,
         imagefadesin: false,
         persists_after_tts: 1.5,
+        speaker: None,
     };
 
     // iterate through the comments and see which ones we want to use
@@ -657,5 +765,5 @@ pub async fn reddit_text_source(
     // yeah i know this is a cardinal sin but the program hangs if I don't do this
     mem::forget(driver.clone());
 
-    Ok(frames)
+    Ok((metadata, frames))
 }
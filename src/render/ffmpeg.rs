@@ -0,0 +1,765 @@
+/*
+ * This file is part of KOTI.
+ *
+ * KOTI is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * KOTI is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Afero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with KOTI.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Render backend that compiles the timeline into an ffmpeg filtergraph, for machines that don't
+//! have MLT installed.
+
+use super::{ProgressSender, RenderBackend, RenderFuture};
+use crate::{
+    mlt::{Element, Filter, Mlt, Playlist, PlaylistEntry, Producer, ServiceId, Tractor, TractorId},
+    process::{Project, FPS},
+};
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
+use tokio::{fs::File, io::AsyncWriteExt, process::Command};
+
+const SCRIPT_NAME: &str = "filtergraph.txt";
+const SAMPLE_RATE: usize = 48000;
+
+/// Renders the project by compiling it into a `-filter_complex` graph and running `ffmpeg`.
+#[derive(Debug, Default)]
+pub struct FfmpegBackend;
+
+impl RenderBackend for FfmpegBackend {
+    #[inline]
+    fn name(&self) -> &str {
+        "ffmpeg"
+    }
+
+    #[inline]
+    fn render<'a>(
+        &'a self,
+        basedir: &'a Path,
+        project: &'a Project,
+        _progress: &'a ProgressSender,
+    ) -> RenderFuture<'a> {
+        Box::pin(async move {
+            // load the project back from disk, in case it's been edited since it was built
+            let (mlt, main_tractor, duration) = Mlt::load(&project.path).await?;
+            let graph = FilterGraph::compile(&mlt, main_tractor, duration)?;
+
+            // the graph gets too long for the command line, so pass it in as a script
+            let script_path = basedir.join(SCRIPT_NAME);
+            let mut f = File::create(&script_path).await?;
+            f.write_all(graph.script().as_bytes()).await?;
+            f.flush().await?;
+
+            log::info!("Running ffmpeg...");
+            let output = Command::new("ffmpeg")
+                .current_dir(basedir)
+                .args(graph.args(&script_path, &project.video_path))
+                .kill_on_drop(true)
+                .output()
+                .await?;
+            log::info!("ffmpeg has finished!");
+
+            if !output.status.success() {
+                return Err(crate::Error::RenderFailed(
+                    "ffmpeg",
+                    output.status.code(),
+                    String::from_utf8_lossy(&output.stderr).trim().to_string(),
+                ));
+            }
+
+            Ok(())
+        })
+    }
+}
+
+/// Something ffmpeg reads from.
+#[derive(Debug, Clone, PartialEq)]
+enum Input {
+    /// A still image, looped forever.
+    Image(PathBuf),
+    /// An audio or video file.
+    Media(PathBuf),
+}
+
+/// An MLT timeline compiled into an ffmpeg filtergraph.
+#[derive(Debug, Clone)]
+pub struct FilterGraph {
+    inputs: Vec<Input>,
+    chains: Vec<String>,
+    duration: usize,
+}
+
+/// Labels for the video and audio of part of the timeline, along with its length in frames.
+#[derive(Debug)]
+struct Streams {
+    video: String,
+    audio: String,
+    length: usize,
+}
+
+impl FilterGraph {
+    /// Compile the timeline under the main tractor. Every part of the timeline is scaled to the
+    /// size of the video, so that parts can be concatenated and overlaid on each other.
+    #[inline]
+    pub fn compile(mlt: &Mlt, main_tractor: TractorId, duration: usize) -> crate::Result<Self> {
+        mlt.validate(main_tractor)?;
+
+        let mut compiler = Compiler {
+            elements: mlt
+                .elements()
+                .iter()
+                .map(|element| (element_id(element), element))
+                .collect(),
+            video_size: mlt.video_size(),
+            inputs: vec![],
+            chains: vec![],
+            next_label: 0,
+        };
+
+        // the consumer's out point is inclusive
+        let main = compiler.service(main_tractor.into(), 0, duration + 1)?;
+        let (width, height) = compiler.video_size;
+        let background = compiler.chain(
+            &[],
+            &format!(
+                "color=c=black:s={}x{}:r={},trim=end_frame={}",
+                width,
+                height,
+                FPS,
+                duration + 1
+            ),
+        );
+        compiler.chains.push(format!(
+            "[{}][{}]overlay=format=auto,format=yuv420p[vout]",
+            background, &main.video
+        ));
+        compiler
+            .chains
+            .push(format!("[{}]anull[aout]", &main.audio));
+
+        Ok(Self {
+            inputs: compiler.inputs,
+            chains: compiler.chains,
+            duration: duration + 1,
+        })
+    }
+
+    /// The filtergraph, in the form ffmpeg expects in `-filter_complex_script`.
+    #[inline]
+    pub fn script(&self) -> String {
+        self.chains.join(";\n")
+    }
+
+    /// Arguments to ffmpeg that render the graph to `output`.
+    #[inline]
+    pub fn args(&self, script_path: &Path, output: &Path) -> Vec<OsString> {
+        let mut args: Vec<OsString> = vec!["-y".into(), "-hide_banner".into()];
+        for input in &self.inputs {
+            let path = match input {
+                Input::Image(path) => {
+                    args.extend(vec![
+                        "-loop".into(),
+                        "1".into(),
+                        "-framerate".into(),
+                        FPS.to_string().into(),
+                    ]);
+                    path
+                }
+                Input::Media(path) => path,
+            };
+            args.push("-i".into());
+            args.push(path.into());
+        }
+
+        args.extend(vec![
+            "-filter_complex_script".into(),
+            script_path.into(),
+            "-map".into(),
+            "[vout]".into(),
+            "-map".into(),
+            "[aout]".into(),
+            "-t".into(),
+            seconds(self.duration).into(),
+            "-c:v".into(),
+            "libvpx-vp9".into(),
+            "-b:v".into(),
+            "0".into(),
+            "-crf".into(),
+            "32".into(),
+            "-c:a".into(),
+            "libopus".into(),
+            output.into(),
+        ]);
+        args
+    }
+}
+
+#[inline]
+fn element_id(element: &Element) -> ServiceId {
+    match element {
+        Element::Producer(producer) => producer.id().into(),
+        Element::Playlist(playlist) => playlist.id().into(),
+        Element::Tractor(tractor) => tractor.id().into(),
+    }
+}
+
+/// Length of some number of frames, in seconds.
+#[inline]
+fn seconds(frames: usize) -> String {
+    format!("{:.3}", frames as f32 / FPS)
+}
+
+/// What kind of media a producer's resource is, going by its name.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum MediaKind {
+    Image,
+    Audio,
+    Video,
+}
+
+#[inline]
+fn media_kind(resource: &Path) -> MediaKind {
+    let extension = resource
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(str::to_ascii_lowercase);
+    match extension.as_deref() {
+        Some("png") | Some("jpg") | Some("jpeg") | Some("bmp") | Some("gif") => MediaKind::Image,
+        Some("wav") | Some("mp3") | Some("ogg") | Some("opus") | Some("flac") | Some("m4a") => {
+            MediaKind::Audio
+        }
+        _ => MediaKind::Video,
+    }
+}
+
+/// The color a producer generates, if it's a color producer rather than a file.
+#[inline]
+fn color_of(resource: &Path) -> Option<&str> {
+    let resource = resource.to_str()?;
+    match resource {
+        "black" => Some("black"),
+        _ => resource
+            .strip_prefix("color:")
+            .or_else(|| resource.strip_prefix("colour:")),
+    }
+}
+
+/// Parse MLT keyframes like `0=-60;30=0` into frames and values.
+#[inline]
+fn keyframes(value: &str) -> Option<Vec<(usize, f32)>> {
+    value
+        .split(';')
+        .map(|keyframe| {
+            let (frame, value) = keyframe.split_once('=')?;
+            Some((frame.trim().parse().ok()?, value.trim().parse().ok()?))
+        })
+        .collect::<Option<Vec<_>>>()
+        .filter(|keyframes| !keyframes.is_empty())
+}
+
+/// An ffmpeg expression over `t` that linearly interpolates between the keyframes.
+#[inline]
+fn interpolate(keyframes: &[(usize, f32)]) -> String {
+    let time = |frame: usize| frame as f32 / FPS;
+    let (first_frame, first_value) = keyframes[0];
+    let (_, last_value) = keyframes[keyframes.len() - 1];
+
+    let expr = keyframes
+        .windows(2)
+        .rev()
+        .fold(last_value.to_string(), |rest, pair| {
+            let ((f1, v1), (f2, v2)) = (pair[0], pair[1]);
+            format!(
+                "if(lt(t,{}),{}+({})*(t-{})/{},{})",
+                time(f2),
+                v1,
+                v2 - v1,
+                time(f1),
+                time(f2) - time(f1),
+                rest
+            )
+        });
+    format!("if(lt(t,{}),{},{})", time(first_frame), first_value, expr)
+}
+
+struct Compiler<'a> {
+    elements: HashMap<ServiceId, &'a Element>,
+    video_size: (usize, usize),
+    inputs: Vec<Input>,
+    chains: Vec<String>,
+    next_label: usize,
+}
+
+impl<'a> Compiler<'a> {
+    /// Add a chain of filters with the given inputs, returning the label of its output.
+    #[inline]
+    fn chain(&mut self, inputs: &[&str], filters: &str) -> String {
+        self.chain_outputs(inputs, filters, 1).remove(0)
+    }
+
+    /// Add a chain of filters that has more than one output.
+    #[inline]
+    fn chain_outputs(&mut self, inputs: &[&str], filters: &str, outputs: usize) -> Vec<String> {
+        let labels: Vec<String> = (self.next_label..self.next_label + outputs)
+            .map(|n| format!("s{}", n))
+            .collect();
+        self.next_label += outputs;
+
+        let inputs: String = inputs.iter().map(|input| format!("[{}]", input)).collect();
+        let outputs: String = labels.iter().map(|label| format!("[{}]", label)).collect();
+        self.chains
+            .push(format!("{}{}{}", inputs, filters, outputs));
+        labels
+    }
+
+    #[inline]
+    fn input(&mut self, input: Input) -> usize {
+        self.inputs.push(input);
+        self.inputs.len() - 1
+    }
+
+    #[inline]
+    fn element(&self, id: ServiceId) -> crate::Result<&'a Element> {
+        self.elements
+            .get(&id)
+            .copied()
+            .ok_or_else(|| crate::Error::InvalidTimeline(format!("{} is never defined", id)))
+    }
+
+    /// Scale a video stream so it fits inside the frame, with transparent borders.
+    #[inline]
+    fn fit_to_frame(&self) -> String {
+        let (width, height) = self.video_size;
+        format!(
+            "format=rgba,scale={w}:{h}:force_original_aspect_ratio=decrease,pad={w}:{h}:(ow-iw)/2:(oh-ih)/2:color=black@0,setsar=1",
+            w = width,
+            h = height
+        )
+    }
+
+    /// Transparent video and silence, `length` frames long.
+    #[inline]
+    fn blank(&mut self, length: usize) -> Streams {
+        let video = self.transparent(length);
+        let audio = self.silence(length);
+        Streams {
+            video,
+            audio,
+            length,
+        }
+    }
+
+    #[inline]
+    fn transparent(&mut self, length: usize) -> String {
+        let (width, height) = self.video_size;
+        self.chain(
+            &[],
+            &format!(
+                "color=c=black@0:s={}x{}:r={},format=rgba,trim=end_frame={}",
+                width, height, FPS, length
+            ),
+        )
+    }
+
+    #[inline]
+    fn silence(&mut self, length: usize) -> String {
+        self.chain(
+            &[],
+            &format!(
+                "anullsrc=r={}:cl=stereo,aformat=sample_fmts=fltp:channel_layouts=stereo,atrim=duration={}",
+                SAMPLE_RATE,
+                seconds(length)
+            ),
+        )
+    }
+
+    /// Cut `length` frames out of a stream, starting at `start`, padding the end if the stream
+    /// runs out early.
+    #[inline]
+    fn cut(&mut self, streams: Streams, start: usize, length: usize) -> Streams {
+        if start == 0 && length == streams.length {
+            return streams;
+        }
+
+        let video = self.chain(
+            &[&streams.video],
+            &format!(
+                "trim=start_frame={},setpts=PTS-STARTPTS,tpad=stop=-1:color=black@0,trim=end_frame={}",
+                start, length
+            ),
+        );
+        let audio = self.chain(
+            &[&streams.audio],
+            &format!(
+                "atrim=start={},asetpts=PTS-STARTPTS,apad,atrim=duration={}",
+                seconds(start),
+                seconds(length)
+            ),
+        );
+        Streams {
+            video,
+            audio,
+            length,
+        }
+    }
+
+    /// Compile `length` frames of a service, starting at `start`.
+    #[inline]
+    fn service(&mut self, id: ServiceId, start: usize, length: usize) -> crate::Result<Streams> {
+        match self.element(id)? {
+            Element::Producer(producer) => Ok(self.producer(producer, start, length)),
+            Element::Playlist(playlist) => {
+                let streams = self.playlist(playlist)?;
+                Ok(self.cut(streams, start, length))
+            }
+            Element::Tractor(tractor) => {
+                let streams = self.tractor(tractor)?;
+                Ok(self.cut(streams, start, length))
+            }
+        }
+    }
+
+    /// Compile the whole of a service that's used as a track.
+    #[inline]
+    fn track(&mut self, id: ServiceId) -> crate::Result<Streams> {
+        match self.element(id)? {
+            Element::Producer(producer) => {
+                let length = producer.length().ok_or_else(|| {
+                    crate::Error::InvalidTimeline(format!(
+                        "{} is used as a track, but doesn't have a length",
+                        id
+                    ))
+                })?;
+                Ok(self.producer(producer, 0, length))
+            }
+            Element::Playlist(playlist) => self.playlist(playlist),
+            Element::Tractor(tractor) => self.tractor(tractor),
+        }
+    }
+
+    #[inline]
+    fn producer(&mut self, producer: &Producer, start: usize, length: usize) -> Streams {
+        let resource = producer.resource();
+        let (width, height) = self.video_size;
+
+        if let Some(color) = color_of(resource) {
+            let video = self.chain(
+                &[],
+                &format!(
+                    "color=c={}:s={}x{}:r={},format=rgba,trim=end_frame={}",
+                    color, width, height, FPS, length
+                ),
+            );
+            let audio = self.silence(length);
+            return Streams {
+                video,
+                audio,
+                length,
+            };
+        }
+
+        let kind = media_kind(resource);
+        let index = self.input(match kind {
+            MediaKind::Image => Input::Image(resource.clone()),
+            MediaKind::Audio | MediaKind::Video => Input::Media(resource.clone()),
+        });
+
+        let video = match kind {
+            MediaKind::Audio => self.transparent(length),
+            MediaKind::Image | MediaKind::Video => {
+                let filters = format!(
+                    "{},fps={},trim=start_frame={},setpts=PTS-STARTPTS,tpad=stop=-1:color=black@0,trim=end_frame={}",
+                    self.fit_to_frame(),
+                    FPS,
+                    start,
+                    length
+                );
+                self.chain(&[&format!("{}:v", index)], &filters)
+            }
+        };
+        let audio = match kind {
+            MediaKind::Image => self.silence(length),
+            MediaKind::Audio | MediaKind::Video => self.chain(
+                &[&format!("{}:a", index)],
+                &format!(
+                    "aresample={},aformat=sample_fmts=fltp:channel_layouts=stereo,atrim=start={},asetpts=PTS-STARTPTS,apad,atrim=duration={}",
+                    SAMPLE_RATE,
+                    seconds(start),
+                    seconds(length)
+                ),
+            ),
+        };
+
+        Streams {
+            video,
+            audio,
+            length,
+        }
+    }
+
+    #[inline]
+    fn playlist(&mut self, playlist: &Playlist) -> crate::Result<Streams> {
+        let segments = playlist
+            .entries()
+            .iter()
+            .map(|entry| match *entry {
+                PlaylistEntry::Blank(length) => Ok(self.blank(length)),
+                PlaylistEntry::Producer { id, start, end } => {
+                    self.service(id, start, end - start + 1)
+                }
+            })
+            .collect::<crate::Result<Vec<_>>>()?;
+
+        let streams = match segments.len() {
+            0 => {
+                return Err(crate::Error::InvalidTimeline(format!(
+                    "{} has nothing in it",
+                    playlist.id()
+                )))
+            }
+            1 => segments.into_iter().next().unwrap(),
+            n => {
+                let length = segments.iter().map(|s| s.length).sum();
+                let inputs: Vec<&str> = segments
+                    .iter()
+                    .flat_map(|s| vec![s.video.as_str(), s.audio.as_str()])
+                    .collect();
+                let mut outputs =
+                    self.chain_outputs(&inputs, &format!("concat=n={}:v=1:a=1", n), 2);
+                let audio = outputs.pop().unwrap();
+                let video = outputs.pop().unwrap();
+
+                Streams {
+                    video,
+                    audio,
+                    length,
+                }
+            }
+        };
+
+        Ok(self.filters(playlist.filters(), streams))
+    }
+
+    #[inline]
+    fn tractor(&mut self, tractor: &Tractor) -> crate::Result<Streams> {
+        let tracks = tractor
+            .tracks()
+            .iter()
+            .map(|&track| self.track(track))
+            .collect::<crate::Result<Vec<_>>>()?;
+        let length = tracks.iter().map(|t| t.length).max().ok_or_else(|| {
+            crate::Error::InvalidTimeline(format!("{} has no tracks", tractor.id()))
+        })?;
+
+        // later tracks go on top of earlier ones
+        let mut video = self.transparent(length);
+        for track in &tracks {
+            video = self.chain(
+                &[&video, &track.video],
+                "overlay=format=auto:eof_action=pass,format=rgba",
+            );
+        }
+
+        // amix divides each input by the number of inputs, so turn it back up afterwards
+        let audio = match tracks.len() {
+            1 => tracks[0].audio.clone(),
+            n => {
+                let inputs: Vec<&str> = tracks.iter().map(|t| t.audio.as_str()).collect();
+                self.chain(
+                    &inputs,
+                    &format!(
+                        "amix=inputs={n}:duration=longest:dropout_transition=0,volume={n},apad,atrim=duration={}",
+                        seconds(length),
+                        n = n
+                    ),
+                )
+            }
+        };
+
+        // every track's audio is already mixed together and every track's video is already laid
+        // over the ones before it, which is what mix and affine transitions are for
+        tractor
+            .transitions()
+            .iter()
+            .filter(|transition| !matches!(transition.name(), "mix" | "affine"))
+            .for_each(|transition| {
+                log::warn!(
+                    "Ignoring {} transition in {}, the ffmpeg backend doesn't support it",
+                    transition.name(),
+                    tractor.id()
+                )
+            });
+
+        Ok(self.filters(
+            tractor.filters(),
+            Streams {
+                video,
+                audio,
+                length,
+            },
+        ))
+    }
+
+    #[inline]
+    fn filters(&mut self, filters: &[Filter], mut streams: Streams) -> Streams {
+        for filter in filters {
+            match self.translate(filter) {
+                Some((true, filters)) => streams.video = self.chain(&[&streams.video], &filters),
+                Some((false, filters)) => streams.audio = self.chain(&[&streams.audio], &filters),
+                None => {}
+            }
+        }
+        streams
+    }
+
+    /// Translate an MLT filter into ffmpeg filters, along with whether they apply to the video
+    /// (as opposed to the audio).
+    #[inline]
+    fn translate(&self, filter: &Filter) -> Option<(bool, String)> {
+        let (width, height) = self.video_size;
+        let unsupported = || {
+            log::warn!(
+                "Ignoring {} filter, the ffmpeg backend doesn't support it like this",
+                filter.name()
+            );
+            None
+        };
+
+        match filter.name() {
+            // everything is already scaled to fit the frame, and audio is already in stereo
+            "resize" | "panner" => None,
+            "affine" => {
+                let geometry = filter.get("transition.geometry")?;
+                let geometry = geometry.split_once('=').map_or(geometry, |(_, g)| g);
+                let rect = geometry
+                    .split_whitespace()
+                    .map(|n| n.parse::<f32>().ok().map(|n| n.max(0.0) as usize))
+                    .collect::<Option<Vec<_>>>();
+                match rect.as_deref() {
+                    Some(&[x, y, w, h]) if x < width && y < height => Some((
+                        true,
+                        format!(
+                            "scale={}:{}:force_original_aspect_ratio=decrease,pad={}:{}:{}:{}:color=black@0",
+                            w.min(width - x).max(1),
+                            h.min(height - y).max(1),
+                            width,
+                            height,
+                            x,
+                            y
+                        ),
+                    )),
+                    _ => unsupported(),
+                }
+            }
+            "volume" => match filter.get("level").map(keyframes) {
+                // keyframed levels are in decibels
+                Some(Some(keyframes)) => Some((
+                    false,
+                    format!(
+                        "volume='pow(10,({})/20)':eval=frame",
+                        interpolate(&keyframes)
+                    ),
+                )),
+                Some(None) => unsupported(),
+                // max_gain only caps normalization in MLT, so on its own it does nothing
+                None => match filter.get("gain") {
+                    Some(gain) => Some((false, format!("volume={}", gain))),
+                    None => unsupported(),
+                },
+            },
+            // fading the video in or out
+            "brightness" => match filter.get("level").and_then(keyframes).as_deref() {
+                Some(&[(f1, v1), (f2, v2)]) if f2 > f1 => Some((
+                    true,
+                    format!(
+                        "fade=t={}:st={}:d={}:alpha=1",
+                        if v2 > v1 { "in" } else { "out" },
+                        seconds(f1),
+                        seconds(f2 - f1)
+                    ),
+                )),
+                _ => unsupported(),
+            },
+            _ => unsupported(),
+        }
+    }
+}
+
+#[test]
+fn test_compile() {
+    let (mlt, main_tractor, duration) =
+        Mlt::from_xml(include_bytes!("../../fixtures/mlt/edited.mlt")).unwrap();
+    let graph = FilterGraph::compile(&mlt, main_tractor, duration).unwrap();
+    let script = graph.script();
+
+    // the card is looped, the voice is read as is
+    assert_eq!(
+        graph.inputs,
+        vec![
+            Input::Image(PathBuf::from("/tmp/koti42/card0.png")),
+            Input::Media(PathBuf::from("/tmp/koti42/tts0.wav")),
+        ]
+    );
+    assert!(script.contains("[0:v]format=rgba,scale=1280:720"));
+    assert!(script.contains("concat=n=2:v=1:a=1"));
+    assert!(script.contains("amix=inputs=2"));
+    assert!(script.ends_with("anull[aout]"));
+
+    let args = graph.args(Path::new("filtergraph.txt"), Path::new("koti.webm"));
+    assert_eq!(args.iter().filter(|arg| *arg == "-i").count(), 2);
+    assert_eq!(args.last().unwrap(), "koti.webm");
+}
+
+#[test]
+fn test_translate_filters() {
+    let mut mlt = Mlt::new(Path::new("/tmp/koti"), 1920, 1080);
+    let music = mlt.add_producer_with_length(PathBuf::from("music.mp3"), Some(300));
+    let music = mlt.add_playlist(
+        std::iter::once(PlaylistEntry::Producer {
+            id: music.into(),
+            start: 0,
+            end: 299,
+        }),
+        vec![
+            Filter::new("volume").property("max_gain", "-10dB"),
+            Filter::new("volume").property("gain", "0.5"),
+            Filter::new("volume").property("level", "0=-60;30=0"),
+        ],
+    );
+    let card = mlt.add_producer(PathBuf::from("card.png"));
+    let card = mlt.add_playlist(
+        std::iter::once(PlaylistEntry::Producer {
+            id: card.into(),
+            start: 0,
+            end: 299,
+        }),
+        vec![
+            Filter::new("affine").property("transition.geometry", "0=0 540 1920 540"),
+            Filter::new("brightness").property("level", "270=1;300=0"),
+        ],
+    );
+    let main_tractor = mlt.add_tractor(vec![music, card], std::iter::empty());
+
+    let script = FilterGraph::compile(&mlt, main_tractor, 299)
+        .unwrap()
+        .script();
+    assert!(!script.contains("volume=-10dB"));
+    assert!(script.contains("volume=0.5"));
+    assert!(script.contains("volume='pow(10,(if(lt(t,0),-60,"));
+    assert!(script.contains("pad=1920:1080:0:540"));
+    assert!(script.contains("fade=t=out:st=9.009:d=1.001:alpha=1"));
+
+    assert_eq!(keyframes("0=-60;30=0"), Some(vec![(0, -60.0), (30, 0.0)]));
+    assert_eq!(keyframes("nonsense"), None);
+}
@@ -0,0 +1,225 @@
+/*
+ * This file is part of KOTI.
+ *
+ * KOTI is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * KOTI is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Afero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with KOTI.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use super::{Progress, ProgressSender, RenderBackend, RenderFuture};
+use crate::process::Project;
+use futures_lite::future;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::{collections::VecDeque, path::Path, process::Stdio, time::Instant};
+use tokio::{
+    io::{AsyncBufReadExt, BufReader},
+    process::{ChildStderr, Command},
+};
+
+/// How many lines of melt's output to keep for the error if it fails.
+const STDERR_LINES: usize = 20;
+
+/// Renders the project by handing it to `melt`.
+#[derive(Debug, Default)]
+pub struct MeltBackend;
+
+impl RenderBackend for MeltBackend {
+    #[inline]
+    fn name(&self) -> &str {
+        "melt"
+    }
+
+    #[inline]
+    fn render<'a>(
+        &'a self,
+        basedir: &'a Path,
+        project: &'a Project,
+        progress: &'a ProgressSender,
+    ) -> RenderFuture<'a> {
+        // melt writes to the target in the project's consumer, which is where the video path
+        // came from in the first place. the consumer's out point is inclusive
+        Box::pin(run_melt(
+            Path::new("melt"),
+            basedir,
+            &project.path,
+            project.duration + 1,
+            progress,
+        ))
+    }
+}
+
+/// Run melt on a saved project, reporting its progress through `progress`. melt is killed if
+/// the returned future is dropped before it finishes.
+#[inline]
+pub async fn run_melt(
+    program: &Path,
+    basedir: &Path,
+    project: &Path,
+    total: usize,
+    progress: &ProgressSender,
+) -> crate::Result {
+    log::info!("Running melt...");
+    let mut child = Command::new(program)
+        .current_dir(basedir)
+        .arg(project)
+        .arg("-progress")
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+    let stderr = child.stderr.take().expect("melt's stderr is piped");
+
+    let (status, output) = future::zip(child.wait(), watch_output(stderr, total, progress)).await;
+    let (status, output) = (status?, output?);
+    log::info!("melt has finished!");
+
+    if !status.success() {
+        return Err(crate::Error::RenderFailed("melt", status.code(), output));
+    }
+
+    Ok(())
+}
+
+/// Read melt's output, sending along its progress. Returns the last few lines that weren't
+/// progress updates.
+#[inline]
+async fn watch_output(
+    stderr: ChildStderr,
+    total: usize,
+    progress: &ProgressSender,
+) -> crate::Result<String> {
+    let start = Instant::now();
+    let mut reader = BufReader::new(stderr);
+    let mut lines = VecDeque::with_capacity(STDERR_LINES);
+    let mut buf = vec![];
+
+    // progress lines end with a carriage return so they overwrite each other in a terminal
+    while reader.read_until(b'\r', &mut buf).await? != 0 {
+        let text = String::from_utf8_lossy(&buf);
+        for line in text.split(|c| c == '\r' || c == '\n') {
+            match parse_progress(line) {
+                Some(frame) => {
+                    let eta = match frame {
+                        0 => None,
+                        frame => Some(
+                            start
+                                .elapsed()
+                                .mul_f64(total.saturating_sub(frame) as f64 / frame as f64),
+                        ),
+                    };
+                    // nobody may be listening, which is fine
+                    let _ = progress.send(Some(Progress { frame, total, eta }));
+                }
+                None if line.trim().is_empty() => {}
+                None => {
+                    if lines.len() == STDERR_LINES {
+                        lines.pop_front();
+                    }
+                    lines.push_back(line.trim().to_string());
+                }
+            }
+        }
+        buf.clear();
+    }
+
+    Ok(lines.into_iter().collect::<Vec<_>>().join("\n"))
+}
+
+/// Get the frame out of one of melt's progress lines.
+#[inline]
+fn parse_progress(line: &str) -> Option<usize> {
+    static PROGRESS_REGEX: Lazy<Regex> = Lazy::new(|| {
+        Regex::new(r"Current (?:Frame|Position):\s*(\d+),\s*percentage:\s*\d+")
+            .expect("Regex failed to compile")
+    });
+
+    PROGRESS_REGEX
+        .captures(line)
+        .and_then(|caps| caps[1].parse().ok())
+}
+
+#[test]
+fn test_parse_progress() {
+    assert_eq!(
+        parse_progress("Current Frame:         12, percentage:          0"),
+        Some(12)
+    );
+    assert_eq!(
+        parse_progress("Current Position:       3000, percentage:        100"),
+        Some(3000)
+    );
+    assert_eq!(parse_progress("[consumer avformat] error"), None);
+}
+
+#[test]
+fn test_run_melt() {
+    use std::{fs, os::unix::fs::PermissionsExt, time::Duration};
+    use tokio::sync::watch;
+
+    // stand in for melt with a script that reports some progress and then fails
+    let dir = std::env::temp_dir().join(format!("koti-melt-test-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let write_script = |name: &str, body: &str| {
+        let path = dir.join(name);
+        fs::write(&path, format!("#!/bin/sh\n{}\n", body)).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    };
+    let failing = write_script(
+        "failing",
+        "printf 'Current Frame:          5, percentage:         50\\r' >&2\n\
+         echo 'Failed to load \"card0.png\"' >&2\n\
+         exit 3",
+    );
+    let pid_path = dir.join("pid");
+    let hanging = write_script(
+        "hanging",
+        &format!("echo $$ > {}\nexec sleep 30", pid_path.display()),
+    );
+
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("Unable to construct Tokio runtime")
+        .block_on(async {
+            let (progress, updates) = watch::channel(None);
+            match run_melt(&failing, &dir, Path::new("project.mlt"), 10, &progress).await {
+                Err(crate::Error::RenderFailed("melt", Some(3), output)) => {
+                    assert_eq!(output, "Failed to load \"card0.png\"")
+                }
+                res => panic!("unexpected result: {:?}", res),
+            }
+            let last = updates.borrow().expect("no progress was reported");
+            assert_eq!((last.frame, last.total), (5, 10));
+
+            // timing out drops the render, which kills melt
+            let start = Instant::now();
+            let res = crate::util::timeout(
+                run_melt(&hanging, &dir, Path::new("project.mlt"), 10, &progress),
+                1,
+            )
+            .await;
+            assert!(matches!(res, Err(crate::Error::Timeout)));
+            assert!(start.elapsed() < Duration::from_secs(10));
+
+            // it's either gone or waiting to be reaped
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            let pid = fs::read_to_string(&pid_path).unwrap();
+            if let Ok(stat) = fs::read_to_string(format!("/proc/{}/stat", pid.trim())) {
+                assert!(stat.contains(") Z "), "melt is still running: {}", stat);
+            }
+        });
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,105 @@
+/*
+ * This file is part of KOTI.
+ *
+ * KOTI is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * KOTI is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Afero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with KOTI.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Backends that turn a saved MLT project into a video.
+
+mod ffmpeg;
+mod melt;
+
+pub use ffmpeg::{FfmpegBackend, FilterGraph};
+pub use melt::{run_melt, MeltBackend};
+
+use crate::process::Project;
+use std::{fmt, future::Future, path::Path, pin::Pin, time::Duration};
+use tokio::sync::watch;
+
+pub type RenderFuture<'a> = Pin<Box<dyn Future<Output = crate::Result> + Send + 'a>>;
+pub type ProgressSender = watch::Sender<Option<Progress>>;
+
+/// How far along a render is.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Progress {
+    pub frame: usize,
+    pub total: usize,
+    /// Estimated time until the render finishes.
+    pub eta: Option<Duration>,
+}
+
+impl Progress {
+    #[inline]
+    pub fn percentage(&self) -> usize {
+        (self.frame * 100 / self.total.max(1)).min(100)
+    }
+}
+
+impl fmt::Display for Progress {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "frame {} of {} ({}%)",
+            self.frame,
+            self.total,
+            self.percentage()
+        )?;
+        if let Some(eta) = self.eta {
+            let secs = eta.as_secs();
+            write!(f, ", about {}m {}s left", secs / 60, secs % 60)?;
+        }
+        Ok(())
+    }
+}
+
+/// Something that can render the project for a video.
+pub trait RenderBackend: Send + Sync {
+    /// The name of the backend, e.g. "melt".
+    fn name(&self) -> &str;
+
+    /// Render the project to `project.video_path`, running from `basedir`. Dropping the future
+    /// stops the render.
+    fn render<'a>(
+        &'a self,
+        basedir: &'a Path,
+        project: &'a Project,
+        progress: &'a ProgressSender,
+    ) -> RenderFuture<'a>;
+}
+
+/// The render backends that can be chosen in the channel configuration.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RenderBackendKind {
+    Melt,
+    Ffmpeg,
+}
+
+impl Default for RenderBackendKind {
+    #[inline]
+    fn default() -> Self {
+        Self::Melt
+    }
+}
+
+impl RenderBackendKind {
+    #[inline]
+    pub fn backend(self) -> Box<dyn RenderBackend> {
+        match self {
+            Self::Melt => Box::new(MeltBackend),
+            Self::Ffmpeg => Box::new(FfmpegBackend),
+        }
+    }
+}
@@ -1,9 +1,8 @@
 // MIT/Apache2 License
 
-use quick_xml::{
-    events::{BytesEnd, BytesStart, Event},
-    Reader,
-};
+use crate::util::decode_html_entities;
+use quick_xml::{events::Event, Reader};
+use std::mem;
 
 /// Find the list of authors for a particular entry.
 #[inline]
@@ -28,8 +27,12 @@ struct MetadataEntry {
 /// Gets an iterator that goes over the HTML of the attribution metadata.
 #[inline]
 fn metadata_entries(page_text: &str) -> impl Iterator<Item = MetadataEntry> + Send {
+    let page_text = page_text.to_string();
+
     genawaiter::sync::Gen::new(move |co| async move {
         let mut reader = Reader::from_reader(page_text.as_bytes());
+        // wikidot's HTML isn't always well-formed
+        reader.check_end_names(false);
         let mut state = StateMachine::default();
 
         let mut buf = vec![];
@@ -39,8 +42,7 @@ fn metadata_entries(page_text: &str) -> impl Iterator<Item = MetadataEntry> + Se
                 Ok(event) => event,
                 Err(e) => {
                     log::error!("XML Error: {}", e);
-                    buf.clear();
-                    continue;
+                    break;
                 }
             };
 
@@ -52,6 +54,8 @@ fn metadata_entries(page_text: &str) -> impl Iterator<Item = MetadataEntry> + Se
                     }
                 }
             }
+
+            buf.clear();
         }
     })
     .into_iter()
@@ -108,7 +112,7 @@ impl StateMachine {
             },
             Self::LookingForNameText => match event {
                 Event::Text(txt) => {
-                    *self = Self::FoundName(String::from_utf8(txt.escaped().to_vec()).unwrap());
+                    *self = Self::FoundName(text_of(&txt));
                 }
                 Event::End(end) if end.name() == b"tr" => {
                     *self = Self::LookingForTr;
@@ -144,14 +148,14 @@ impl StateMachine {
                     *self = Self::LookingForTr;
                     return Some(MetadataEntry {
                         page_name: txt,
-                        author: String::from_utf8(txt2.escaped().to_vec()).unwrap(),
+                        author: text_of(&txt2),
                     });
                 }
                 Event::End(end) if end.name() == b"tr" => {
                     *self = Self::LookingForTr;
                 }
                 _ => {
-                    *self = LookingForAuthorText(txt);
+                    *self = Self::LookingForAuthorText(txt);
                 }
             },
         }
@@ -159,3 +163,19 @@ impl StateMachine {
         None
     }
 }
+
+#[inline]
+fn text_of(txt: &[u8]) -> String {
+    decode_html_entities(String::from_utf8_lossy(txt).trim()).into_owned()
+}
+
+#[test]
+fn test_find_authors() {
+    let metadata = include_str!("../../fixtures/scp/attribution-metadata.html");
+    assert_eq!(
+        find_authors("scp-173", metadata),
+        vec!["Moto42".to_string(), "Lt Masipag".to_string()]
+    );
+    assert_eq!(find_authors("scp-999", metadata), vec!["ProfSnider"]);
+    assert!(find_authors("scp-4000", metadata).is_empty());
+}
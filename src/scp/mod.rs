@@ -1,68 +1,325 @@
 // MIT/Apache2 License
 
+//! Frame source that reads out articles from the SCP Foundation wiki.
+
 mod metadata;
 mod title;
 
-use nanorand::{RNG, tls_rng};
-use quick_xml::{Event, Reader};
-use reqwest::Client;
-use std::borrow::Cow;
+use crate::{
+    card::{save_card, Card, Theme},
+    context::Context,
+    filter::filter_text,
+    frame::Frame,
+    frame_source::{FrameSource, ProduceFuture, SourceOutput, VideoMetadata},
+    util::{self, decode_html_entities, http_get, strip_html_tags},
+};
+use futures_lite::stream::{self, StreamExt};
+use nanorand::{tls_rng, RNG};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::{
+    path::Path,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
 
-// figure out the author(s) for a particular SCP
-#[inline]
-async fn authors(client: &Client, number: u16) -> crate::Result<Vec<String>> {
-    // first, try for the "attribution metadata" page
-    let attribution_metadata = client
-        .get("http://www.scpwiki.com/attribution-metadata")
-        .send()
-        .await?
-        .text()
-        .await?;
-    // then, read through it to see if we can find our number
-    let names = metadata::find_authors(&format!("scp-{:0>3}", number), &attribution_metadata);
-    match names.is_empty() {
-        false => Ok(names),
-        true => {
-            Err(crate::Error::StaticMsg("TODO: figure out how to get the author here"))
+const SCP_WIKI_URL: &str = "https://scp-wiki.wikidot.com";
+const LICENSE: &str =
+    "Licensed under CC BY-SA 3.0: https://creativecommons.org/licenses/by-sa/3.0/";
+
+static CARD_NUMBER: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ScpParams {
+    /// Always use this entry instead of picking one at random.
+    pub number: Option<u16>,
+    pub max_number: u16,
+    /// How many random entries to try before giving up.
+    pub attempts: usize,
+    pub thumbnail_template: String,
+}
+
+impl Default for ScpParams {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            number: None,
+            max_number: 5999,
+            attempts: 10,
+            thumbnail_template: "scp".to_string(),
         }
     }
 }
 
+/// An entry on the wiki, along with everything we need to read it out.
+#[derive(Debug, Clone)]
 struct Entry {
     number: u16,
     title: String,
     authors: Vec<String>,
-    html: String,
+    paragraphs: Vec<String>,
+}
+
+impl Entry {
+    #[inline]
+    fn designation(&self) -> String {
+        format!("SCP-{:0>3}", self.number)
+    }
+
+    #[inline]
+    fn url(&self) -> String {
+        format!("{}/{}", SCP_WIKI_URL, page_name(self.number))
+    }
+
+    #[inline]
+    fn description(&self) -> String {
+        format!(
+            "\"{}\" ({}) by {}, from the SCP Wiki.\n{}\n{}\n\n",
+            self.designation(),
+            &self.title,
+            self.authors.join(", "),
+            self.url(),
+            LICENSE
+        )
+    }
+}
+
+#[inline]
+fn page_name(number: u16) -> String {
+    format!("scp-{:0>3}", number)
 }
 
-// figure out the number and title for an SCP
 #[inline]
-async fn get_number_and_title(client: &Client) -> crate::Result<Entry> {
-    loop {
-        let number = tls_rng().generate::<u16>().saturating_add(1);
-        let title = format!("scp-{:0>3}", number);
-        
-        // figure out if the page exists
-        let html = match client
-        .get(format!("http://www.scpwiki.com/{}", &title))
-        .send()
-        .await {
-            Err(_) => continue,
-            Ok(response) if response.status().is_success() => { response.text().await? }, 
-            Ok(_) => continue,
-        };
-
-        let a = authors(client, number).await?; 
-
-        // the page exists, get a title
-        let series_page_name = match number / 1000 {
-            0 => Cow::Borrowed("https:/www.scpwiki.com/scp-series"),
-            i => Cow::Owned(format!("https:/www.scpwiki.com/scp-series-{}", i)),
-        };
-        let series_page = client.get(series_page_name).send().await?.text().await?;  
-
-        let t = title::get_title(client, number)?;
-
-        unimplemented!()
+async fn fetch_page(page_name: &str) -> crate::Result<String> {
+    let data = http_get(&format!("{}/{}", SCP_WIKI_URL, page_name)).await?;
+    Ok(String::from_utf8_lossy(&data).into_owned())
+}
+
+/// Load an entry, making sure it has everything we need.
+#[inline]
+async fn load_entry(number: u16, attribution_metadata: &str) -> crate::Result<Entry> {
+    let name = page_name(number);
+
+    let authors = metadata::find_authors(&name, attribution_metadata);
+    if authors.is_empty() {
+        return Err(crate::Error::Msg(format!("No authors listed for {}", name)));
+    }
+
+    let series = fetch_page(&title::series_page_name(number)).await?;
+    let title = title::find_title(&name, &series)
+        .ok_or_else(|| crate::Error::Msg(format!("No title listed for {}", name)))?;
+    filter_text(&title)?;
+
+    let paragraphs = article_paragraphs(&fetch_page(&name).await?);
+    if paragraphs.is_empty() {
+        return Err(crate::Error::Msg(format!("{} has no text", name)));
+    }
+    paragraphs.iter().try_for_each(|p| filter_text(p))?;
+
+    Ok(Entry {
+        number,
+        title,
+        authors,
+        paragraphs,
+    })
+}
+
+/// Get the paragraphs of text that make up an article.
+#[inline]
+fn article_paragraphs(html: &str) -> Vec<String> {
+    static CAPTION_REGEX: Lazy<Regex> = Lazy::new(|| {
+        Regex::new(r#"(?s)<div class="scp-image-caption"[^>]*>.*?</div>"#)
+            .expect("Regex failed to compile")
+    });
+    static PARAGRAPH_REGEX: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r"(?s)<p>(.*?)</p>").expect("Regex failed to compile"));
+
+    // only look at the article itself, not the navigation or license boxes after it
+    let content = match html.find(r#"id="page-content""#) {
+        Some(start) => &html[start..],
+        None => return vec![],
+    };
+    let content = ["footer-wikiwalk-nav", "licensebox"]
+        .iter()
+        .filter_map(|marker| content.find(marker))
+        .min()
+        .map_or(content, |end| &content[..end]);
+    let content = CAPTION_REGEX.replace_all(content, "");
+
+    PARAGRAPH_REGEX
+        .captures_iter(&content)
+        .map(|caps| {
+            let text = strip_html_tags(&caps[1]);
+            decode_html_entities(&text)
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        .filter(|p| !p.is_empty())
+        .collect()
+}
+
+/// Frame source that reads out a random SCP article.
+#[derive(Debug, Default)]
+pub struct ScpSource {
+    params: ScpParams,
+}
+
+impl ScpSource {
+    #[inline]
+    async fn choose_entry(&self) -> crate::Result<Entry> {
+        let attribution_metadata = fetch_page("attribution-metadata").await?;
+
+        if let Some(number) = self.params.number {
+            return load_entry(number, &attribution_metadata).await;
+        }
+
+        for _ in 0..self.params.attempts {
+            // SCP-001 is a collection of proposals rather than an article
+            let number = tls_rng().generate_range(2, self.params.max_number.saturating_add(1));
+            match load_entry(number, &attribution_metadata).await {
+                Ok(entry) => return Ok(entry),
+                Err(e) => log::warn!("Skipping {}: {}", page_name(number), e),
+            }
+        }
+
+        Err(crate::Error::Msg(format!(
+            "Unable to find a usable SCP entry after {} attempts",
+            self.params.attempts
+        )))
+    }
+}
+
+impl FrameSource for ScpSource {
+    #[inline]
+    fn name(&self) -> &str {
+        "scp"
+    }
+
+    #[inline]
+    fn configure(&mut self, params: &serde_json::Value) -> crate::Result {
+        if !params.is_null() {
+            self.params = serde_json::from_value(params.clone())?;
+        }
+        if self.params.max_number < 2 {
+            return Err(crate::Error::InvalidConfig(
+                "max_number for the SCP source must be at least 2".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn produce<'a>(&'a self, ctx: &'a Context) -> ProduceFuture<'a> {
+        Box::pin(async move {
+            let entry = self.choose_entry().await?;
+            let designation = entry.designation();
+            log::info!("Reading {}: {}", &designation, &entry.title);
+
+            let metadata = VideoMetadata {
+                title: format!("{} - {}", &designation, entry.title.to_uppercase()),
+                thumbnail_text: format!("{} - {}", &designation, &entry.title),
+                thumbnail_template: self.params.thumbnail_template.clone(),
+                thumbnail_fields: [
+                    ("title".to_string(), entry.title.clone()),
+                    ("designation".to_string(), designation.clone()),
+                ]
+                .iter()
+                .cloned()
+                .collect(),
+                description: entry.description(),
+            };
+
+            let mut cards = vec![(
+                Card {
+                    author: "SCP Foundation".to_string(),
+                    score: None,
+                    age: None,
+                    depth: 0,
+                    body: format!("{}: {}", &designation, &entry.title),
+                },
+                1.5,
+            )];
+            cards.extend(entry.paragraphs.into_iter().map(|body| {
+                (
+                    Card {
+                        author: designation.clone(),
+                        score: None,
+                        age: None,
+                        depth: 0,
+                        body,
+                    },
+                    0.5,
+                )
+            }));
+
+            let basedir: Arc<Path> = ctx.basedir().await.into_boxed_path().into();
+            let (video_width, _) = ctx.video_size();
+            let card_width = (video_width as u32) * 4 / 5;
+            let theme = ctx.config().card_theme;
+
+            let frames = stream::iter(cards)
+                .then(move |(card, persists_after_tts)| {
+                    card_frame(card, persists_after_tts, basedir.clone(), card_width, theme)
+                })
+                .filter_map(util::ok_log);
+
+            Ok(SourceOutput {
+                metadata,
+                frames: Box::pin(frames),
+            })
+        })
+    }
+}
+
+#[inline]
+async fn card_frame(
+    card: Card,
+    persists_after_tts: f32,
+    basedir: Arc<Path>,
+    card_width: u32,
+    theme: Theme,
+) -> crate::Result<Frame> {
+    let path = basedir.join(format!(
+        "scp{}.png",
+        CARD_NUMBER.fetch_add(1, Ordering::SeqCst)
+    ));
+    let imagepath = save_card(&card, card_width, theme, path).await?;
+    Ok(Frame {
+        tts: card.body,
+        overlaid: String::new(),
+        imagepath: Some(imagepath),
+        imagefadesin: false,
+        persists_after_tts,
+        speaker: None,
+    })
+}
+
+#[test]
+fn test_article_paragraphs() {
+    let paragraphs = article_paragraphs(include_str!("../../fixtures/scp/scp-173.html"));
+    assert_eq!(paragraphs.len(), 5);
+    assert_eq!(paragraphs[0], "Item #: SCP-173");
+    assert_eq!(paragraphs[1], "Object Class: Euclid");
+    assert!(paragraphs[2].contains("SCP-173's container"));
+    assert!(paragraphs.iter().all(|p| !p.contains("photographed")));
+
+    assert!(article_paragraphs("<html><body></body></html>").is_empty());
+}
+
+#[test]
+fn test_description() {
+    let entry = Entry {
+        number: 173,
+        title: "The Sculpture - The Original".to_string(),
+        authors: vec!["Moto42".to_string(), "Lt Masipag".to_string()],
+        paragraphs: vec![],
     };
+    let description = entry.description();
+    assert!(description.contains("by Moto42, Lt Masipag"));
+    assert!(description.contains("https://scp-wiki.wikidot.com/scp-173"));
+    assert!(description.contains("CC BY-SA 3.0"));
 }
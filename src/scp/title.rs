@@ -0,0 +1,59 @@
+// MIT/Apache2 License
+
+use crate::util::{decode_html_entities, strip_html_tags};
+use regex::Regex;
+
+/// Find the title of an entry in one of the series pages.
+#[inline]
+pub fn find_title(page_name: &str, series_html: &str) -> Option<String> {
+    let title_regex = Regex::new(&format!(
+        r#"<a[^>]*href="/{}"[^>]*>[^<]*</a>\s*-\s*(.*?)</li>"#,
+        regex::escape(page_name)
+    ))
+    .expect("Regex failed to compile");
+
+    let title = title_regex.captures(series_html)?.get(1)?.as_str();
+    let title = decode_html_entities(&strip_html_tags(title))
+        .trim()
+        .to_string();
+
+    // placeholder titles for entries that haven't been written yet or are hidden on purpose
+    if title.is_empty() || title.starts_with('[') {
+        None
+    } else {
+        Some(title)
+    }
+}
+
+/// Name of the series page that lists the given entry.
+#[inline]
+pub fn series_page_name(number: u16) -> String {
+    match number / 1000 {
+        0 => "scp-series".to_string(),
+        i => format!("scp-series-{}", i + 1),
+    }
+}
+
+#[test]
+fn test_find_title() {
+    let series = include_str!("../../fixtures/scp/scp-series.html");
+    assert_eq!(
+        find_title("scp-173", series).as_deref(),
+        Some("The Sculpture - The Original")
+    );
+    assert_eq!(
+        find_title("scp-002", series).as_deref(),
+        Some("The \"Living\" Room")
+    );
+    assert_eq!(
+        find_title("scp-999", series).as_deref(),
+        Some("The Tickle Monster")
+    );
+    assert_eq!(find_title("scp-174", series), None);
+    assert_eq!(find_title("scp-055", series), None);
+    assert_eq!(find_title("scp-4000", series), None);
+
+    assert_eq!(series_page_name(173), "scp-series");
+    assert_eq!(series_page_name(1000), "scp-series-2");
+    assert_eq!(series_page_name(5999), "scp-series-6");
+}
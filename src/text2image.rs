@@ -17,7 +17,7 @@
 
 use image::{DynamicImage, GenericImageView, Rgba, RgbaImage};
 use rusttype::{point, vector, Font, PositionedGlyph, Scale, ScaledGlyph};
-use std::{cmp, env, mem};
+use std::{cmp, env, mem, path::Path};
 use tokio::{
     fs::File,
     io::{AsyncReadExt, BufReader},
@@ -30,7 +30,7 @@ const IMAGE_MARGIN: f32 = 1.6;
 static FONT: OnceCell<Font<'static>> = OnceCell::const_new();
 
 #[inline]
-async fn load_font() -> crate::Result<&'static Font<'static>> {
+pub async fn load_font() -> crate::Result<&'static Font<'static>> {
     FONT.get_or_try_init(|| async {
         log::info!("Loading font from file");
         let fontpath = env::current_dir()?.join("fonts").join("Ubuntu-L.ttf");
@@ -44,6 +44,17 @@ async fn load_font() -> crate::Result<&'static Font<'static>> {
     .await
 }
 
+/// Load a font other than the default one.
+#[inline]
+pub async fn load_font_file(path: &Path) -> crate::Result<Font<'static>> {
+    let mut fontfile = BufReader::new(File::open(path).await?);
+    let mut fontdata = vec![];
+    fontfile.read_to_end(&mut fontdata).await?;
+
+    Font::try_from_vec(fontdata)
+        .ok_or_else(|| crate::Error::Msg(format!("{:?} isn't a font file", path)))
+}
+
 // get the total width of some scaled glyphs
 #[inline]
 fn cumulative_width<'a, 'b, I: IntoIterator<Item = &'a ScaledGlyph<'b>>>(
@@ -72,7 +83,7 @@ fn word_glyphs(
     scale: Scale,
     max_image_width: u32,
     max_image_height: u32,
-    font: &'static Font<'static>,
+    font: &Font<'static>,
 ) -> crate::Result<Vec<PositionedGlyph<'static>>> {
     let v_metrics = font.v_metrics(scale);
     let ascent = v_metrics.ascent;
@@ -155,6 +166,76 @@ fn word_glyphs(
     }
 }
 
+/// Lay out text at the given font size, wrapping it so that it doesn't go past `max_width`.
+#[inline]
+pub fn layout_text(
+    text: &str,
+    font_size: f32,
+    max_width: u32,
+    font: &'static Font<'static>,
+) -> crate::Result<Vec<PositionedGlyph<'static>>> {
+    // word_glyphs() leaves a margin on the left, take it back out
+    let margin = vector(TEXT_MARGIN * font_size, 0.0);
+    let mut glyphs = word_glyphs(text, Scale::uniform(font_size), max_width, u32::MAX, font)?;
+    glyphs.iter_mut().for_each(|g| {
+        let pos = g.position();
+        g.set_position(pos - margin);
+    });
+    Ok(glyphs)
+}
+
+/// Get the width and height of the area some laid out glyphs cover.
+#[inline]
+pub fn glyphs_extent(glyphs: &[PositionedGlyph<'_>]) -> (u32, u32) {
+    glyphs
+        .iter()
+        .filter_map(|g| g.pixel_bounding_box())
+        .fold((0, 0), |(w, h), bb| {
+            (
+                cmp::max(w, cmp::max(bb.max.x, 0) as u32),
+                cmp::max(h, cmp::max(bb.max.y, 0) as u32),
+            )
+        })
+}
+
+/// Draw laid out glyphs onto an image at an offset, blending them over what's already there.
+#[inline]
+pub fn draw_glyphs(
+    img: &mut RgbaImage,
+    glyphs: &[PositionedGlyph<'_>],
+    offset_x: i32,
+    offset_y: i32,
+    color: [u8; 3],
+) {
+    let (width, height) = img.dimensions();
+    glyphs.iter().for_each(|glyph| {
+        if let Some(bounding_box) = glyph.pixel_bounding_box() {
+            glyph.draw(|x, y, intensity| {
+                let px = bounding_box.min.x + offset_x + x as i32;
+                let py = bounding_box.min.y + offset_y + y as i32;
+                if px < 0 || py < 0 || px as u32 >= width || py as u32 >= height {
+                    return;
+                }
+
+                let Rgba([r, g, b, a]) = *img.get_pixel(px as u32, py as u32);
+                let blend = |under: u8, over: u8| {
+                    ((under as f32) * (1.0 - intensity) + (over as f32) * intensity) as u8
+                };
+                img.put_pixel(
+                    px as u32,
+                    py as u32,
+                    Rgba([
+                        blend(r, color[0]),
+                        blend(g, color[1]),
+                        blend(b, color[2]),
+                        cmp::max(a, (intensity * 255.0) as u8),
+                    ]),
+                );
+            });
+        }
+    });
+}
+
 /// Generate an image to use as a border background.
 #[inline]
 fn border_image<I: GenericImageView<Pixel = Rgba<u8>>>(
@@ -198,6 +279,30 @@ pub async fn text_overlay(
     border_width: u32,
 ) -> crate::Result<(RgbaImage, u32, u32)> {
     let font = load_font().await?;
+    text_overlay_with_font(
+        text,
+        font,
+        font_size,
+        max_image_width,
+        max_image_height,
+        word_color,
+        border_color,
+        border_width,
+    )
+}
+
+/// Same as `text_overlay`, but with a font other than the default one.
+#[inline]
+pub fn text_overlay_with_font(
+    text: &str,
+    font: &Font<'static>,
+    font_size: f32,
+    max_image_width: u32,
+    max_image_height: u32,
+    word_color: [u8; 3],
+    border_color: [u8; 3],
+    border_width: u32,
+) -> crate::Result<(RgbaImage, u32, u32)> {
     let scale = Scale::uniform(font_size);
 
     // set up the glyphs
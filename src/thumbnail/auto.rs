@@ -0,0 +1,225 @@
+/*
+ * This file is part of KOTI.
+ *
+ * KOTI is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * KOTI is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Afero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with KOTI.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Make a thumbnail without a template, from the pictures in the video and its thumbnail text.
+
+use super::{encode_png, ThumbnailContent};
+use crate::text2image;
+use image::{imageops, DynamicImage, RgbImage};
+use std::path::PathBuf;
+
+pub const WIDTH: u32 = 1280;
+pub const HEIGHT: u32 = 720;
+
+/// How many of the video's pictures to look at when picking the background.
+const CANDIDATES: usize = 8;
+/// How much more interesting than the title screenshot another picture has to be to be used
+/// instead.
+const TITLE_BONUS: f32 = 1.25;
+/// How blurry the background is, as the standard deviation of the blur at a quarter of the size.
+const BLUR: f32 = 4.0;
+/// How much of the background's brightness is left, so the text stands out.
+const DIM: f32 = 0.55;
+
+/// How much is going on in a picture: how much contrast and color it has, measured on a small
+/// copy of it.
+#[inline]
+fn interest(img: &DynamicImage) -> f32 {
+    let small = img
+        .resize_exact(64, 36, imageops::FilterType::Triangle)
+        .to_rgb8();
+    let count = small.pixels().len() as f32;
+    let luma: Vec<f32> = small
+        .pixels()
+        .map(|p| 0.299 * p[0] as f32 + 0.587 * p[1] as f32 + 0.114 * p[2] as f32)
+        .collect();
+    let mean = luma.iter().sum::<f32>() / count;
+    let contrast = (luma.iter().map(|l| (l - mean).powi(2)).sum::<f32>() / count).sqrt();
+
+    // colorfulness as described by Hasler and Süsstrunk
+    let (rg, yb): (Vec<f32>, Vec<f32>) = small
+        .pixels()
+        .map(|p| {
+            let (r, g, b) = (p[0] as f32, p[1] as f32, p[2] as f32);
+            (r - g, 0.5 * (r + g) - b)
+        })
+        .unzip();
+    let stats = |v: &[f32]| {
+        let mean = v.iter().sum::<f32>() / count;
+        let sd = (v.iter().map(|x| (x - mean).powi(2)).sum::<f32>() / count).sqrt();
+        (mean, sd)
+    };
+    let ((rg_mean, rg_sd), (yb_mean, yb_sd)) = (stats(&rg), stats(&yb));
+    let colorfulness =
+        (rg_sd.powi(2) + yb_sd.powi(2)).sqrt() + 0.3 * (rg_mean.powi(2) + yb_mean.powi(2)).sqrt();
+
+    contrast + colorfulness
+}
+
+/// Pick the most interesting of the first few pictures, favoring the first one, which is usually
+/// the title screenshot. Pictures that can't be opened are skipped.
+#[inline]
+fn pick_background(frames: &[PathBuf]) -> Option<DynamicImage> {
+    frames
+        .iter()
+        .take(CANDIDATES)
+        .enumerate()
+        .filter_map(|(i, path)| match image::open(path) {
+            Ok(img) => {
+                let bonus = if i == 0 { TITLE_BONUS } else { 1.0 };
+                Some((interest(&img) * bonus, img))
+            }
+            Err(e) => {
+                log::warn!("Unable to open {:?} for the thumbnail: {}", path, e);
+                None
+            }
+        })
+        .fold(
+            None,
+            |best: Option<(f32, DynamicImage)>, (score, img)| match best {
+                Some(best) if best.0 >= score => Some(best),
+                _ => Some((score, img)),
+            },
+        )
+        .map(|(_, img)| img)
+}
+
+/// Crop the picture to fill the thumbnail, then blur and dim it.
+#[inline]
+fn background(img: &DynamicImage) -> RgbImage {
+    // blurring a small copy is much faster, and looks the same once it's scaled back up
+    let small = img.resize_to_fill(WIDTH / 4, HEIGHT / 4, imageops::FilterType::Triangle);
+    let mut bg = small
+        .blur(BLUR)
+        .resize_exact(WIDTH, HEIGHT, imageops::FilterType::Triangle)
+        .to_rgb8();
+    bg.pixels_mut()
+        .flat_map(|p| p.0.iter_mut())
+        .for_each(|c| *c = (*c as f32 * DIM) as u8);
+    bg
+}
+
+/// Draw the text as large as it fits in the middle of the background.
+#[inline]
+fn draw_text(
+    bg: &mut RgbImage,
+    text: &str,
+    color: [u8; 3],
+    font: &rusttype::Font<'static>,
+) -> crate::Result {
+    let (max_w, max_h) = (WIDTH * 9 / 10, HEIGHT * 8 / 10);
+    let mut size = 140.0f32;
+    let timage = loop {
+        match text2image::text_overlay_with_font(
+            text,
+            font,
+            size,
+            max_w,
+            max_h,
+            color,
+            [0, 0, 0],
+            10,
+        ) {
+            // the text is wrapped to fit, but the image has a margin on top of that
+            Ok((timage, w, h)) if w <= WIDTH && h <= HEIGHT => break timage,
+            Ok(_) | Err(crate::Error::GlyphOverflow) if size > 20.0 => size -= 4.0,
+            Ok(_) => return Err(crate::Error::GlyphOverflow),
+            Err(e) => return Err(e),
+        }
+    };
+
+    // center what was drawn, not the margin around it
+    let drawn = || timage.enumerate_pixels().filter(|(_, _, p)| p[3] > 0);
+    let (left, top) = (
+        drawn().map(|(x, _, _)| x).min().unwrap_or(0),
+        drawn().map(|(_, y, _)| y).min().unwrap_or(0),
+    );
+    let (right, bottom) = (
+        drawn().map(|(x, _, _)| x + 1).max().unwrap_or(0),
+        drawn().map(|(_, y, _)| y + 1).max().unwrap_or(0),
+    );
+    let timage = imageops::crop_imm(&timage, left, top, right - left, bottom - top).to_image();
+    let (x, y) = ((WIDTH - timage.width()) / 2, (HEIGHT - timage.height()) / 2);
+    let mut composed = DynamicImage::ImageRgb8(bg.clone()).to_rgba8();
+    imageops::overlay(&mut composed, &timage, x, y);
+    *bg = DynamicImage::ImageRgba8(composed).to_rgb8();
+    Ok(())
+}
+
+/// Make a 1280x720 thumbnail from the video's pictures and its thumbnail text, drawn in
+/// `text_color`, and save it as a PNG at `outpath`.
+#[inline]
+pub async fn auto_thumbnail(
+    content: &ThumbnailContent,
+    text_color: [u8; 3],
+    outpath: PathBuf,
+) -> crate::Result<PathBuf> {
+    let font = text2image::load_font().await?.clone();
+    let frames = content.frames.clone();
+    let text = content
+        .fields
+        .get("text")
+        .or_else(|| content.fields.get("title"))
+        .filter(|text| !text.trim().is_empty())
+        .cloned();
+
+    tokio::task::spawn_blocking(move || {
+        let mut bg = match pick_background(&frames) {
+            Some(img) => background(&img),
+            None => RgbImage::from_pixel(WIDTH, HEIGHT, image::Rgb([20, 20, 30])),
+        };
+        if let Some(text) = text {
+            draw_text(&mut bg, &text, text_color, &font)?;
+        }
+        std::fs::write(&outpath, encode_png(&bg)?)?;
+        crate::Result::Ok(outpath)
+    })
+    .await?
+}
+
+#[test]
+fn test_pick_background() {
+    let dir = std::env::temp_dir().join(format!("koti-auto-thumbnail-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let save = |name: &str, img: RgbImage| {
+        let path = dir.join(name);
+        img.save(&path).unwrap();
+        path
+    };
+    let flat = save(
+        "flat.png",
+        RgbImage::from_pixel(160, 90, image::Rgb([128; 3])),
+    );
+    let busy = save(
+        "busy.png",
+        RgbImage::from_fn(160, 90, |x, y| match (x / 10 + y / 10) % 3 {
+            0 => image::Rgb([255, 0, 0]),
+            1 => image::Rgb([0, 0, 255]),
+            _ => image::Rgb([255, 255, 255]),
+        }),
+    );
+    let missing = dir.join("missing.png");
+
+    // a flat title screenshot loses out to something with more going on
+    let picked = pick_background(&[flat.clone(), missing, busy]).unwrap();
+    assert!(interest(&picked) > 10.0);
+    assert!(pick_background(&[]).is_none());
+
+    let bg = background(&picked);
+    assert_eq!(bg.dimensions(), (WIDTH, HEIGHT));
+    let _ = std::fs::remove_dir_all(&dir);
+}
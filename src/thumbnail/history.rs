@@ -0,0 +1,109 @@
+/*
+ * This file is part of KOTI.
+ *
+ * KOTI is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * KOTI is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Afero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with KOTI.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! A record of which thumbnail was used for each video, kept in the data directory, for comparing
+//! how the variants do.
+
+use super::ThumbnailVariant;
+use crate::context::Context;
+use std::{io::ErrorKind, mem, path::PathBuf};
+use tokio::{
+    fs::{self, File},
+    io::{AsyncReadExt, AsyncWriteExt},
+};
+
+/// The thumbnail a video went out with.
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct ThumbnailRecord {
+    pub job: String,
+    /// The video's YouTube ID, or `None` if it wasn't uploaded.
+    pub video_id: Option<String>,
+    pub title: String,
+    /// When the video went out, in seconds since the Unix epoch.
+    pub time: u64,
+    /// Name of the variant that was used.
+    pub used: String,
+    /// Whether the variant was picked by hand, instead of being the first one.
+    pub picked: bool,
+    /// Every variant that was made for the video, with the paths of their copies in the data
+    /// directory.
+    pub variants: Vec<ThumbnailVariant>,
+}
+
+impl ThumbnailRecord {
+    /// The variant that was used.
+    #[inline]
+    pub fn used_variant(&self) -> Option<&ThumbnailVariant> {
+        self.variants.iter().find(|v| v.name == self.used)
+    }
+}
+
+#[inline]
+async fn history_path(ctx: &Context) -> PathBuf {
+    ctx.datadir().await.join("thumbnail_history.json")
+}
+
+/// Load every record, oldest first, or none if there aren't any yet.
+#[inline]
+pub async fn load_history(ctx: &Context) -> crate::Result<Vec<ThumbnailRecord>> {
+    let mut f = match File::open(history_path(ctx).await).await {
+        Ok(f) => f,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e.into()),
+    };
+    let mut data = vec![];
+    f.read_to_end(&mut data).await?;
+
+    let history = serde_json::from_slice(&data)?;
+    Ok(history)
+}
+
+/// Add a record to the history, copying its variants out of the job directory so they outlive
+/// the job. A job that already has a record, because it was retried, is left as it is.
+#[inline]
+pub async fn record_thumbnail(ctx: &Context, mut record: ThumbnailRecord) -> crate::Result {
+    let mut history = load_history(ctx).await?;
+    if history.iter().any(|r| r.job == record.job) {
+        log::info!("Thumbnail for job {} is already recorded", &record.job);
+        return Ok(());
+    }
+
+    let dir = ctx
+        .datadir()
+        .await
+        .join("thumbnail_history")
+        .join(&record.job);
+    fs::create_dir_all(&dir).await?;
+    for variant in &mut record.variants {
+        let copy = dir.join(format!("{}.png", &variant.name));
+        fs::copy(&variant.path, &copy).await?;
+        variant.path = copy;
+    }
+
+    history.push(record);
+
+    // write to a temporary file first, so a crash never leaves a half-written history behind
+    let path = history_path(ctx).await;
+    let tmppath = path.with_extension("json.tmp");
+    let data = serde_json::to_vec_pretty(&history)?;
+    let mut f = File::create(&tmppath).await?;
+    f.write_all(&data).await?;
+    f.sync_all().await?;
+    mem::drop(f);
+    fs::rename(&tmppath, &path).await?;
+    Ok(())
+}
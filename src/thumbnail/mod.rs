@@ -15,43 +15,110 @@
  * along with KOTI.  If not, see <https://www.gnu.org/licenses/>.
  */
 
+mod auto;
+mod history;
 mod template;
+mod variants;
 
 use crate::context::Context;
-use std::{path::PathBuf, sync::Arc};
-use template::ThumbnailTemplate;
+use image::{
+    codecs::png::{CompressionType, FilterType, PngEncoder},
+    ColorType, RgbImage,
+};
+use std::{io::ErrorKind, iter, path::PathBuf, sync::Arc};
 use tokio::{
-    fs::File,
+    fs::{self, File},
     io::{AsyncReadExt, AsyncWriteExt},
 };
 
+pub use auto::auto_thumbnail;
+pub use history::{load_history, record_thumbnail, ThumbnailRecord};
+pub use template::{ThumbnailContent, ThumbnailTemplate};
+pub use variants::{ThumbnailSettings, ThumbnailVariant};
+
+/// The largest thumbnail YouTube takes, in bytes.
+const MAX_THUMBNAIL_SIZE: usize = 2 * 1024 * 1024;
+
+/// Create the thumbnails for a video in the job directory: one with the template with the given
+/// name and the others configured, and automatic ones in a few colors, each with one of the ways
+/// of wording the text. Variants that can't be made are skipped.
 #[inline]
-pub async fn create_thumbnail(ctx: Arc<Context>) -> crate::Result {
-    // load thumbnail templates
+pub async fn create_thumbnail(
+    ctx: Arc<Context>,
+    content: ThumbnailContent,
+    template: String,
+) -> crate::Result<Vec<ThumbnailVariant>> {
+    let settings = &ctx.config().thumbnails;
     let thumbnails = load_thumbnails(&ctx).await?;
 
-    log::info!("Waiting for thumbnail info");
-    ctx.wait_for_thumbnail().await;
-    log::info!("Thumbnail wait ended!");
+    // missing templates get replaced by automatic thumbnails
+    let mut templates: Vec<String> = vec![];
+    for name in iter::once(&template).chain(&settings.templates) {
+        if templates.contains(name) {
+            continue;
+        }
+        match thumbnails.iter().any(|t| t.id() == name) {
+            true => templates.push(name.clone()),
+            false => log::warn!(
+                "Unable to find thumbnail template with name {}, making one automatically",
+                name
+            ),
+        }
+    }
 
-    // get the thumbnail stuff
-    let text = ctx.take_thumbnail_text().await;
-    let template = ctx.take_thumbnail_template().await;
+    let dir = ctx.basedir().await.join("thumbnails");
+    fs::create_dir_all(&dir).await?;
+    let plan = variants::plan(
+        &templates,
+        &variants::phrasings(&content),
+        settings.variants,
+    );
 
-    // get the desired template
-    let template = thumbnails
-        .into_iter()
-        .find(|thumbnail| thumbnail.id() == &template)
-        .ok_or_else(|| {
-            crate::Error::Msg(format!("Unable to find template with name: {}", template))
-        })?;
-
-    // apply it
-    let applied_path = template.apply(text, ctx.clone()).await?;
+    let mut made = vec![];
+    let mut error = None;
+    for (i, (style, text)) in plan.into_iter().enumerate() {
+        let name = char::from(b'a' + i as u8).to_string();
+        let outpath = dir.join(format!("{}.png", &name));
+        let mut content = content.clone();
+        content.fields.insert("text".to_string(), text.clone());
 
-    ctx.set_thumbnail_path(applied_path).await;
+        let (template, color, res) = match style {
+            variants::Style::Template(id) => {
+                let template = thumbnails
+                    .iter()
+                    .find(|t| t.id() == id)
+                    .cloned()
+                    .expect("only templates that exist are planned");
+                (Some(id), None, template.apply(&content, outpath).await)
+            }
+            variants::Style::Automatic(color) => (
+                None,
+                Some(color),
+                auto_thumbnail(&content, color, outpath).await,
+            ),
+        };
+        match res {
+            Ok(path) => made.push(ThumbnailVariant {
+                name,
+                template,
+                color,
+                text,
+                path,
+            }),
+            Err(e) => {
+                log::error!("Unable to make thumbnail variant {}: {}", &name, e);
+                error = Some(e);
+            }
+        }
+    }
 
-    Ok(())
+    match (made.is_empty(), error) {
+        (true, Some(e)) => Err(e),
+        (true, None) => Err(crate::Error::StaticMsg(
+            "There is no text to make a thumbnail with",
+        )),
+        _ => Ok(made),
+    }
 }
 
 #[inline]
@@ -64,19 +131,95 @@ pub async fn add_thumbnail_to_collection(
     w: u32,
     h: u32,
 ) -> crate::Result {
-    let mut thumbnails = load_thumbnails(ctx).await.unwrap_or(vec![]);
-    thumbnails.push(ThumbnailTemplate::new(id, path, x, y, w, h));
+    let mut thumbnails = load_thumbnails(ctx).await?;
+    if thumbnails.iter().any(|t| t.id() == id) {
+        return Err(crate::Error::Msg(format!(
+            "There is already a thumbnail template named {}",
+            id
+        )));
+    }
+
+    let template = ThumbnailTemplate::new(id, path, x, y, w, h);
+    template.validate().await?;
+    thumbnails.push(template);
+    save_thumbnails(ctx, thumbnails).await
+}
+
+/// Remove the template with the given name.
+#[inline]
+pub async fn remove_thumbnail(ctx: &Context, id: &str) -> crate::Result {
+    let mut thumbnails = load_thumbnails(ctx).await?;
+    let count = thumbnails.len();
+    thumbnails.retain(|t| t.id() != id);
+    if thumbnails.len() == count {
+        return Err(crate::Error::Msg(format!(
+            "Unable to find template with name: {}",
+            id
+        )));
+    }
     save_thumbnails(ctx, thumbnails).await
 }
 
+/// Draw sample content on the template with the given name, saving it at `outpath`.
+#[inline]
+pub async fn preview_thumbnail(
+    ctx: &Context,
+    id: &str,
+    content: ThumbnailContent,
+    outpath: PathBuf,
+) -> crate::Result<PathBuf> {
+    let template = load_thumbnails(ctx)
+        .await?
+        .into_iter()
+        .find(|t| t.id() == id)
+        .ok_or_else(|| crate::Error::Msg(format!("Unable to find template with name: {}", id)))?;
+    template.validate().await?;
+    template.apply(&content, outpath).await
+}
+
+/// Encode a thumbnail as a PNG, throwing away detail in its colors until it's small enough for
+/// YouTube.
+#[inline]
+fn encode_png(img: &RgbImage) -> crate::Result<Vec<u8>> {
+    for dropped_bits in 0..=4 {
+        let mask = 0xffu8 << dropped_bits;
+        let mut data = img.as_raw().clone();
+        data.iter_mut().for_each(|c| *c &= mask);
+
+        let mut png = vec![];
+        PngEncoder::new_with_quality(&mut png, CompressionType::Best, FilterType::Paeth).encode(
+            &data,
+            img.width(),
+            img.height(),
+            ColorType::Rgb8,
+        )?;
+        if png.len() <= MAX_THUMBNAIL_SIZE {
+            return Ok(png);
+        }
+        log::debug!(
+            "Thumbnail is {} bytes with {} bits of color dropped",
+            png.len(),
+            dropped_bits
+        );
+    }
+    Err(crate::Error::StaticMsg(
+        "Unable to make the thumbnail small enough for YouTube",
+    ))
+}
+
 #[inline]
 async fn thumbnails_path(ctx: &Context) -> PathBuf {
     ctx.datadir().await.join("thumbnails.json")
 }
 
+/// Load the thumbnail templates, or none if there aren't any yet.
 #[inline]
-async fn load_thumbnails(ctx: &Context) -> crate::Result<Vec<ThumbnailTemplate>> {
-    let mut f = File::open(thumbnails_path(ctx).await).await?;
+pub async fn load_thumbnails(ctx: &Context) -> crate::Result<Vec<ThumbnailTemplate>> {
+    let mut f = match File::open(thumbnails_path(ctx).await).await {
+        Ok(f) => f,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e.into()),
+    };
     let mut data = vec![];
     f.read_to_end(&mut data).await?;
 
@@ -91,3 +234,24 @@ async fn save_thumbnails(ctx: &Context, t: Vec<ThumbnailTemplate>) -> crate::Res
     f.write_all(&data).await?;
     Ok(())
 }
+
+#[test]
+fn test_encode_png() {
+    use nanorand::{tls_rng, RNG};
+
+    // noise doesn't compress, so it has to lose some color to fit
+    let mut rng = tls_rng();
+    let noise = RgbImage::from_fn(auto::WIDTH, auto::HEIGHT, |_, _| {
+        image::Rgb([rng.generate(), rng.generate(), rng.generate()])
+    });
+    let png = encode_png(&noise).unwrap();
+    assert!(png.len() <= MAX_THUMBNAIL_SIZE);
+    let decoded = image::load_from_memory(&png).unwrap().to_rgb8();
+    assert_eq!(decoded.dimensions(), noise.dimensions());
+    assert_ne!(decoded, noise);
+
+    // a plain image is left as it is
+    let plain = RgbImage::from_pixel(auto::WIDTH, auto::HEIGHT, image::Rgb([1, 2, 3]));
+    let decoded = image::load_from_memory(&encode_png(&plain).unwrap()).unwrap();
+    assert_eq!(decoded.to_rgb8(), plain);
+}
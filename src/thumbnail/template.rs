@@ -15,13 +15,18 @@
  * along with KOTI.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use crate::{context::Context, text2image};
+//! Thumbnail templates: a base image with layers of text and pictures drawn over it, in order.
+
+use super::encode_png;
+use crate::{image_size::image_size, text2image};
+use image::{imageops, DynamicImage, Rgba, RgbaImage};
+use rusttype::Font;
 use std::{
+    collections::BTreeMap,
     path::{Path, PathBuf},
-    sync::Arc,
 };
 
-#[derive(Debug, Copy, Clone, serde::Deserialize, serde::Serialize)]
+#[derive(Debug, Copy, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
 struct Rect {
     x: u32,
     y: u32,
@@ -29,20 +34,228 @@ struct Rect {
     h: u32,
 }
 
-#[derive(Debug, serde::Deserialize, serde::Serialize)]
+/// Something drawn on top of the base image.
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum Layer {
+    Text(TextLayer),
+    Image(ImageLayer),
+}
+
+/// Text that's shrunk until it fits in its rectangle.
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+struct TextLayer {
+    /// What to draw, with `{field}` replaced by the value of the field. The layer is left out if
+    /// one of its fields isn't there.
+    #[serde(default = "default_text")]
+    text: String,
+    rect: Rect,
+    #[serde(default = "default_color")]
+    color: [u8; 3],
+    #[serde(default)]
+    stroke: Stroke,
+    #[serde(default)]
+    shadow: Option<Shadow>,
+    /// A TTF or OTF file to use instead of the default font.
+    #[serde(default)]
+    font: Option<PathBuf>,
+    /// The largest the text can be.
+    #[serde(default = "default_font_size")]
+    font_size: f32,
+    /// Degrees clockwise.
+    #[serde(default)]
+    rotation: f32,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+struct Stroke {
+    color: [u8; 3],
+    width: u32,
+}
+
+impl Default for Stroke {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            color: [15, 15, 15],
+            width: 5,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+struct Shadow {
+    color: [u8; 3],
+    /// How far the shadow is from the text, in pixels to the right and down.
+    offset: (i32, i32),
+    /// Standard deviation of the blur, in pixels.
+    blur: f32,
+    opacity: f32,
+}
+
+impl Default for Shadow {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            color: [0, 0, 0],
+            offset: (6, 6),
+            blur: 4.0,
+            opacity: 0.7,
+        }
+    }
+}
+
+/// A picture that's scaled to fit in its rectangle, such as a frame from the video.
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+struct ImageLayer {
+    /// Name of the picture to draw. The layer is left out if there isn't one with that name.
+    slot: String,
+    rect: Rect,
+    /// Degrees clockwise.
+    #[serde(default)]
+    rotation: f32,
+}
+
+#[inline]
+fn default_text() -> String {
+    "{text}".to_string()
+}
+
+#[inline]
+fn default_color() -> [u8; 3] {
+    [255, 255, 255]
+}
+
+#[inline]
+fn default_font_size() -> f32 {
+    80.0
+}
+
+impl Layer {
+    /// The text layer older templates had, with one rectangle for the thumbnail text.
+    #[inline]
+    fn legacy(rect: Rect) -> Self {
+        Layer::Text(TextLayer {
+            text: default_text(),
+            rect,
+            color: default_color(),
+            stroke: Stroke::default(),
+            shadow: None,
+            font: None,
+            font_size: default_font_size(),
+            rotation: 0.0,
+        })
+    }
+
+    #[inline]
+    fn rect(&self) -> Rect {
+        match self {
+            Layer::Text(layer) => layer.rect,
+            Layer::Image(layer) => layer.rect,
+        }
+    }
+
+    #[inline]
+    fn rotation(&self) -> f32 {
+        match self {
+            Layer::Text(layer) => layer.rotation,
+            Layer::Image(layer) => layer.rotation,
+        }
+    }
+}
+
+/// What goes in a thumbnail's layers.
+#[derive(Debug, Clone, Default)]
+pub struct ThumbnailContent {
+    /// Values for the `{field}`s in text layers.
+    pub fields: BTreeMap<String, String>,
+    /// Pictures for image layers, by slot.
+    pub images: BTreeMap<String, PathBuf>,
+    /// The pictures in the video, in order, for making a thumbnail without a template.
+    pub frames: Vec<PathBuf>,
+}
+
+impl ThumbnailContent {
+    /// Content with only the thumbnail text, as the `text` field.
+    #[inline]
+    pub fn new(text: String) -> Self {
+        let mut fields = BTreeMap::new();
+        fields.insert("text".to_string(), text);
+        Self {
+            fields,
+            images: BTreeMap::new(),
+            frames: vec![],
+        }
+    }
+
+    /// Replace each `{field}` in the text with its value, or `None` if one of them is missing.
+    #[inline]
+    fn fill(&self, text: &str) -> Option<String> {
+        let mut filled = String::with_capacity(text.len());
+        let mut rest = text;
+        while let Some(start) = rest.find('{') {
+            filled.push_str(&rest[..start]);
+            match rest[start..].find('}') {
+                Some(end) => {
+                    filled.push_str(self.fields.get(&rest[start + 1..start + end])?);
+                    rest = &rest[start + end + 1..];
+                }
+                None => {
+                    rest = &rest[start..];
+                    break;
+                }
+            }
+        }
+        filled.push_str(rest);
+        Some(filled)
+    }
+}
+
+/// How templates are saved, including ones from before they had layers.
+#[derive(serde::Deserialize)]
+struct RawTemplate {
+    id: String,
+    path: PathBuf,
+    #[serde(default)]
+    trect: Option<Rect>,
+    #[serde(default)]
+    layers: Vec<Layer>,
+}
+
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+#[serde(from = "RawTemplate")]
 pub struct ThumbnailTemplate {
     id: String,
     path: PathBuf,
-    trect: Rect,
+    layers: Vec<Layer>,
+}
+
+impl From<RawTemplate> for ThumbnailTemplate {
+    #[inline]
+    fn from(raw: RawTemplate) -> Self {
+        let RawTemplate {
+            id,
+            path,
+            trect,
+            mut layers,
+        } = raw;
+        if let Some(trect) = trect {
+            layers.insert(0, Layer::legacy(trect));
+        }
+        Self { id, path, layers }
+    }
 }
 
 impl ThumbnailTemplate {
+    /// A template with the thumbnail text drawn in one rectangle.
     #[inline]
     pub fn new(id: String, path: PathBuf, x: u32, y: u32, w: u32, h: u32) -> Self {
         Self {
             id,
             path,
-            trect: Rect { x, y, w, h },
+            layers: vec![Layer::legacy(Rect { x, y, w, h })],
         }
     }
 
@@ -52,54 +265,377 @@ impl ThumbnailTemplate {
     }
 
     #[inline]
-    pub async fn apply(self, text: String, ctx: Arc<Context>) -> crate::Result<PathBuf> {
-        let Self { id, path, trect } = self;
-        let outpath = ctx.basedir().await.join("thumbnail.png");
-        let base_image = tokio::task::spawn_blocking(move || {
-            let mut base_image = image::open(&path)?;
-            crate::Result::Ok(base_image)
-        });
-
-        // iterate over the text image until we can effectively fit it within the rect
-        let timage = tokio::spawn(async move {
-            let mut current_size = 80f32;
-            let timage = loop {
-                let res = text2image::text_overlay(
-                    &text,
-                    current_size,
-                    trect.w,
-                    trect.h,
-                    [255, 255, 255],
-                    [15, 15, 15],
-                    5,
-                )
-                .await;
-                match res {
-                    Ok((timage, _, _)) => break timage,
-                    Err(crate::Error::GlyphOverflow) => {
-                        current_size -= 1.0;
-                        if current_size < 1.0f32 {
-                            return Err(crate::Error::GlyphOverflow);
-                        }
-                    }
-                    Err(e) => {
-                        return Err(e);
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// A short description of each layer.
+    #[inline]
+    pub fn describe_layers(&self) -> Vec<String> {
+        self.layers
+            .iter()
+            .map(|layer| {
+                let Rect { x, y, w, h } = layer.rect();
+                let what = match layer {
+                    Layer::Text(layer) => format!("text {:?}", &layer.text),
+                    Layer::Image(layer) => format!("image {:?}", &layer.slot),
+                };
+                format!("{} {}x{} at {}, {}", what, w, h, x, y)
+            })
+            .collect()
+    }
+
+    /// Make sure the base image can be opened, every layer fits inside of it and the fonts can
+    /// be loaded.
+    #[inline]
+    pub async fn validate(&self) -> crate::Result {
+        let size = image_size(&self.path).await?;
+        for (i, layer) in self.layers.iter().enumerate() {
+            check_rect(&self.id, i, layer.rect(), size)?;
+            if !layer.rotation().is_finite() {
+                return Err(self.invalid(i, "has an invalid rotation"));
+            }
+            if let Layer::Text(layer) = layer {
+                if !layer.font_size.is_finite() || layer.font_size < 1.0 {
+                    return Err(self.invalid(i, "has an invalid font size"));
+                }
+                if let Some(shadow) = &layer.shadow {
+                    if !(0.0..=100.0).contains(&shadow.blur)
+                        || !(0.0..=1.0).contains(&shadow.opacity)
+                    {
+                        return Err(self.invalid(i, "has an invalid shadow"));
                     }
                 }
-            };
+                if let Some(font) = &layer.font {
+                    text2image::load_font_file(font).await?;
+                }
+            }
+        }
+        Ok(())
+    }
 
-            crate::Result::Ok(timage)
-        });
+    #[inline]
+    fn invalid(&self, layer: usize, msg: &str) -> crate::Error {
+        crate::Error::Msg(format!(
+            "Layer {} of thumbnail template {} {}",
+            layer, &self.id, msg
+        ))
+    }
 
-        let (mut base_image, timage) = (base_image.await??, timage.await??);
+    /// Draw the layers on the base image and save it as a PNG at `outpath`.
+    #[inline]
+    pub async fn apply(
+        self,
+        content: &ThumbnailContent,
+        outpath: PathBuf,
+    ) -> crate::Result<PathBuf> {
+        let Self { path, layers, .. } = self;
+        let mut base_image =
+            tokio::task::spawn_blocking(move || crate::Result::Ok(image::open(&path)?.to_rgba8()))
+                .await??;
+
+        for layer in layers {
+            let rect = layer.rect();
+            let rotation = layer.rotation();
+            let (sprite, (x, y)) = match layer {
+                Layer::Text(layer) => {
+                    let text = match content.fill(&layer.text) {
+                        Some(text) if !text.trim().is_empty() => text,
+                        _ => {
+                            log::debug!("Leaving out text layer {:?}", &layer.text);
+                            continue;
+                        }
+                    };
+                    let font = match &layer.font {
+                        Some(font) => text2image::load_font_file(font).await?,
+                        None => text2image::load_font().await?.clone(),
+                    };
+                    tokio::task::spawn_blocking(move || render_text(&layer, &text, &font)).await??
+                }
+                Layer::Image(layer) => {
+                    let path = match content.images.get(&layer.slot) {
+                        Some(path) => path.clone(),
+                        None => {
+                            log::debug!("Leaving out image layer {:?}", &layer.slot);
+                            continue;
+                        }
+                    };
+                    tokio::task::spawn_blocking(move || render_image(&path, rect)).await??
+                }
+            };
 
-        image::imageops::overlay(&mut base_image, &timage, trect.x, trect.y);
+            // turn the layer around its middle
+            let rotated = rotate(&sprite, rotation);
+            let x = i64::from(rect.x)
+                - i64::from(x)
+                - (i64::from(rotated.width()) - i64::from(sprite.width())) / 2;
+            let y = i64::from(rect.y)
+                - i64::from(y)
+                - (i64::from(rotated.height()) - i64::from(sprite.height())) / 2;
+            overlay_at(&mut base_image, &rotated, x, y);
+        }
 
         // save the image
         tokio::task::spawn_blocking(move || {
-            base_image.save_with_format(&outpath, image::ImageFormat::Png)?;
+            let png = encode_png(&DynamicImage::ImageRgba8(base_image).to_rgb8())?;
+            std::fs::write(&outpath, png)?;
             crate::Result::Ok(outpath)
         })
         .await?
     }
 }
+
+/// Draw the text as large as it can be while fitting in the layer's rectangle, along with where
+/// the rectangle's corner is in the image.
+#[inline]
+fn render_text(
+    layer: &TextLayer,
+    text: &str,
+    font: &Font<'static>,
+) -> crate::Result<(RgbaImage, (u32, u32))> {
+    let Rect { w, h, .. } = layer.rect;
+    let mut current_size = layer.font_size;
+    let timage = loop {
+        let res = text2image::text_overlay_with_font(
+            text,
+            font,
+            current_size,
+            w,
+            h,
+            layer.color,
+            layer.stroke.color,
+            layer.stroke.width,
+        );
+        // the text can still run past the bottom of the rect once its margins are added
+        match res {
+            Ok((timage, tw, th)) if tw <= w && th <= h => break timage,
+            Ok(_) | Err(crate::Error::GlyphOverflow) => {
+                current_size -= 1.0;
+                if current_size < 1.0f32 {
+                    return Err(crate::Error::GlyphOverflow);
+                }
+            }
+            Err(e) => return Err(e),
+        }
+    };
+
+    Ok(match &layer.shadow {
+        Some(shadow) => drop_shadow(&timage, shadow),
+        None => (timage, (0, 0)),
+    })
+}
+
+/// Put a blurred shadow behind an image, which grows to make room for it. Also returns where the
+/// original image is in the new one.
+#[inline]
+fn drop_shadow(img: &RgbaImage, shadow: &Shadow) -> (RgbaImage, (u32, u32)) {
+    let pad = (shadow.blur * 3.0).ceil() as u32;
+    let (ox, oy) = shadow.offset;
+    let (width, height) = img.dimensions();
+    let (tx, ty) = (pad + (-ox).max(0) as u32, pad + (-oy).max(0) as u32);
+    let (sx, sy) = (
+        (tx as i64 + ox as i64) as u32,
+        (ty as i64 + oy as i64) as u32,
+    );
+
+    let [r, g, b] = shadow.color;
+    let mut canvas = RgbaImage::from_pixel(
+        width + 2 * pad + ox.unsigned_abs(),
+        height + 2 * pad + oy.unsigned_abs(),
+        Rgba([r, g, b, 0]),
+    );
+    for (x, y, Rgba([_, _, _, a])) in img.enumerate_pixels() {
+        let alpha = (*a as f32 * shadow.opacity) as u8;
+        canvas.put_pixel(sx + x, sy + y, Rgba([r, g, b, alpha]));
+    }
+    if shadow.blur > 0.0 {
+        canvas = imageops::blur(&canvas, shadow.blur);
+    }
+    overlay_at(&mut canvas, img, tx as i64, ty as i64);
+    (canvas, (tx, ty))
+}
+
+/// Scale a picture to fit in the rectangle, centered in it, along with where the rectangle's
+/// corner is in the image.
+#[inline]
+fn render_image(path: &Path, rect: Rect) -> crate::Result<(RgbaImage, (u32, u32))> {
+    let img = image::open(path)?.resize(rect.w, rect.h, imageops::FilterType::Triangle);
+    let img = img.to_rgba8();
+    let (x, y) = ((rect.w - img.width()) / 2, (rect.h - img.height()) / 2);
+    let mut canvas = RgbaImage::new(rect.w, rect.h);
+    overlay_at(&mut canvas, &img, x as i64, y as i64);
+    Ok((canvas, (0, 0)))
+}
+
+/// Turn an image clockwise around its middle, growing it to fit the corners.
+#[inline]
+fn rotate(img: &RgbaImage, degrees: f32) -> RgbaImage {
+    if degrees % 360.0 == 0.0 {
+        return img.clone();
+    }
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    let (width, height) = (img.width() as f32, img.height() as f32);
+    // a quarter turn shouldn't grow the image because of rounding errors
+    let new_width = (width * cos.abs() + height * sin.abs() - 0.001).ceil();
+    let new_height = (width * sin.abs() + height * cos.abs() - 0.001).ceil();
+
+    RgbaImage::from_fn(new_width as u32, new_height as u32, |x, y| {
+        // find where the pixel came from by turning it back
+        let dx = x as f32 + 0.5 - new_width / 2.0;
+        let dy = y as f32 + 0.5 - new_height / 2.0;
+        let sx = dx * cos + dy * sin + width / 2.0;
+        let sy = -dx * sin + dy * cos + height / 2.0;
+        sample(img, sx - 0.5, sy - 0.5)
+    })
+}
+
+/// Read a pixel between pixels, blending the four around it. Outside of the image is transparent.
+#[inline]
+fn sample(img: &RgbaImage, x: f32, y: f32) -> Rgba<u8> {
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+    let mut sum = [0.0f32; 4];
+    for (px, py, weight) in [
+        (x0, y0, (1.0 - fx) * (1.0 - fy)),
+        (x0 + 1.0, y0, fx * (1.0 - fy)),
+        (x0, y0 + 1.0, (1.0 - fx) * fy),
+        (x0 + 1.0, y0 + 1.0, fx * fy),
+    ] {
+        if px < 0.0 || py < 0.0 || px >= img.width() as f32 || py >= img.height() as f32 {
+            continue;
+        }
+        // blend with premultiplied alpha, so transparent pixels don't darken the edges
+        let Rgba([r, g, b, a]) = *img.get_pixel(px as u32, py as u32);
+        let alpha = a as f32 * weight;
+        sum[0] += r as f32 * alpha;
+        sum[1] += g as f32 * alpha;
+        sum[2] += b as f32 * alpha;
+        sum[3] += alpha;
+    }
+    if sum[3] <= 0.0 {
+        return Rgba([0, 0, 0, 0]);
+    }
+    Rgba([
+        (sum[0] / sum[3]).round() as u8,
+        (sum[1] / sum[3]).round() as u8,
+        (sum[2] / sum[3]).round() as u8,
+        sum[3].round().min(255.0) as u8,
+    ])
+}
+
+/// Draw an image over another one, which it can hang off the edges of.
+#[inline]
+fn overlay_at(bottom: &mut RgbaImage, top: &RgbaImage, x: i64, y: i64) {
+    let (width, height) = (i64::from(bottom.width()), i64::from(bottom.height()));
+    for (tx, ty, over) in top.enumerate_pixels() {
+        let (bx, by) = (x + i64::from(tx), y + i64::from(ty));
+        if bx < 0 || by < 0 || bx >= width || by >= height || over[3] == 0 {
+            continue;
+        }
+        let under = bottom.get_pixel_mut(bx as u32, by as u32);
+        let top_alpha = over[3] as f32 / 255.0;
+        let bottom_alpha = under[3] as f32 / 255.0 * (1.0 - top_alpha);
+        let alpha = top_alpha + bottom_alpha;
+        let blend =
+            |t: u8, b: u8| ((t as f32 * top_alpha + b as f32 * bottom_alpha) / alpha).round() as u8;
+        *under = Rgba([
+            blend(over[0], under[0]),
+            blend(over[1], under[1]),
+            blend(over[2], under[2]),
+            (alpha * 255.0).round() as u8,
+        ]);
+    }
+}
+
+#[inline]
+fn check_rect(id: &str, layer: usize, rect: Rect, (width, height): (u32, u32)) -> crate::Result {
+    let Rect { x, y, w, h } = rect;
+    if w == 0 || h == 0 {
+        return Err(crate::Error::Msg(format!(
+            "The rectangle of layer {} of thumbnail template {} is empty",
+            layer, id
+        )));
+    }
+    if u64::from(x) + u64::from(w) > u64::from(width)
+        || u64::from(y) + u64::from(h) > u64::from(height)
+    {
+        return Err(crate::Error::Msg(format!(
+            "The rectangle of layer {} of thumbnail template {} ({}x{} at {}, {}) doesn't fit inside its {}x{} base image",
+            layer, id, w, h, x, y, width, height
+        )));
+    }
+    Ok(())
+}
+
+#[test]
+fn test_check_rect() {
+    let rect = |x, y, w, h| Rect { x, y, w, h };
+    check_rect("ok", 0, rect(0, 0, 1280, 720), (1280, 720)).unwrap();
+    check_rect("ok", 0, rect(100, 500, 1000, 200), (1280, 720)).unwrap();
+    check_rect("wide", 0, rect(100, 0, 1200, 100), (1280, 720)).unwrap_err();
+    check_rect("tall", 0, rect(0, 700, 100, 21), (1280, 720)).unwrap_err();
+    check_rect("empty", 0, rect(10, 10, 0, 100), (1280, 720)).unwrap_err();
+    check_rect("huge", 0, rect(u32::MAX, 0, u32::MAX, 1), (1280, 720)).unwrap_err();
+}
+
+#[test]
+fn test_template_layers() {
+    // templates from before layers still load, with their rectangle as a text layer
+    let old = r#"{"id":"old","path":"base.png","trect":{"x":10,"y":20,"w":300,"h":100}}"#;
+    let template: ThumbnailTemplate = serde_json::from_str(old).unwrap();
+    assert_eq!(
+        template.layers,
+        vec![Layer::legacy(Rect {
+            x: 10,
+            y: 20,
+            w: 300,
+            h: 100
+        })]
+    );
+    let saved = serde_json::to_string(&template).unwrap();
+    assert!(!saved.contains("trect"));
+    let reloaded: ThumbnailTemplate = serde_json::from_str(&saved).unwrap();
+    assert_eq!(reloaded.layers, template.layers);
+
+    let new = r#"{"id":"new","path":"base.png","layers":[
+        {"kind":"image","slot":"frame","rect":{"x":0,"y":0,"w":640,"h":360},"rotation":-4},
+        {"kind":"text","text":"r/{subreddit}","rect":{"x":0,"y":400,"w":640,"h":80},
+         "color":[255,200,0],"shadow":{"blur":2},"font_size":48}
+    ]}"#;
+    let template: ThumbnailTemplate = serde_json::from_str(new).unwrap();
+    match &template.layers[..] {
+        [Layer::Image(image), Layer::Text(text)] => {
+            assert_eq!(image.rotation, -4.0);
+            assert_eq!(text.stroke, Stroke::default());
+            assert_eq!(text.shadow.unwrap().offset, (6, 6));
+        }
+        layers => panic!("{:?}", layers),
+    }
+
+    let mut content = ThumbnailContent::new("What's the best advice?".to_string());
+    content
+        .fields
+        .insert("subreddit".to_string(), "AskReddit".to_string());
+    assert_eq!(content.fill("r/{subreddit}").unwrap(), "r/AskReddit");
+    assert_eq!(
+        content.fill("{text} {").unwrap(),
+        "What's the best advice? {"
+    );
+    assert_eq!(content.fill("{score} points"), None);
+}
+
+#[test]
+fn test_rotate() {
+    let img = RgbaImage::from_pixel(40, 10, Rgba([255, 0, 0, 255]));
+    assert_eq!(rotate(&img, 0.0), img);
+    let turned = rotate(&img, 90.0);
+    assert_eq!(turned.dimensions(), (10, 40));
+    assert_eq!(*turned.get_pixel(5, 20), Rgba([255, 0, 0, 255]));
+
+    // the corners are transparent once it's turned partway
+    let turned = rotate(&img, 45.0);
+    assert_eq!(turned.dimensions(), (36, 36));
+    assert_eq!(turned.get_pixel(0, 0)[3], 0);
+    let (w, h) = turned.dimensions();
+    assert_eq!(*turned.get_pixel(w / 2, h / 2), Rgba([255, 0, 0, 255]));
+}
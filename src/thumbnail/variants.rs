@@ -0,0 +1,176 @@
+/*
+ * This file is part of KOTI.
+ *
+ * KOTI is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * KOTI is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Afero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with KOTI.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Make a few different thumbnails for each video, so the one that does best can be found.
+
+use super::ThumbnailContent;
+use std::path::PathBuf;
+
+/// Text colors for automatic thumbnails, in the order they're tried.
+const AUTO_COLORS: [[u8; 3]; 3] = [[255, 255, 255], [255, 210, 0], [120, 220, 255]];
+
+/// How many thumbnails to make for each video, and how one is picked.
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ThumbnailSettings {
+    /// How many different thumbnails to make for each video.
+    pub variants: usize,
+    /// Templates to try besides the frame source's.
+    pub templates: Vec<String>,
+    /// Wait for a thumbnail to be picked with `koti thumbnail pick` before uploading, instead of
+    /// using the first one.
+    pub pick: bool,
+}
+
+impl Default for ThumbnailSettings {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            variants: 3,
+            templates: vec![],
+            pick: false,
+        }
+    }
+}
+
+impl ThumbnailSettings {
+    #[inline]
+    pub fn validate(&self) -> crate::Result {
+        // variants are named with letters
+        if !(1..=26).contains(&self.variants) {
+            return Err(crate::Error::InvalidConfig(format!(
+                "{} thumbnail variants is outside of the range 1 to 26",
+                self.variants
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// One of the thumbnails made for a video.
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct ThumbnailVariant {
+    /// A letter, in the order the variants were made.
+    pub name: String,
+    /// The template it was made with, or `None` if it was made automatically.
+    pub template: Option<String>,
+    /// Color of the text, for automatic thumbnails.
+    pub color: Option<[u8; 3]>,
+    pub text: String,
+    pub path: PathBuf,
+}
+
+impl ThumbnailVariant {
+    /// A short description of how the variant looks.
+    #[inline]
+    pub fn describe(&self) -> String {
+        let style = match (&self.template, self.color) {
+            (Some(template), _) => format!("template {}", template),
+            (None, Some([r, g, b])) => format!("automatic, #{:02x}{:02x}{:02x} text", r, g, b),
+            (None, None) => "automatic".to_string(),
+        };
+        format!("{}, {:?}", style, &self.text)
+    }
+}
+
+/// How a variant looks.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) enum Style {
+    Template(String),
+    Automatic([u8; 3]),
+}
+
+/// Pair up the templates (and then automatic thumbnails in each color) with the phrasings of the
+/// text, going through every look and every phrasing before repeating either, for up to `count`
+/// variants.
+#[inline]
+pub(super) fn plan(
+    templates: &[String],
+    phrasings: &[String],
+    count: usize,
+) -> Vec<(Style, String)> {
+    let styles: Vec<Style> = templates
+        .iter()
+        .cloned()
+        .map(Style::Template)
+        .chain(AUTO_COLORS.iter().copied().map(Style::Automatic))
+        .collect();
+
+    let mut plan = vec![];
+    for k in 0..styles.len() * phrasings.len() {
+        if plan.len() == count {
+            break;
+        }
+        // each time around the styles, shift which phrasing goes with which
+        let style = styles[k % styles.len()].clone();
+        let phrasing = phrasings[(k + k / styles.len()) % phrasings.len()].clone();
+        let variant = (style, phrasing);
+        if !plan.contains(&variant) {
+            plan.push(variant);
+        }
+    }
+    plan
+}
+
+/// The ways to word a video's thumbnail: the thumbnail text, then the bare title if it's different.
+#[inline]
+pub(super) fn phrasings(content: &ThumbnailContent) -> Vec<String> {
+    let mut phrasings = vec![];
+    for field in &["text", "title"] {
+        if let Some(text) = content.fields.get(*field) {
+            if !text.trim().is_empty() && !phrasings.contains(text) {
+                phrasings.push(text.clone());
+            }
+        }
+    }
+    phrasings
+}
+
+#[test]
+fn test_plan() {
+    let templates = vec!["reddit_text".to_string()];
+    let phrasings = vec!["/r/AskReddit - Why?".to_string(), "Why?".to_string()];
+    let white = Style::Automatic(AUTO_COLORS[0]);
+    let yellow = Style::Automatic(AUTO_COLORS[1]);
+    let template = Style::Template("reddit_text".to_string());
+
+    let variants = plan(&templates, &phrasings, 3);
+    assert_eq!(
+        variants,
+        vec![
+            (template.clone(), phrasings[0].clone()),
+            (white.clone(), phrasings[1].clone()),
+            (yellow, phrasings[0].clone()),
+        ]
+    );
+
+    // every look gets every phrasing before running out
+    let variants = plan(&templates, &phrasings, 26);
+    assert_eq!(variants.len(), 8);
+    assert!(variants.contains(&(template, phrasings[1].clone())));
+
+    // without templates, only automatic thumbnails are made
+    let variants = plan(&[], &phrasings[..1], 2);
+    assert_eq!(
+        variants,
+        vec![
+            (white, phrasings[0].clone()),
+            (Style::Automatic(AUTO_COLORS[1]), phrasings[0].clone())
+        ]
+    );
+    assert!(plan(&templates, &[], 3).is_empty());
+}
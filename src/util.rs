@@ -21,13 +21,9 @@ use regex::Regex;
 use std::{
     borrow::Cow,
     fmt,
-    fs::File,
     future::Future,
-    io::{prelude::*, BufReader, BufWriter},
-    iter, mem,
     path::Path,
     pin::Pin,
-    process::Stdio,
     str::FromStr,
     sync::Arc,
     task::{Context, Poll},
@@ -37,7 +33,7 @@ use thirtyfour::{
     common::types::{ElementId, ElementRect},
     prelude::*,
 };
-use tokio::{process::Command, task::JoinError};
+use tokio::task::JoinError;
 
 #[inline]
 pub async fn cropped_screenshot(
@@ -241,56 +237,91 @@ fn test_timeout() {
         });
 }
 
+/// Escape text so it can go in a URL's query string.
 #[inline]
-pub async fn video_length(path: &Path) -> crate::Result<f32> {
-    static DURATION_REGEX: Lazy<Regex> = Lazy::new(|| {
-        Regex::new(r"Duration: (\d\d):(\d\d):(\d\d).(\d\d)").expect("Regex failed to compile")
-    });
-
-    // figure out the length of the sound file using ffmpeg
-    let mut c = Command::new("ffmpeg")
-        .arg("-i")
-        .arg(path)
-        .stderr(Stdio::piped())
-        .stdout(Stdio::piped())
-        .output()
-        .await?;
-
-    // it is supposed to fail
-
-    let mut textout =
-        String::from_utf8(mem::take(&mut c.stdout)).expect("ffmpeg output isn't utf-8?");
-    textout.extend(iter::once(
-        String::from_utf8(c.stderr).expect("ffmpeg stderr isn't utf-8?"),
-    ));
-    let total: f32 = match DURATION_REGEX.captures(&textout) {
-        Some(caps) => caps
-            .iter()
-            .skip(1)
-            .map(|cap| match cap {
-                Some(cap) => usize::from_str(cap.as_str()).expect("Not really an f64?") as f32,
-                None => panic!("Should've participated?"),
-            })
-            .enumerate()
-            .fold(0.0, |sum, (index, value)| {
-                let multiplier: f32 = match index {
-                    0 => 360.0,
-                    1 => 60.0,
-                    2 => 1.0,
-                    3 => 0.01,
-                    _ => panic!("More than four captures?"),
-                };
-
-                sum + (value * multiplier)
-            }),
-        None => {
-            return Err(crate::Error::StaticMsg(
-                "Could not find duration with regex",
-            ))
+pub fn percent_encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            b => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// Fetch the body at a URL over HTTP(S), following redirects.
+#[inline]
+pub async fn http_get(url: &str) -> crate::Result<Vec<u8>> {
+    const MAX_REDIRECTS: usize = 5;
+    const USER_AGENT: &str = "linux:koti:0.1.0";
+
+    let client = hyper::Client::builder()
+        .build::<_, hyper::Body>(hyper_rustls::HttpsConnector::with_native_roots());
+    let mut url = url.to_string();
+
+    for _ in 0..MAX_REDIRECTS {
+        log::info!("Fetching {}", &url);
+        let req = hyper::Request::get(url.as_str())
+            .header(hyper::header::USER_AGENT, USER_AGENT)
+            .body(hyper::Body::empty())
+            .map_err(|e| crate::Error::Msg(format!("Invalid request for {}: {}", &url, e)))?;
+        let resp = client.request(req).await?;
+        let status = resp.status();
+
+        if status.is_redirection() {
+            match resp
+                .headers()
+                .get(hyper::header::LOCATION)
+                .and_then(|l| l.to_str().ok())
+            {
+                Some(location) => {
+                    url = resolve_location(&url, location)?;
+                    continue;
+                }
+                None => return Err(crate::Error::HttpStatus(status.as_u16(), url)),
+            }
+        }
+
+        if !status.is_success() {
+            return Err(crate::Error::HttpStatus(status.as_u16(), url));
         }
-    };
 
-    Ok(total)
+        let body = hyper::body::to_bytes(resp.into_body()).await?;
+        return Ok(body.to_vec());
+    }
+
+    Err(crate::Error::Msg(format!(
+        "Too many redirects from {}",
+        url
+    )))
+}
+
+/// Resolves a redirect's `Location` against the URL it came from, since it may leave out the
+/// scheme and host.
+#[inline]
+fn resolve_location(url: &str, location: &str) -> crate::Result<String> {
+    let invalid = || crate::Error::Msg(format!("Invalid redirect from {} to {}", url, location));
+    let base = url.parse::<hyper::Uri>().map_err(|_| invalid())?;
+    let scheme = base.scheme_str().ok_or_else(invalid)?;
+    let authority = base.authority().ok_or_else(invalid)?;
+
+    if location
+        .parse::<hyper::Uri>()
+        .is_ok_and(|l| l.scheme().is_some())
+    {
+        Ok(location.to_string())
+    } else if location.starts_with("//") {
+        Ok(format!("{}:{}", scheme, location))
+    } else if location.starts_with('/') {
+        Ok(format!("{}://{}{}", scheme, authority, location))
+    } else {
+        // relative to the directory of the current path
+        let path = base.path();
+        let dir = &path[..path.rfind('/').map_or(0, |i| i + 1)];
+        let dir = if dir.is_empty() { "/" } else { dir };
+        Ok(format!("{}://{}{}{}", scheme, authority, dir, location))
+    }
 }
 
 #[inline]
@@ -301,6 +332,75 @@ pub fn strip_html_tags(input: &str) -> Cow<'_, str> {
     HTML_REGEX.replace_all(input, "")
 }
 
+/// Replace HTML character references with the characters they stand for.
+#[inline]
+pub fn decode_html_entities(input: &str) -> Cow<'_, str> {
+    static ENTITY_REGEX: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r"&(#[xX]?)?(\w+);").expect("Regex failed to compile"));
+
+    ENTITY_REGEX.replace_all(input, |caps: &regex::Captures<'_>| {
+        let name = &caps[2];
+        let c = match caps.get(1).map(|m| m.as_str()) {
+            Some("#") => u32::from_str(name).ok().and_then(std::char::from_u32),
+            Some(_) => u32::from_str_radix(name, 16)
+                .ok()
+                .and_then(std::char::from_u32),
+            None => match name {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                "nbsp" => Some(' '),
+                "laquo" => Some('\u{ab}'),
+                "raquo" => Some('\u{bb}'),
+                "ndash" => Some('\u{2013}'),
+                "mdash" => Some('\u{2014}'),
+                "hellip" => Some('\u{2026}'),
+                _ => None,
+            },
+        };
+
+        match c {
+            Some(c) => c.to_string(),
+            None => caps[0].to_string(),
+        }
+    })
+}
+
+#[test]
+fn test_decode_entities() {
+    assert_eq!(
+        &*decode_html_entities("&quot;Living&quot; &amp; &#8212; &#x41;&nbsp;&bogus;"),
+        "\"Living\" & \u{2014} A &bogus;"
+    );
+}
+
+#[test]
+fn test_resolve_location() {
+    let url = "https://example.com/a/b?c=d";
+    assert_eq!(
+        resolve_location(url, "https://other.org/x").unwrap(),
+        "https://other.org/x"
+    );
+    assert_eq!(
+        resolve_location(url, "//other.org/x").unwrap(),
+        "https://other.org/x"
+    );
+    assert_eq!(
+        resolve_location(url, "/x?y=z").unwrap(),
+        "https://example.com/x?y=z"
+    );
+    assert_eq!(
+        resolve_location(url, "x").unwrap(),
+        "https://example.com/a/x"
+    );
+    assert_eq!(
+        resolve_location("https://example.com", "x").unwrap(),
+        "https://example.com/x"
+    );
+}
+
 #[test]
 fn test_strip() {
     assert_eq!(&*strip_html_tags("<b>awesome!</b>"), "awesome!");
@@ -20,7 +20,7 @@ mod config;
 use crate::context::Context;
 use config::YtConfig;
 use google_youtube3::{
-    api::{Video, VideoSnippet, VideoStatus},
+    api::{Caption, CaptionSnippet, Video, VideoSnippet, VideoStatus},
     YouTube,
 };
 use std::path::PathBuf;
@@ -34,9 +34,10 @@ pub async fn upload_video(
     ctx: &Context,
     video_path: PathBuf,
     thumbnail_path: PathBuf,
+    captions_path: Option<PathBuf>,
     mut video_title: String,
     video_desc: String,
-) -> crate::Result {
+) -> crate::Result<String> {
     let config = load_config(ctx).await?;
     let YtConfig {
         client_id,
@@ -109,8 +110,9 @@ pub async fn upload_video(
 
     // upload the thumbnail
     log::info!("Video has been uploaded, uploading thumbnail...");
+    let video_id = video.id.expect("Video has no id?");
     yt.thumbnails()
-        .set(video.id.expect("Video has no id?").as_str())
+        .set(video_id.as_str())
         .upload_resumable(
             File::open(thumbnail_path).await?.into_std().await,
             "image/png".parse().unwrap(),
@@ -118,23 +120,63 @@ pub async fn upload_video(
         .await
         .expect("Failed to set thumbnail for video");
 
+    // upload the captions. the video is already up, so don't fail over them
+    if let Some(captions_path) = captions_path {
+        log::info!("Uploading captions...");
+        let mut caption = Caption::default();
+        caption.snippet = Some(CaptionSnippet {
+            video_id: Some(video_id.clone()),
+            language: Some("en".to_string()),
+            name: Some(String::new()),
+            ..Default::default()
+        });
+        match File::open(captions_path).await {
+            Ok(file) => {
+                if let Err(e) = yt
+                    .captions()
+                    .insert(caption)
+                    .upload(
+                        file.into_std().await,
+                        "application/octet-stream".parse().unwrap(),
+                    )
+                    .await
+                {
+                    log::error!("Failed to upload captions: {}", e);
+                }
+            }
+            Err(e) => log::error!("Failed to open captions: {}", e),
+        }
+    }
+
     log::info!("Should now be uploaded and processing on YouTube!");
-    Ok(())
+    Ok(video_id)
 }
 
 #[inline]
-pub async fn upload_to_youtube(ctx: &Context) -> crate::Result {
-    let video_path = ctx.take_video_path().await;
-    let thumbnail_path = ctx.take_thumbnail_path().await;
-    let video_title = ctx.take_video_title().await;
-    let video_desc = ctx.take_video_description().await;
+pub async fn upload_to_youtube(
+    ctx: &Context,
+    video_path: PathBuf,
+    thumbnail_path: PathBuf,
+    captions_path: Option<PathBuf>,
+    video_title: String,
+    mut video_desc: String,
+) -> crate::Result<String> {
+    video_desc.push_str(&ctx.config().description_footer);
     log::info!(
         "Video path is {:?}, thumbnail path is {:?}",
         &video_path,
         &thumbnail_path
     );
 
-    upload_video(ctx, video_path, thumbnail_path, video_title, video_desc).await
+    upload_video(
+        ctx,
+        video_path,
+        thumbnail_path,
+        captions_path,
+        video_title,
+        video_desc,
+    )
+    .await
 }
 
 #[inline]